@@ -2,6 +2,7 @@ use moka::future::Cache as MokaCache;
 use serde_json::Value;
 use std::time::Duration;
 
+#[derive(Clone)]
 pub struct Cache {
     cache: MokaCache<String, Value>,
 }
@@ -16,6 +17,16 @@ impl Cache {
         Self { cache }
     }
 
+    /// Rebuilds the cache so entries expire `ttl` after they are written.
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        let cache = MokaCache::builder()
+            .max_capacity(1000)
+            .time_to_live(ttl)
+            .build();
+
+        Self { cache }
+    }
+
     pub async fn get(&self, key: &str) -> Option<Value> {
         self.cache.get(key).await
     }
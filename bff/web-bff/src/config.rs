@@ -17,6 +17,27 @@ pub struct ServiceConfig {
     pub name: String,
     pub host: String,
     pub port: u16,
+    /// Port of the service's gRPC server, if it has one.
+    pub grpc_port: Option<u16>,
+}
+
+/// How the BFF talks to the services behind it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    #[default]
+    Http,
+    Grpc,
+}
+
+impl Transport {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "http" => Some(Transport::Http),
+            "grpc" => Some(Transport::Grpc),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Deserialize, Clone)]
@@ -25,6 +46,10 @@ pub struct Config {
     pub host: String,
     pub port: u16,
     pub cache_ttl_seconds: u64,
+    pub transport: Transport,
+    /// Cached data is evicted when events say it changed. Without NATS it
+    /// is served until it expires.
+    pub nats_url: Option<String>,
 }
 
 impl Config {
@@ -38,17 +63,24 @@ impl Config {
                     name: "user-service".to_string(),
                     host: "localhost".to_string(),
                     port: 3001,
+                    grpc_port: Some(50051),
                 });
                 services.insert("order-service".to_string(), ServiceConfig {
                     name: "order-service".to_string(),
                     host: "localhost".to_string(),
                     port: 3002,
+                    grpc_port: Some(50052),
                 });
                 services
             },
             host: "0.0.0.0".to_string(),
             port: 3003,
             cache_ttl_seconds: 300, // 5 minutes
+            transport: std::env::var("BACKEND_TRANSPORT")
+                .ok()
+                .and_then(|value| Transport::parse(&value))
+                .unwrap_or_default(),
+            nats_url: std::env::var("NATS_URL").ok(),
         })
     }
 
@@ -57,4 +89,10 @@ impl Config {
             format!("http://{}:{}", service.host, service.port)
         })
     }
-}
\ No newline at end of file
+
+    pub fn get_service_grpc_url(&self, service_name: &str) -> Option<String> {
+        self.services.get(service_name).and_then(|service| {
+            service.grpc_port.map(|port| format!("http://{}:{}", service.host, port))
+        })
+    }
+}
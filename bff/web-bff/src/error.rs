@@ -15,11 +15,18 @@ pub enum AppError {
     #[error("Configuration error: {0}")]
     ConfigError(String),
     
-    #[error("Cache error: {0}")]
-    CacheError(String),
-    
     #[error("JSON error: {0}")]
     JsonError(#[from] serde_json::Error),
+
+    // Boxed, as a Status is several times the size of the other variants
+    #[error("gRPC error: {0}")]
+    GrpcError(Box<tonic::Status>),
+}
+
+impl From<tonic::Status> for AppError {
+    fn from(status: tonic::Status) -> Self {
+        AppError::GrpcError(Box::new(status))
+    }
 }
 
 impl IntoResponse for AppError {
@@ -28,8 +35,12 @@ impl IntoResponse for AppError {
             AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             AppError::ClientError(_) => StatusCode::BAD_GATEWAY,
             AppError::ConfigError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            AppError::CacheError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::JsonError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::GrpcError(status) => match status.code() {
+                tonic::Code::NotFound => StatusCode::NOT_FOUND,
+                tonic::Code::Unavailable | tonic::Code::DeadlineExceeded => StatusCode::SERVICE_UNAVAILABLE,
+                _ => StatusCode::BAD_GATEWAY,
+            },
         };
 
         (status, self.to_string()).into_response()
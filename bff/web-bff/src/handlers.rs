@@ -1,60 +1,87 @@
 use axum::{
-    extract::State,
+    extract::{Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
     Json,
 };
+use serde::Deserialize;
 use serde_json::json;
 use tracing::info;
 
 use crate::{
+    cache::Cache,
     config::Config,
     error::AppError,
+    service_client::ServiceClient,
 };
 
+const RECENT_ORDERS: u32 = 3;
+
+/// Cache key of the dashboard, which is evicted whenever an order is placed
+/// or changes status, or a customer is renamed, deleted or restored.
+pub const DASHBOARD_KEY: &str = "dashboard";
+
 pub async fn health_check() -> impl IntoResponse {
     (StatusCode::OK, "Web BFF is healthy")
 }
 
 pub async fn get_dashboard(
-    State(_config): State<Config>,
+    State(config): State<Config>,
+    State(cache): State<Cache>,
 ) -> Result<impl IntoResponse, AppError> {
     info!("Fetching dashboard data");
-    
-    // In a real implementation, we would fetch data from multiple services
-    // and aggregate them for the frontend
+
+    if let Some(dashboard) = cache.get(DASHBOARD_KEY).await {
+        return Ok(Json(dashboard));
+    }
+
+    // Order figures come from order-service's read model, which already has
+    // each order joined with its customer's name
+    let orders = ServiceClient::new(config).get_dashboard_orders(RECENT_ORDERS).await?;
+
+    let recent_orders: Vec<_> = orders["recent_orders"]
+        .as_array()
+        .map(|orders| orders.iter().map(|order| json!({
+            "id": order["order_id"],
+            "customer": order["customer_name"],
+            "amount": order["total"],
+        })).collect())
+        .unwrap_or_default();
+
     let dashboard_data = json!({
+        // user-service does not expose a user count yet
         "user_count": 1250,
-        "order_count": 3420,
-        "revenue": 125000.00,
-        "recent_orders": [
-            {"id": "1", "customer": "John Doe", "amount": 120.00},
-            {"id": "2", "customer": "Jane Smith", "amount": 85.50},
-            {"id": "3", "customer": "Bob Johnson", "amount": 210.75}
-        ]
+        "order_count": orders["order_count"],
+        "revenue": orders["revenue"],
+        "recent_orders": recent_orders,
     });
+    cache.set(DASHBOARD_KEY.to_string(), dashboard_data.clone()).await;
     
     Ok(Json(dashboard_data))
 }
 
+#[derive(Deserialize)]
+pub struct ProfileQuery {
+    pub user_id: String,
+}
+
 pub async fn get_profile(
-    State(_config): State<Config>,
+    State(config): State<Config>,
+    Query(query): Query<ProfileQuery>,
 ) -> Result<impl IntoResponse, AppError> {
     info!("Fetching user profile");
-    
-    // In a real implementation, we would fetch user data from the user service
-    let profile_data = json!({
-        "id": "user-123",
-        "username": "johndoe",
-        "email": "john.doe@example.com",
-        "first_name": "John",
-        "last_name": "Doe",
-        "avatar": "https://example.com/avatar/johndoe.png",
-        "preferences": {
-            "theme": "dark",
-            "notifications": true
-        }
-    });
-    
-    Ok(Json(profile_data))
-}
\ No newline at end of file
+
+    // Over HTTP or gRPC, as BACKEND_TRANSPORT says
+    let user = ServiceClient::new(config).get_user(&query.user_id).await?;
+    Ok(Json(user))
+}
+
+pub async fn get_order(
+    State(config): State<Config>,
+    Path(order_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    info!("Fetching order {}", order_id);
+
+    let order = ServiceClient::new(config).get_order(&order_id).await?;
+    Ok(Json(order))
+}
@@ -1,5 +1,10 @@
-use reqwest::Client;
-use crate::{config::Config, error::AppError};
+use order_client::order_service_client::OrderServiceClient;
+use reqwest::{Client, RequestBuilder};
+use serde_json::{json, Value};
+use time::format_description::well_known::Rfc3339;
+use tonic::transport::{Channel, Endpoint};
+use user_client::user_service_client::UserServiceClient;
+use crate::{config::{Config, Transport}, error::AppError};
 
 pub struct ServiceClient {
     client: Client,
@@ -14,35 +19,125 @@ impl ServiceClient {
         }
     }
 
+    /// Starts a GET that carries the current trace and correlation id, so the
+    /// downstream service joins the caller's trace.
+    fn get(&self, url: &str) -> RequestBuilder {
+        observability::outgoing_headers()
+            .into_iter()
+            .fold(self.client.get(url), |request, (name, value)| request.header(name, value))
+    }
+
+    /// Opens a gRPC channel to `service_name`, connecting on first use.
+    fn channel(&self, service_name: &str) -> Result<Channel, AppError> {
+        let url = self.config.get_service_grpc_url(service_name)
+            .ok_or_else(|| AppError::ServiceUnavailable(format!("{} gRPC not configured", service_name)))?;
+        let endpoint = Endpoint::from_shared(url).map_err(|e| AppError::ConfigError(e.to_string()))?;
+        Ok(endpoint.connect_lazy())
+    }
+
     pub async fn get_user(&self, user_id: &str) -> Result<serde_json::Value, AppError> {
+        if self.config.transport == Transport::Grpc {
+            let request = observability::outgoing_request(user_client::GetUserRequest { id: user_id.to_string() });
+            let user = UserServiceClient::new(self.channel("user-service")?).get_user(request).await?.into_inner();
+            return Ok(user_json(user));
+        }
+
         let service_url = self.config.get_service_url("user-service")
             .ok_or_else(|| AppError::ServiceUnavailable("User service not configured".to_string()))?;
 
         let url = format!("{}/users/{}", service_url, user_id);
-        let response = self.client.get(&url).send().await?;
+        let response = self.get(&url).send().await?;
         
         if response.status().is_success() {
             let body = response.text().await?;
             let user: serde_json::Value = serde_json::from_str(&body)?;
             Ok(user)
         } else {
-            Err(AppError::ClientError(reqwest::Error::from(response.error_for_status().unwrap_err())))
+            Err(AppError::ClientError(response.error_for_status().unwrap_err()))
         }
     }
 
     pub async fn get_order(&self, order_id: &str) -> Result<serde_json::Value, AppError> {
+        if self.config.transport == Transport::Grpc {
+            let request = observability::outgoing_request(order_client::GetOrderRequest { id: order_id.to_string() });
+            let order = OrderServiceClient::new(self.channel("order-service")?).get_order(request).await?.into_inner();
+            return Ok(order_json(order));
+        }
+
         let service_url = self.config.get_service_url("order-service")
             .ok_or_else(|| AppError::ServiceUnavailable("Order service not configured".to_string()))?;
 
         let url = format!("{}/orders/{}", service_url, order_id);
-        let response = self.client.get(&url).send().await?;
+        let response = self.get(&url).send().await?;
         
         if response.status().is_success() {
             let body = response.text().await?;
             let order: serde_json::Value = serde_json::from_str(&body)?;
             Ok(order)
         } else {
-            Err(AppError::ClientError(reqwest::Error::from(response.error_for_status().unwrap_err())))
+            Err(AppError::ClientError(response.error_for_status().unwrap_err()))
+        }
+    }
+
+    /// Order totals and the most recent orders with their customer names,
+    /// served from order-service's dashboard read model.
+    pub async fn get_dashboard_orders(&self, limit: u32) -> Result<serde_json::Value, AppError> {
+        let service_url = self.config.get_service_url("order-service")
+            .ok_or_else(|| AppError::ServiceUnavailable("Order service not configured".to_string()))?;
+
+        let url = format!("{}/dashboard/orders?limit={}", service_url, limit);
+        let response = self.get(&url).send().await?;
+
+        if response.status().is_success() {
+            let body = response.text().await?;
+            let dashboard: serde_json::Value = serde_json::from_str(&body)?;
+            Ok(dashboard)
+        } else {
+            Err(AppError::ClientError(response.error_for_status().unwrap_err()))
         }
     }
-}
\ No newline at end of file
+}
+
+// gRPC answers are turned into the JSON the services' REST APIs return,
+// with times as RFC 3339 strings.
+
+fn time_json(timestamp: Option<user_client::Timestamp>) -> Value {
+    timestamp
+        .as_ref()
+        .and_then(shared::grpc::from_timestamp)
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .map_or(Value::Null, Value::String)
+}
+
+fn user_json(user: user_client::User) -> Value {
+    json!({
+        "id": user.id,
+        "username": user.username,
+        "email": user.email,
+        "created_at": time_json(user.created_at),
+        "updated_at": time_json(user.updated_at),
+        "version": user.version,
+    })
+}
+
+fn money_json(money: Option<order_client::Money>) -> Value {
+    money.map_or(Value::Null, |money| json!({ "amount_minor": money.amount_minor, "currency": money.currency }))
+}
+
+fn order_json(order: order_client::Order) -> Value {
+    let line_items: Vec<Value> = order.line_items.into_iter().map(|item| json!({
+        "sku": item.sku,
+        "quantity": item.quantity,
+        "unit_price": money_json(item.unit_price),
+    })).collect();
+    json!({
+        "id": order.id,
+        "user_id": order.user_id,
+        "line_items": line_items,
+        "total": money_json(order.total),
+        "status": order.status,
+        "created_at": time_json(order.created_at),
+        "updated_at": time_json(order.updated_at),
+        "version": order.version,
+    })
+}
@@ -0,0 +1,29 @@
+use axum::extract::FromRef;
+use std::time::Duration;
+
+use crate::{cache::Cache, config::Config};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Config,
+    pub cache: Cache,
+}
+
+impl AppState {
+    pub fn new(config: Config) -> Self {
+        let cache = Cache::new().with_ttl(Duration::from_secs(config.cache_ttl_seconds));
+        Self { config, cache }
+    }
+}
+
+impl FromRef<AppState> for Config {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for Cache {
+    fn from_ref(state: &AppState) -> Self {
+        state.cache.clone()
+    }
+}
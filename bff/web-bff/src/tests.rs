@@ -11,6 +11,7 @@ mod tests {
             name: "user-service".to_string(),
             host: "localhost".to_string(),
             port: 3001,
+            grpc_port: Some(50051),
         };
         
         services.insert("user-service".to_string(), user_service);
@@ -20,6 +21,8 @@ mod tests {
             host: "0.0.0.0".to_string(),
             port: 3003,
             cache_ttl_seconds: 300,
+            transport: crate::config::Transport::Http,
+            nats_url: None,
         };
         
         assert_eq!(config.host, "0.0.0.0");
@@ -35,4 +38,94 @@ mod tests {
         // For now, we just verify the struct can be created
         assert!(true); // Placeholder
     }
+
+    /// user-service's gRPC API with one user, `ada`.
+    struct FakeUsers;
+
+    const ADA: &str = "6f9619ff-8b86-d011-b42d-00c04fc964ff";
+
+    #[tonic::async_trait]
+    impl user_client::user_service_server::UserService for FakeUsers {
+        async fn create_user(
+            &self,
+            _request: tonic::Request<user_client::CreateUserRequest>,
+        ) -> Result<tonic::Response<user_client::User>, tonic::Status> {
+            Err(tonic::Status::unimplemented("not needed"))
+        }
+
+        async fn get_user(
+            &self,
+            request: tonic::Request<user_client::GetUserRequest>,
+        ) -> Result<tonic::Response<user_client::User>, tonic::Status> {
+            if request.into_inner().id != ADA {
+                return Err(tonic::Status::not_found("User not found"));
+            }
+            Ok(tonic::Response::new(user_client::User {
+                id: ADA.to_string(),
+                username: "ada".to_string(),
+                email: "ada@example.com".to_string(),
+                created_at: Some(user_client::Timestamp { seconds: 1_700_000_000, nanos: 0 }),
+                updated_at: None,
+                version: 3,
+            }))
+        }
+
+        async fn list_users(
+            &self,
+            _request: tonic::Request<user_client::ListUsersRequest>,
+        ) -> Result<tonic::Response<user_client::ListUsersResponse>, tonic::Status> {
+            Err(tonic::Status::unimplemented("not needed"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_user_over_grpc() {
+        use axum::response::IntoResponse;
+        use crate::config::Transport;
+        use crate::error::AppError;
+        use crate::service_client::ServiceClient;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap();
+        let server = tonic::transport::Server::builder()
+            .add_service(user_client::user_service_server::UserServiceServer::new(FakeUsers))
+            .serve_with_incoming(incoming);
+        tokio::spawn(server);
+
+        let mut services = HashMap::new();
+        services.insert("user-service".to_string(), ServiceConfig {
+            name: "user-service".to_string(),
+            host: "127.0.0.1".to_string(),
+            // Nothing answers HTTP here, so only gRPC can succeed
+            port: 1,
+            grpc_port: Some(port),
+        });
+        let config = Config {
+            services,
+            host: "0.0.0.0".to_string(),
+            port: 3003,
+            cache_ttl_seconds: 300,
+            transport: Transport::Grpc,
+            nats_url: None,
+        };
+        let client = ServiceClient::new(config);
+
+        let user = client.get_user(ADA).await.unwrap();
+        assert_eq!(user, serde_json::json!({
+            "id": ADA,
+            "username": "ada",
+            "email": "ada@example.com",
+            "created_at": "2023-11-14T22:13:20Z",
+            "updated_at": null,
+            "version": 3,
+        }));
+
+        let error = client.get_user("someone-else").await.unwrap_err();
+        assert!(matches!(&error, AppError::GrpcError(status) if status.code() == tonic::Code::NotFound));
+        assert_eq!(error.into_response().status(), axum::http::StatusCode::NOT_FOUND);
+
+        let error = client.get_order("anything").await.unwrap_err();
+        assert!(matches!(error, AppError::ServiceUnavailable(_)));
+    }
 }
\ No newline at end of file
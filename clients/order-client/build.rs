@@ -0,0 +1,11 @@
+// Generates the messages and the client, plus the server behind the `server`
+// feature, from the .proto file. protox parses it, so no protoc is needed.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/order.proto");
+    let file_descriptors = protox::compile(["order.proto"], ["proto"])?;
+    let server = std::env::var_os("CARGO_FEATURE_SERVER").is_some();
+    prost_build::Config::new()
+        .service_generator(tonic_build::configure().build_server(server).service_generator())
+        .compile_fds(file_descriptors)?;
+    Ok(())
+}
@@ -0,0 +1,7 @@
+// gRPC client for order-service, and with the `server` feature the service
+// trait order-service implements. Everything here is generated at build time
+// from the contract, `proto/order.proto`.
+
+pub use prost_types::Timestamp;
+
+tonic::include_proto!("order.v1");
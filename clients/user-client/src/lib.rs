@@ -0,0 +1,7 @@
+// gRPC client for user-service, and with the `server` feature the service
+// trait user-service implements. Everything here is generated at build time
+// from the contract, `proto/user.proto`.
+
+pub use prost_types::Timestamp;
+
+tonic::include_proto!("user.v1");
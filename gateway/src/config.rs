@@ -4,6 +4,14 @@ use std::collections::HashMap;
 #[derive(Debug)]
 pub struct ConfigError(String);
 
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 #[derive(Deserialize, Clone)]
 pub struct ServiceConfig {
     pub name: String,
@@ -14,6 +14,9 @@ pub enum AppError {
     
     #[error("Configuration error: {0}")]
     ConfigError(String),
+
+    #[error("Bad request: {0}")]
+    BodyError(String),
 }
 
 impl IntoResponse for AppError {
@@ -22,6 +25,7 @@ impl IntoResponse for AppError {
             AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             AppError::ProxyError(_) => StatusCode::BAD_GATEWAY,
             AppError::ConfigError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::BodyError(_) => StatusCode::BAD_REQUEST,
         };
 
         (status, self.to_string()).into_response()
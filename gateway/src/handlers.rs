@@ -1,29 +1,111 @@
 use axum::{
-    extract::{Path, State},
-    http::{HeaderMap, Method, Uri},
-    response::IntoResponse,
-    Json,
+    body::{to_bytes, Body},
+    extract::{Path, Request, State},
+    http::{HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
 };
-use serde_json::json;
-use reqwest;
-use std::collections::HashMap;
 
 use crate::{
     config::Config,
     error::AppError,
 };
 
+// Requests larger than this are refused rather than buffered
+const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+// Headers that describe one connection and are not passed along
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
 pub async fn health_check() -> impl IntoResponse {
     (axum::http::StatusCode::OK, "Gateway is healthy")
 }
 
-// We'll simplify the proxy handlers for now to avoid complex type issues
-pub async fn user_service_proxy() -> Result<impl IntoResponse, AppError> {
-    // Mock response for now
-    Ok((axum::http::StatusCode::OK, "User service proxy".to_string()))
+pub async fn user_service_proxy(
+    State(config): State<Config>,
+    path: Option<Path<String>>,
+    request: Request,
+) -> Result<Response, AppError> {
+    proxy(&config, "user-service", "users", path, request).await
+}
+
+pub async fn order_service_proxy(
+    State(config): State<Config>,
+    path: Option<Path<String>>,
+    request: Request,
+) -> Result<Response, AppError> {
+    proxy(&config, "order-service", "orders", path, request).await
 }
 
-pub async fn order_service_proxy() -> Result<impl IntoResponse, AppError> {
-    // Mock response for now
-    Ok((axum::http::StatusCode::OK, "Order service proxy".to_string()))
-}
\ No newline at end of file
+/// Forwards `request` to `/{resource}/{path}` on `service_name` and relays
+/// the answer. The service sees the gateway's span as its parent, so the
+/// call joins the caller's trace.
+async fn proxy(
+    config: &Config,
+    service_name: &str,
+    resource: &str,
+    path: Option<Path<String>>,
+    request: Request,
+) -> Result<Response, AppError> {
+    let service_url = config.get_service_url(service_name)
+        .ok_or_else(|| AppError::ConfigError(format!("{} is not configured", service_name)))?;
+
+    let mut url = format!("{}/{}", service_url, resource);
+    if let Some(Path(path)) = path {
+        url.push('/');
+        url.push_str(path.trim_start_matches('/'));
+    }
+    if let Some(query) = request.uri().query() {
+        url.push('?');
+        url.push_str(query);
+    }
+
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, MAX_BODY_BYTES).await
+        .map_err(|e| AppError::BodyError(e.to_string()))?;
+
+    // reqwest is on an older `http` than axum, so methods and headers cross
+    // over as strings and bytes
+    let method = reqwest::Method::from_bytes(parts.method.as_str().as_bytes())
+        .map_err(|e| AppError::BodyError(e.to_string()))?;
+    let mut forwarded = reqwest::Client::new().request(method, &url);
+    for (name, value) in &parts.headers {
+        if name != axum::http::header::HOST && !HOP_BY_HOP.contains(&name.as_str()) {
+            forwarded = forwarded.header(name.as_str(), value.as_bytes());
+        }
+    }
+    // The caller's own trace headers are replaced by ones naming this span
+    for (name, value) in observability::outgoing_headers() {
+        forwarded = forwarded.header(name, value);
+    }
+
+    let response = forwarded.body(body).send().await.map_err(|e| {
+        if e.is_connect() || e.is_timeout() {
+            AppError::ServiceUnavailable(format!("{} is not reachable", service_name))
+        } else {
+            AppError::ProxyError(e)
+        }
+    })?;
+
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let mut relayed = Response::builder().status(status);
+    for (name, value) in response.headers() {
+        if HOP_BY_HOP.contains(&name.as_str()) {
+            continue;
+        }
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_str().as_bytes()), HeaderValue::from_bytes(value.as_bytes())) {
+            relayed = relayed.header(name, value);
+        }
+    }
+    let body = response.bytes().await?;
+
+    Ok(relayed.body(Body::from(body)).expect("headers were already valid"))
+}
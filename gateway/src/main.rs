@@ -1,5 +1,5 @@
 use axum::{
-    routing::{get},
+    routing::{any, get},
     Router,
 };
 use std::net::SocketAddr;
@@ -7,8 +7,11 @@ use tower_http::{
     cors::{CorsLayer, Any},
     trace::TraceLayer,
 };
-use http::header::{AUTHORIZATION, ACCEPT, CONTENT_TYPE};
-use tracing_subscriber;
+use http::header::{HeaderName, AUTHORIZATION, ACCEPT, CONTENT_TYPE};
+
+const TRACEPARENT: HeaderName = HeaderName::from_static(observability::TRACEPARENT_HEADER);
+const TRACESTATE: HeaderName = HeaderName::from_static(observability::TRACESTATE_HEADER);
+const CORRELATION_ID: HeaderName = HeaderName::from_static(observability::CORRELATION_ID_HEADER);
 
 mod handlers;
 mod config;
@@ -17,26 +20,34 @@ mod error;
 #[tokio::main]
 async fn main() {
     // Initialize tracing
-    tracing_subscriber::fmt::init();
+    observability::init_tracing("gateway").expect("Failed to initialize tracing");
 
     // Load configuration
     let config = config::Config::from_env().expect("Failed to load configuration");
+    for service in config.services.values() {
+        tracing::info!("Routing to {} at {}:{}", service.name, service.host, service.port);
+    }
+
+    let addr: SocketAddr = format!("{}:{}", config.host, config.port)
+        .parse()
+        .expect("Invalid listen address");
 
     // Build our application with routes
     let app = Router::new()
         .route("/health", get(handlers::health_check))
-        // Simplified routes for now
-        .route("/api/users/*path", get(handlers::user_service_proxy))
-        .route("/api/orders/*path", get(handlers::order_service_proxy))
+        .route("/api/users", any(handlers::user_service_proxy))
+        .route("/api/users/*path", any(handlers::user_service_proxy))
+        .route("/api/orders", any(handlers::order_service_proxy))
+        .route("/api/orders/*path", any(handlers::order_service_proxy))
+        .layer(axum::middleware::from_fn(observability::trace_requests))
         .layer(TraceLayer::new_for_http())
         .layer(CorsLayer::new()
             .allow_origin(Any)
             .allow_methods(Any)
-            .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]))
+            .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE, TRACEPARENT, TRACESTATE, CORRELATION_ID]))
         .with_state(config);
 
-    // Run our app with hyper, listening globally on port 3000
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
+    // Run our app with hyper, listening on the configured address
     tracing::info!("Gateway listening on {}", addr);
     
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
@@ -44,6 +55,8 @@ async fn main() {
         .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
+
+    observability::shutdown_tracing();
 }
 
 async fn shutdown_signal() {
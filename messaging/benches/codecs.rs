@@ -0,0 +1,80 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use messaging::{
+    encode_message, CborCodec, Codec, Compression, JsonCodec, Message, MessagePackCodec, OrderCreatedEvent,
+    OrderLineItem, ProtobufCodec,
+};
+use serde_json::json;
+
+fn order_created() -> Message {
+    let unit_price = shared::Money::new(7499, "USD").unwrap();
+    let event = OrderCreatedEvent {
+        order_id: uuid::Uuid::new_v4(),
+        user_id: uuid::Uuid::new_v4(),
+        line_items: vec![OrderLineItem {
+            sku: "Mechanical Keyboard".to_string(),
+            quantity: 2,
+            unit_price: unit_price.clone(),
+        }],
+        total: unit_price.checked_mul(2).unwrap(),
+        timestamp: time::OffsetDateTime::now_utc(),
+    };
+    Message::from_event("order-service", &event)
+        .unwrap()
+        .with_correlation(uuid::Uuid::new_v4())
+}
+
+fn bulk_order() -> Message {
+    let mut message = order_created();
+    message.payload = json!({
+        "order_id": uuid::Uuid::new_v4(),
+        "line_items": (0..200)
+            .map(|i| json!({"sku": format!("SKU-{:04}", i), "quantity": i % 5 + 1, "unit_price": 1999}))
+            .collect::<Vec<_>>(),
+    });
+    message
+}
+
+fn codecs() -> Vec<Box<dyn Codec>> {
+    vec![
+        Box::new(JsonCodec),
+        Box::new(MessagePackCodec),
+        Box::new(CborCodec),
+        Box::new(ProtobufCodec),
+    ]
+}
+
+fn bench_codecs(c: &mut Criterion) {
+    for (name, message) in [("order_created", order_created()), ("bulk_order", bulk_order())] {
+        let mut group = c.benchmark_group(format!("codecs/{}", name));
+        for codec in codecs() {
+            let encoded = codec.encode(&message).unwrap();
+            println!("{} {}: {} bytes", name, codec.content_type(), encoded.len());
+
+            group.bench_with_input(BenchmarkId::new("encode", codec.content_type()), &message, |b, message| {
+                b.iter(|| codec.encode(black_box(message)).unwrap())
+            });
+            group.bench_with_input(BenchmarkId::new("decode", codec.content_type()), &encoded, |b, encoded| {
+                b.iter(|| codec.decode(black_box(encoded)).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+fn bench_compression(c: &mut Criterion) {
+    let message = bulk_order();
+    let compression = Compression { threshold_bytes: 0, ..Compression::default() };
+    let mut group = c.benchmark_group("codecs/zstd");
+    for codec in codecs() {
+        let (_, compressed) = encode_message(&message, codec.as_ref(), Some(compression)).unwrap();
+        println!("bulk_order {} + zstd: {} bytes", codec.content_type(), compressed.len());
+
+        group.bench_function(codec.content_type(), |b| {
+            b.iter(|| encode_message(black_box(&message), codec.as_ref(), Some(compression)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_codecs, bench_compression);
+criterion_main!(benches);
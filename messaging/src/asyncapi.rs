@@ -0,0 +1,309 @@
+use crate::{Event, MessagingError, SchemaRegistry, SCHEMA_VERSION_HEADER};
+use serde_json::{json, Map, Value};
+
+/// Where each service serves the AsyncAPI document for the messages it
+/// sends and receives.
+pub const ASYNCAPI_PATH: &str = "/.well-known/asyncapi.json";
+pub const ASYNCAPI_VERSION: &str = "3.0.0";
+
+const SCHEMA_FORMAT: &str = "application/schema+json;version=draft-07";
+
+/// Schemas shipped with this crate for the events it defines.
+const BUNDLED_SCHEMAS: &[(&str, u32, &str)] = &[
+    ("user_created", 1, include_str!("../schemas/user_created/v1.json")),
+    ("user_updated", 1, include_str!("../schemas/user_updated/v1.json")),
+    ("user_deleted", 1, include_str!("../schemas/user_deleted/v1.json")),
+    ("user_restored", 1, include_str!("../schemas/user_restored/v1.json")),
+    ("order_created", 1, include_str!("../schemas/order_created/v1.json")),
+    ("order_created", 2, include_str!("../schemas/order_created/v2.json")),
+    ("order_created", 3, include_str!("../schemas/order_created/v3.json")),
+    ("order_status_changed", 1, include_str!("../schemas/order_status_changed/v1.json")),
+    ("order_status_changed", 2, include_str!("../schemas/order_status_changed/v2.json")),
+];
+
+/// The schema this crate ships for `event_type` at `version`, if any.
+pub fn bundled_schema(event_type: &str, version: u32) -> Option<Value> {
+    BUNDLED_SCHEMAS
+        .iter()
+        .find(|(name, v, _)| *name == event_type && *v == version)
+        .map(|(_, _, schema)| serde_json::from_str(schema).expect("bundled schemas are valid JSON"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Send,
+    Receive,
+}
+
+impl Action {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Action::Send => "send",
+            Action::Receive => "receive",
+        }
+    }
+}
+
+/// One message a service sends or receives on one subject.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CatalogEntry {
+    pub subject: String,
+    pub message_type: String,
+    /// Payload schema version, for versioned events.
+    pub version: Option<u32>,
+    pub action: Action,
+    pub summary: Option<String>,
+    pub schema: Option<Value>,
+}
+
+impl CatalogEntry {
+    fn message_key(&self) -> String {
+        match self.version {
+            Some(version) => format!("{}.v{}", self.message_type, version),
+            None => self.message_type.clone(),
+        }
+    }
+}
+
+/// The messages one service sends and receives, rendered as an AsyncAPI
+/// document with [`EventCatalog::to_asyncapi`].
+#[derive(Debug, Clone)]
+pub struct EventCatalog {
+    service: String,
+    version: String,
+    description: Option<String>,
+    entries: Vec<CatalogEntry>,
+}
+
+impl EventCatalog {
+    pub fn new(service: &str, version: &str) -> Self {
+        Self {
+            service: service.to_string(),
+            version: version.to_string(),
+            description: None,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    pub fn entries(&self) -> &[CatalogEntry] {
+        &self.entries
+    }
+
+    /// Events of type `E` published by this service on `E::SUBJECT`.
+    pub fn publishes<E: Event>(self) -> Self {
+        self.event::<E>(Action::Send)
+    }
+
+    /// Events of type `E` this service consumes.
+    pub fn subscribes<E: Event>(self) -> Self {
+        self.event::<E>(Action::Receive)
+    }
+
+    fn event<E: Event>(mut self, action: Action) -> Self {
+        self.entries.push(CatalogEntry {
+            subject: E::SUBJECT.to_string(),
+            message_type: E::TYPE.to_string(),
+            version: Some(E::VERSION),
+            action,
+            summary: None,
+            schema: bundled_schema(E::TYPE, E::VERSION),
+        });
+        self
+    }
+
+    /// A command this service sends as a request on `subject`.
+    pub fn sends_command(self, subject: &str, summary: &str) -> Self {
+        self.command(subject, summary, Action::Send)
+    }
+
+    /// A command this service serves on `subject`.
+    pub fn receives_command(self, subject: &str, summary: &str) -> Self {
+        self.command(subject, summary, Action::Receive)
+    }
+
+    fn command(mut self, subject: &str, summary: &str, action: Action) -> Self {
+        self.entries.push(CatalogEntry {
+            subject: subject.to_string(),
+            message_type: subject.strip_prefix("commands.").unwrap_or(subject).to_string(),
+            version: None,
+            action,
+            summary: Some(summary.to_string()),
+            schema: None,
+        });
+        self
+    }
+
+    /// Fills in schemas missing from the bundled set from `registry`.
+    pub async fn resolve_schemas(mut self, registry: &SchemaRegistry) -> Result<Self, MessagingError> {
+        for entry in self.entries.iter_mut().filter(|entry| entry.schema.is_none()) {
+            if let Some(version) = entry.version {
+                entry.schema = Some(registry.schema(&entry.message_type, version).await?);
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn to_asyncapi(&self) -> Value {
+        let mut channels = Map::new();
+        let mut operations = Map::new();
+        let mut messages = Map::new();
+
+        for entry in &self.entries {
+            let key = entry.message_key();
+            let channel = channels
+                .entry(entry.subject.clone())
+                .or_insert_with(|| json!({ "address": entry.subject, "messages": {} }));
+            channel["messages"][&key] = json!({ "$ref": format!("#/components/messages/{}", key) });
+
+            let mut operation = json!({
+                "action": entry.action.as_str(),
+                "channel": { "$ref": format!("#/channels/{}", entry.subject) },
+                "messages": [{ "$ref": format!("#/channels/{}/messages/{}", entry.subject, key) }],
+                "x-service": self.service,
+            });
+            if let Some(summary) = &entry.summary {
+                operation["summary"] = json!(summary);
+            }
+            operations.insert(format!("{}.{}.{}", self.service, entry.action.as_str(), key), operation);
+
+            messages.insert(key, message_object(entry));
+        }
+
+        let mut info = json!({
+            "title": format!("{} messages", self.service),
+            "version": self.version,
+        });
+        if let Some(description) = &self.description {
+            info["description"] = json!(description);
+        }
+
+        json!({
+            "asyncapi": ASYNCAPI_VERSION,
+            "info": info,
+            "defaultContentType": "application/json",
+            "channels": channels,
+            "operations": operations,
+            "components": { "messages": messages },
+        })
+    }
+}
+
+/// The AsyncAPI message for `entry`. On the wire the event payload travels
+/// inside the [`crate::Message`] envelope, so that is what the schema
+/// describes.
+fn message_object(entry: &CatalogEntry) -> Value {
+    let mut payload = entry.schema.clone().unwrap_or_else(|| json!({}));
+    let title = payload.get("title").cloned();
+    if let Value::Object(schema) = &mut payload {
+        schema.remove("$schema");
+    }
+
+    let mut headers = json!({ "type": "object", "additionalProperties": { "type": "string" } });
+    if let Some(version) = entry.version {
+        headers["properties"] = json!({ SCHEMA_VERSION_HEADER: { "const": version.to_string() } });
+    }
+
+    let mut message = json!({
+        "name": entry.message_type,
+        "contentType": "application/json",
+        "payload": {
+            "schemaFormat": SCHEMA_FORMAT,
+            "schema": {
+                "type": "object",
+                "required": ["id", "message_type", "source", "destination", "timestamp", "headers", "payload"],
+                "properties": {
+                    "id": { "type": "string", "format": "uuid" },
+                    "correlation_id": { "type": ["string", "null"], "format": "uuid" },
+                    "causation_id": { "type": ["string", "null"], "format": "uuid" },
+                    "message_type": { "const": entry.message_type },
+                    "source": { "type": "string" },
+                    "destination": { "type": "string" },
+                    "timestamp": {
+                        "description": "time::OffsetDateTime in its compact serde form",
+                        "type": "array",
+                        "items": { "type": "integer" }
+                    },
+                    "headers": headers,
+                    "payload": payload,
+                },
+            },
+        },
+    });
+    if let Some(title) = title {
+        message["title"] = title;
+    }
+    if let Some(summary) = &entry.summary {
+        message["summary"] = json!(summary);
+    }
+    message
+}
+
+/// Merges per-service documents into one catalog.
+///
+/// Channels shared between services are combined. A message defined
+/// differently by two services is an error, since producers and consumers
+/// would disagree about it.
+pub fn merge_asyncapi(title: &str, documents: &[Value]) -> Result<Value, MessagingError> {
+    let mut channels = Map::new();
+    let mut operations = Map::new();
+    let mut messages = Map::new();
+    let mut services = Vec::new();
+
+    for document in documents {
+        if let Some(service) = document.pointer("/info/title").and_then(Value::as_str) {
+            services.push(service.to_string());
+        }
+        for (id, channel) in section(document, "/channels") {
+            let merged = channels.entry(id.clone()).or_insert_with(|| json!({ "address": channel["address"], "messages": {} }));
+            if merged["address"] != channel["address"] {
+                return Err(conflict("channel", id));
+            }
+            for (key, reference) in section(channel, "/messages") {
+                merged["messages"][key] = reference.clone();
+            }
+        }
+        for (id, operation) in section(document, "/operations") {
+            insert_unique(&mut operations, "operation", id, operation)?;
+        }
+        for (key, message) in section(document, "/components/messages") {
+            insert_unique(&mut messages, "message", key, message)?;
+        }
+    }
+
+    Ok(json!({
+        "asyncapi": ASYNCAPI_VERSION,
+        "info": {
+            "title": title,
+            "version": "1.0.0",
+            "description": format!("Merged from: {}", services.join(", ")),
+        },
+        "defaultContentType": "application/json",
+        "channels": channels,
+        "operations": operations,
+        "components": { "messages": messages },
+    }))
+}
+
+fn section<'a>(value: &'a Value, pointer: &str) -> impl Iterator<Item = (&'a String, &'a Value)> {
+    value.pointer(pointer).and_then(Value::as_object).into_iter().flatten()
+}
+
+fn insert_unique(target: &mut Map<String, Value>, kind: &str, key: &str, value: &Value) -> Result<(), MessagingError> {
+    match target.get(key) {
+        Some(existing) if existing != value => Err(conflict(kind, key)),
+        Some(_) => Ok(()),
+        None => {
+            target.insert(key.to_string(), value.clone());
+            Ok(())
+        }
+    }
+}
+
+fn conflict(kind: &str, key: &str) -> MessagingError {
+    MessagingError::CatalogError(format!("{} {} is defined differently by two services", kind, key))
+}
@@ -0,0 +1,366 @@
+use crate::{Message, MessagingError, SCHEMA_VERSION_HEADER};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// CloudEvents specification version produced by this mapping.
+pub const CLOUDEVENTS_SPEC_VERSION: &str = "1.0";
+/// Content type of a structured-mode CloudEvent.
+pub const CLOUDEVENTS_JSON_CONTENT_TYPE: &str = "application/cloudevents+json";
+const JSON_CONTENT_TYPE: &str = "application/json";
+const BINARY_HEADER_PREFIX: &str = "ce-";
+
+// Extension attributes carrying `Message` fields that CloudEvents has no slot for.
+const CORRELATION_ID_EXTENSION: &str = "correlationid";
+const CAUSATION_ID_EXTENSION: &str = "causationid";
+const DESTINATION_EXTENSION: &str = "destination";
+const SCHEMA_VERSION_EXTENSION: &str = "schemaversion";
+/// Message headers that are not valid extension names, as a JSON object string.
+const HEADERS_EXTENSION: &str = "msgheaders";
+
+// Message headers preserving CloudEvents attributes that `Message` has no field for.
+const SUBJECT_HEADER: &str = "ce-subject";
+const DATASCHEMA_HEADER: &str = "ce-dataschema";
+
+/// Binary-mode headers (name, value) and body.
+pub type BinaryEvent = (Vec<(String, String)>, Vec<u8>);
+
+/// A CloudEvents 1.0 event whose data is JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CloudEvent {
+    pub specversion: String,
+    pub id: String,
+    pub source: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "time::serde::rfc3339::option")]
+    pub time: Option<OffsetDateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datacontenttype: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataschema: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    /// Extension attributes. Values are kept in their string form, which is
+    /// how they travel in binary mode.
+    #[serde(flatten)]
+    pub extensions: BTreeMap<String, String>,
+}
+
+/// Whether `name` is a legal CloudEvents attribute name: lowercase ASCII letters and digits.
+pub fn is_valid_attribute_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+}
+
+fn is_context_attribute(name: &str) -> bool {
+    matches!(
+        name,
+        "specversion" | "id" | "source" | "type" | "subject" | "time" | "datacontenttype" | "dataschema" | "data"
+    )
+}
+
+fn is_reserved_extension(name: &str) -> bool {
+    matches!(
+        name,
+        CORRELATION_ID_EXTENSION | CAUSATION_ID_EXTENSION | DESTINATION_EXTENSION | SCHEMA_VERSION_EXTENSION | HEADERS_EXTENSION
+    )
+}
+
+fn invalid(message: impl Into<String>) -> MessagingError {
+    MessagingError::CloudEventError(message.into())
+}
+
+impl From<&Message> for CloudEvent {
+    fn from(message: &Message) -> Self {
+        let mut extensions = BTreeMap::new();
+        if let Some(correlation_id) = message.correlation_id {
+            extensions.insert(CORRELATION_ID_EXTENSION.to_string(), correlation_id.to_string());
+        }
+        if let Some(causation_id) = message.causation_id {
+            extensions.insert(CAUSATION_ID_EXTENSION.to_string(), causation_id.to_string());
+        }
+        if !message.destination.is_empty() {
+            extensions.insert(DESTINATION_EXTENSION.to_string(), message.destination.clone());
+        }
+
+        let mut subject = None;
+        let mut dataschema = None;
+        let mut other_headers = BTreeMap::new();
+        for (key, value) in &message.headers {
+            match key.as_str() {
+                SUBJECT_HEADER => subject = Some(value.clone()),
+                DATASCHEMA_HEADER => dataschema = Some(value.clone()),
+                SCHEMA_VERSION_HEADER => {
+                    extensions.insert(SCHEMA_VERSION_EXTENSION.to_string(), value.clone());
+                }
+                key if is_valid_attribute_name(key) && !is_context_attribute(key) && !is_reserved_extension(key) => {
+                    extensions.insert(key.to_string(), value.clone());
+                }
+                _ => {
+                    other_headers.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        if !other_headers.is_empty() {
+            extensions.insert(
+                HEADERS_EXTENSION.to_string(),
+                serde_json::to_string(&other_headers).expect("string map serializes"),
+            );
+        }
+
+        CloudEvent {
+            specversion: CLOUDEVENTS_SPEC_VERSION.to_string(),
+            id: message.id.to_string(),
+            source: message.source.clone(),
+            event_type: message.message_type.clone(),
+            subject,
+            time: Some(message.timestamp),
+            datacontenttype: Some(JSON_CONTENT_TYPE.to_string()),
+            dataschema,
+            data: Some(message.payload.clone()),
+            extensions,
+        }
+    }
+}
+
+impl From<Message> for CloudEvent {
+    fn from(message: Message) -> Self {
+        CloudEvent::from(&message)
+    }
+}
+
+impl TryFrom<CloudEvent> for Message {
+    type Error = MessagingError;
+
+    fn try_from(event: CloudEvent) -> Result<Self, Self::Error> {
+        if event.specversion != CLOUDEVENTS_SPEC_VERSION {
+            return Err(invalid(format!("Unsupported specversion {}", event.specversion)));
+        }
+        if let Some(content_type) = &event.datacontenttype {
+            if !is_json_content_type(content_type) {
+                return Err(invalid(format!("Unsupported datacontenttype {}", content_type)));
+            }
+        }
+
+        let id = Uuid::parse_str(&event.id).map_err(|e| invalid(format!("id is not a UUID: {}", e)))?;
+        let parse_uuid = |name: &str, value: &str| {
+            Uuid::parse_str(value).map_err(|e| invalid(format!("{} is not a UUID: {}", name, e)))
+        };
+
+        let mut message = Message::new(
+            event.event_type,
+            event.source,
+            String::new(),
+            event.data.unwrap_or(Value::Null),
+        );
+        message.id = id;
+        message.timestamp = event.time.unwrap_or(message.timestamp);
+        if let Some(subject) = event.subject {
+            message.headers.insert(SUBJECT_HEADER.to_string(), subject);
+        }
+        if let Some(dataschema) = event.dataschema {
+            message.headers.insert(DATASCHEMA_HEADER.to_string(), dataschema);
+        }
+
+        for (name, value) in event.extensions {
+            match name.as_str() {
+                CORRELATION_ID_EXTENSION => message.correlation_id = Some(parse_uuid(&name, &value)?),
+                CAUSATION_ID_EXTENSION => message.causation_id = Some(parse_uuid(&name, &value)?),
+                DESTINATION_EXTENSION => message.destination = value,
+                SCHEMA_VERSION_EXTENSION => {
+                    message.headers.insert(SCHEMA_VERSION_HEADER.to_string(), value);
+                }
+                HEADERS_EXTENSION => {
+                    let headers: HashMap<String, String> = serde_json::from_str(&value)
+                        .map_err(|e| invalid(format!("{} is not a JSON object of strings: {}", HEADERS_EXTENSION, e)))?;
+                    message.headers.extend(headers);
+                }
+                _ => {
+                    message.headers.insert(name, value);
+                }
+            }
+        }
+
+        Ok(message)
+    }
+}
+
+fn is_json_content_type(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    media_type.eq_ignore_ascii_case(JSON_CONTENT_TYPE) || media_type.ends_with("+json")
+}
+
+impl CloudEvent {
+    /// Serializes the event in structured JSON mode.
+    pub fn to_structured(&self) -> Result<Vec<u8>, MessagingError> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_structured(body: &[u8]) -> Result<Self, MessagingError> {
+        Ok(serde_json::from_slice(body)?)
+    }
+
+    /// Splits the event into binary-mode headers and a body.
+    ///
+    /// Attributes become `ce-<name>` headers and `datacontenttype` becomes
+    /// `content-type`. Header names are returned in lowercase.
+    pub fn to_binary(&self) -> Result<BinaryEvent, MessagingError> {
+        let mut headers = vec![
+            (format!("{}specversion", BINARY_HEADER_PREFIX), self.specversion.clone()),
+            (format!("{}id", BINARY_HEADER_PREFIX), self.id.clone()),
+            (format!("{}source", BINARY_HEADER_PREFIX), self.source.clone()),
+            (format!("{}type", BINARY_HEADER_PREFIX), self.event_type.clone()),
+        ];
+        if let Some(subject) = &self.subject {
+            headers.push((format!("{}subject", BINARY_HEADER_PREFIX), subject.clone()));
+        }
+        if let Some(time) = &self.time {
+            let time = time
+                .format(&time::format_description::well_known::Rfc3339)
+                .map_err(|e| invalid(e.to_string()))?;
+            headers.push((format!("{}time", BINARY_HEADER_PREFIX), time));
+        }
+        if let Some(dataschema) = &self.dataschema {
+            headers.push((format!("{}dataschema", BINARY_HEADER_PREFIX), dataschema.clone()));
+        }
+        if let Some(content_type) = &self.datacontenttype {
+            headers.push(("content-type".to_string(), content_type.clone()));
+        }
+        for (name, value) in &self.extensions {
+            headers.push((format!("{}{}", BINARY_HEADER_PREFIX, name), value.clone()));
+        }
+
+        let body = match &self.data {
+            Some(data) => serde_json::to_vec(data)?,
+            None => Vec::new(),
+        };
+        Ok((headers, body))
+    }
+
+    /// Rebuilds an event from binary-mode headers and body.
+    ///
+    /// Header names are matched case-insensitively; headers without the
+    /// `ce-` prefix other than `content-type` are ignored.
+    pub fn from_binary<'a>(
+        headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+        body: &[u8],
+    ) -> Result<Self, MessagingError> {
+        let mut attributes = BTreeMap::new();
+        let mut datacontenttype = None;
+        for (name, value) in headers {
+            let name = name.to_ascii_lowercase();
+            if name == "content-type" {
+                datacontenttype = Some(value.to_string());
+            } else if let Some(attribute) = name.strip_prefix(BINARY_HEADER_PREFIX) {
+                attributes.insert(attribute.to_string(), value.to_string());
+            }
+        }
+
+        let mut take = |name: &str| attributes.remove(name);
+        let required = |value: Option<String>, name: &str| {
+            value.ok_or_else(|| invalid(format!("Missing required attribute {}", name)))
+        };
+        let specversion = required(take("specversion"), "specversion")?;
+        let id = required(take("id"), "id")?;
+        let source = required(take("source"), "source")?;
+        let event_type = required(take("type"), "type")?;
+        let subject = take("subject");
+        let dataschema = take("dataschema");
+        let time = take("time")
+            .map(|time| OffsetDateTime::parse(&time, &time::format_description::well_known::Rfc3339))
+            .transpose()
+            .map_err(|e| invalid(format!("time is not RFC 3339: {}", e)))?;
+
+        let data = if body.is_empty() {
+            None
+        } else {
+            Some(serde_json::from_slice(body)?)
+        };
+
+        Ok(CloudEvent {
+            specversion,
+            id,
+            source,
+            event_type,
+            subject,
+            time,
+            datacontenttype,
+            dataschema,
+            data,
+            extensions: attributes,
+        })
+    }
+
+    /// Binary mode over NATS: attributes as message headers.
+    pub fn to_nats(&self) -> Result<(async_nats::HeaderMap, Vec<u8>), MessagingError> {
+        let (headers, body) = self.to_binary()?;
+        let mut map = async_nats::HeaderMap::new();
+        for (name, value) in &headers {
+            map.insert(name.as_str(), value.as_str());
+        }
+        Ok((map, body))
+    }
+
+    pub fn from_nats(headers: &async_nats::HeaderMap, body: &[u8]) -> Result<Self, MessagingError> {
+        let pairs = headers
+            .iter()
+            .filter_map(|(name, values)| Some((name.as_ref(), values.first()?.as_str())));
+        Self::from_binary(pairs, body)
+    }
+
+    /// Binary mode over HTTP: attributes as percent-encoded headers.
+    pub fn to_http(&self) -> Result<(http::HeaderMap, Vec<u8>), MessagingError> {
+        let (headers, body) = self.to_binary()?;
+        let mut map = http::HeaderMap::new();
+        for (name, value) in &headers {
+            let name = http::HeaderName::from_bytes(name.as_bytes()).map_err(|e| invalid(e.to_string()))?;
+            let value = http::HeaderValue::from_str(&percent_encode(value)).map_err(|e| invalid(e.to_string()))?;
+            map.insert(name, value);
+        }
+        Ok((map, body))
+    }
+
+    pub fn from_http(headers: &http::HeaderMap, body: &[u8]) -> Result<Self, MessagingError> {
+        let mut decoded = Vec::new();
+        for (name, value) in headers {
+            let value = value.to_str().map_err(|e| invalid(e.to_string()))?;
+            decoded.push((name.as_str(), percent_decode(value)?));
+        }
+        Self::from_binary(decoded.iter().map(|(name, value)| (*name, value.as_str())), body)
+    }
+}
+
+/// Percent-encodes everything outside printable ASCII, plus space, `"` and `%`,
+/// as required for CloudEvents HTTP header values.
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if (0x21..=0x7e).contains(&byte) && byte != b'"' && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn percent_decode(value: &str) -> Result<String, MessagingError> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = value.get(i + 1..i + 3).ok_or_else(|| invalid("Truncated percent-encoding"))?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|e| invalid(e.to_string()))?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|e| invalid(e.to_string()))
+}
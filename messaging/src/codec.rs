@@ -0,0 +1,258 @@
+use crate::{Message, MessagingError};
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::Arc;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// NATS header naming the codec used for the message envelope.
+pub const CONTENT_TYPE_HEADER: &str = "content-type";
+/// NATS header naming the compression applied after encoding, if any.
+pub const CONTENT_ENCODING_HEADER: &str = "content-encoding";
+pub const ZSTD_ENCODING: &str = "zstd";
+/// Largest envelope a compressed payload may expand to. Anything bigger is
+/// rejected rather than buffered, so a small message cannot exhaust memory.
+pub const MAX_DECOMPRESSED_BYTES: usize = 16 * 1024 * 1024;
+
+/// Serialization format for the [`Message`] envelope on the wire.
+pub trait Codec: Send + Sync {
+    fn content_type(&self) -> &'static str;
+
+    fn encode(&self, message: &Message) -> Result<Vec<u8>, MessagingError>;
+
+    fn decode(&self, bytes: &[u8]) -> Result<Message, MessagingError>;
+}
+
+fn codec_error(e: impl std::fmt::Display) -> MessagingError {
+    MessagingError::CodecError(e.to_string())
+}
+
+/// The original format: the envelope as JSON.
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn encode(&self, message: &Message) -> Result<Vec<u8>, MessagingError> {
+        Ok(serde_json::to_vec(message)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Message, MessagingError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+pub struct MessagePackCodec;
+
+impl Codec for MessagePackCodec {
+    fn content_type(&self) -> &'static str {
+        "application/msgpack"
+    }
+
+    fn encode(&self, message: &Message) -> Result<Vec<u8>, MessagingError> {
+        rmp_serde::to_vec_named(message).map_err(codec_error)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Message, MessagingError> {
+        rmp_serde::from_slice(bytes).map_err(codec_error)
+    }
+}
+
+pub struct CborCodec;
+
+impl Codec for CborCodec {
+    fn content_type(&self) -> &'static str {
+        "application/cbor"
+    }
+
+    fn encode(&self, message: &Message) -> Result<Vec<u8>, MessagingError> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(message, &mut bytes).map_err(codec_error)?;
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Message, MessagingError> {
+        ciborium::from_reader(bytes).map_err(codec_error)
+    }
+}
+
+/// Wire form of `Message` described by `proto/message.proto`.
+///
+/// The payload stays JSON inside the envelope since it is schemaless here.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MessageProto {
+    #[prost(bytes = "vec", tag = "1")]
+    pub id: Vec<u8>,
+    #[prost(bytes = "vec", optional, tag = "2")]
+    pub correlation_id: Option<Vec<u8>>,
+    #[prost(bytes = "vec", optional, tag = "3")]
+    pub causation_id: Option<Vec<u8>>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub payload_json: Vec<u8>,
+    #[prost(string, tag = "5")]
+    pub message_type: String,
+    #[prost(string, tag = "6")]
+    pub source: String,
+    #[prost(string, tag = "7")]
+    pub destination: String,
+    #[prost(int64, tag = "8")]
+    pub timestamp_seconds: i64,
+    #[prost(int32, tag = "9")]
+    pub timestamp_nanos: i32,
+    #[prost(int32, tag = "10")]
+    pub timestamp_offset_seconds: i32,
+    #[prost(map = "string, string", tag = "11")]
+    pub headers: HashMap<String, String>,
+}
+
+pub struct ProtobufCodec;
+
+impl Codec for ProtobufCodec {
+    fn content_type(&self) -> &'static str {
+        "application/x-protobuf"
+    }
+
+    fn encode(&self, message: &Message) -> Result<Vec<u8>, MessagingError> {
+        let proto = MessageProto {
+            id: message.id.as_bytes().to_vec(),
+            correlation_id: message.correlation_id.map(|id| id.as_bytes().to_vec()),
+            causation_id: message.causation_id.map(|id| id.as_bytes().to_vec()),
+            payload_json: serde_json::to_vec(&message.payload)?,
+            message_type: message.message_type.clone(),
+            source: message.source.clone(),
+            destination: message.destination.clone(),
+            timestamp_seconds: message.timestamp.unix_timestamp(),
+            timestamp_nanos: message.timestamp.nanosecond() as i32,
+            timestamp_offset_seconds: message.timestamp.offset().whole_seconds(),
+            headers: message.headers.clone(),
+        };
+        Ok(prost::Message::encode_to_vec(&proto))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Message, MessagingError> {
+        let proto: MessageProto = prost::Message::decode(bytes).map_err(codec_error)?;
+        let uuid = |bytes: &[u8]| Uuid::from_slice(bytes).map_err(codec_error);
+        let offset = time::UtcOffset::from_whole_seconds(proto.timestamp_offset_seconds).map_err(codec_error)?;
+        let timestamp = OffsetDateTime::from_unix_timestamp(proto.timestamp_seconds)
+            .and_then(|t| t.replace_nanosecond(proto.timestamp_nanos as u32))
+            .map_err(codec_error)?
+            .to_offset(offset);
+
+        Ok(Message {
+            id: uuid(&proto.id)?,
+            correlation_id: proto.correlation_id.as_deref().map(uuid).transpose()?,
+            causation_id: proto.causation_id.as_deref().map(uuid).transpose()?,
+            payload: serde_json::from_slice(&proto.payload_json)?,
+            message_type: proto.message_type,
+            source: proto.source,
+            destination: proto.destination,
+            timestamp,
+            headers: proto.headers,
+        })
+    }
+}
+
+/// Looks up a built-in codec by content type. Parameters such as `; charset`
+/// are ignored.
+pub fn codec_for(content_type: &str) -> Option<Arc<dyn Codec>> {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    let codec: Arc<dyn Codec> = match media_type {
+        "application/json" => Arc::new(JsonCodec),
+        "application/msgpack" | "application/x-msgpack" => Arc::new(MessagePackCodec),
+        "application/cbor" => Arc::new(CborCodec),
+        "application/x-protobuf" | "application/protobuf" => Arc::new(ProtobufCodec),
+        _ => return None,
+    };
+    Some(codec)
+}
+
+/// zstd compression applied to encoded payloads at or above `threshold_bytes`.
+#[derive(Debug, Clone, Copy)]
+pub struct Compression {
+    pub threshold_bytes: usize,
+    pub level: i32,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            threshold_bytes: 4 * 1024,
+            level: 3,
+        }
+    }
+}
+
+/// Headers and body of an encoded message, ready for `publish_with_headers`.
+pub type EncodedMessage = (async_nats::HeaderMap, Vec<u8>);
+
+/// Encodes `message` with `codec`, compressing when configured and worthwhile,
+/// and returns the headers a subscriber needs to reverse it.
+pub fn encode_message(
+    message: &Message,
+    codec: &dyn Codec,
+    compression: Option<Compression>,
+) -> Result<EncodedMessage, MessagingError> {
+    let mut headers = async_nats::HeaderMap::new();
+    headers.insert(CONTENT_TYPE_HEADER, codec.content_type());
+
+    let mut bytes = codec.encode(message)?;
+    if let Some(compression) = compression.filter(|c| bytes.len() >= c.threshold_bytes) {
+        bytes = zstd::encode_all(bytes.as_slice(), compression.level).map_err(codec_error)?;
+        headers.insert(CONTENT_ENCODING_HEADER, ZSTD_ENCODING);
+    }
+
+    Ok((headers, bytes))
+}
+
+/// Inflates a zstd payload, reading at most one byte past
+/// [`MAX_DECOMPRESSED_BYTES`] to tell whether it is too big.
+fn decompress(bytes: &[u8]) -> Result<Vec<u8>, MessagingError> {
+    let mut decompressed = Vec::new();
+    zstd::Decoder::new(bytes)
+        .map_err(codec_error)?
+        .take(MAX_DECOMPRESSED_BYTES as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(codec_error)?;
+    if decompressed.len() > MAX_DECOMPRESSED_BYTES {
+        return Err(MessagingError::CodecError(format!(
+            "Compressed payload expands beyond {} bytes",
+            MAX_DECOMPRESSED_BYTES
+        )));
+    }
+    Ok(decompressed)
+}
+
+/// Reverses [`encode_message`]. Messages without a content type header are
+/// treated as uncompressed JSON, which is what older publishers sent.
+pub fn decode_message(headers: Option<&async_nats::HeaderMap>, bytes: &[u8]) -> Result<Message, MessagingError> {
+    let header = |name: &str| {
+        headers.and_then(|headers| {
+            headers
+                .iter()
+                .find(|(key, _)| AsRef::<str>::as_ref(*key).eq_ignore_ascii_case(name))
+                .and_then(|(_, values)| values.first())
+                .map(|value| value.as_str().to_string())
+        })
+    };
+
+    let decompressed;
+    let bytes = match header(CONTENT_ENCODING_HEADER) {
+        None => bytes,
+        Some(encoding) if encoding.eq_ignore_ascii_case(ZSTD_ENCODING) => {
+            decompressed = decompress(bytes)?;
+            decompressed.as_slice()
+        }
+        Some(encoding) => {
+            return Err(MessagingError::CodecError(format!("Unsupported content encoding {}", encoding)));
+        }
+    };
+
+    match header(CONTENT_TYPE_HEADER) {
+        None => JsonCodec.decode(bytes),
+        Some(content_type) => codec_for(&content_type)
+            .ok_or_else(|| MessagingError::CodecError(format!("Unsupported content type {}", content_type)))?
+            .decode(bytes),
+    }
+}
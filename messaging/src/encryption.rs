@@ -0,0 +1,146 @@
+use crate::{Message, MessagingError};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Id of the key the payload was encrypted with.
+pub const ENCRYPTION_KEY_HEADER: &str = "encryption-key";
+/// `*` when the whole payload is encrypted, otherwise a comma-separated list
+/// of the encrypted top-level payload fields.
+pub const ENCRYPTED_FIELDS_HEADER: &str = "encrypted-fields";
+
+const WHOLE_PAYLOAD: &str = "*";
+const NONCE_LEN: usize = 12;
+
+/// AES-256-GCM encryption of message payloads, or of selected payload fields
+/// such as an email address.
+///
+/// Each encrypted value becomes a base64 string of nonce and ciphertext. The
+/// message id and field name are bound in as associated data, so ciphertext
+/// cannot be moved to another message or field. Encrypting only some fields
+/// leaves the rest readable for routing, e.g. ordering by `user_id`.
+#[derive(Clone)]
+pub struct PayloadCipher {
+    key_id: String,
+    keys: HashMap<String, Aes256Gcm>,
+    fields: Option<Vec<String>>,
+}
+
+impl PayloadCipher {
+    /// Encrypts with `key` and decrypts anything encrypted with it.
+    pub fn new(key_id: impl Into<String>, key: [u8; 32]) -> Self {
+        let key_id = key_id.into();
+        let mut keys = HashMap::new();
+        keys.insert(key_id.clone(), Aes256Gcm::new(&key.into()));
+        Self { key_id, keys, fields: None }
+    }
+
+    /// Keeps an older key around for decryption while it is rotated out.
+    pub fn with_decryption_key(mut self, key_id: impl Into<String>, key: [u8; 32]) -> Self {
+        self.keys.insert(key_id.into(), Aes256Gcm::new(&key.into()));
+        self
+    }
+
+    /// Encrypts only these top-level payload fields instead of the whole payload.
+    pub fn only_fields<I, S>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.fields = Some(fields.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn encrypt(&self, mut message: Message) -> Result<Message, MessagingError> {
+        let cipher = &self.keys[&self.key_id];
+        let aad = message.id.to_string();
+        let encrypted = match &self.fields {
+            None => {
+                message.payload = Value::String(seal(cipher, &aad, WHOLE_PAYLOAD, &message.payload)?);
+                WHOLE_PAYLOAD.to_string()
+            }
+            Some(fields) => {
+                let Value::Object(payload) = &mut message.payload else {
+                    return Err(MessagingError::EncryptionError(format!(
+                        "message {} payload is not an object",
+                        message.id
+                    )));
+                };
+                let mut encrypted = Vec::new();
+                for field in fields {
+                    if let Some(value) = payload.get_mut(field) {
+                        *value = Value::String(seal(cipher, &aad, field, value)?);
+                        encrypted.push(field.as_str());
+                    }
+                }
+                if encrypted.is_empty() {
+                    return Ok(message);
+                }
+                encrypted.join(",")
+            }
+        };
+        message.headers.insert(ENCRYPTION_KEY_HEADER.to_string(), self.key_id.clone());
+        message.headers.insert(ENCRYPTED_FIELDS_HEADER.to_string(), encrypted);
+        Ok(message)
+    }
+
+    /// Restores the plaintext payload. Messages that were not encrypted are
+    /// returned unchanged.
+    pub fn decrypt(&self, mut message: Message) -> Result<Message, MessagingError> {
+        let Some(key_id) = message.headers.remove(ENCRYPTION_KEY_HEADER) else {
+            return Ok(message);
+        };
+        let fields = message.headers.remove(ENCRYPTED_FIELDS_HEADER).unwrap_or_else(|| WHOLE_PAYLOAD.to_string());
+        let cipher = self
+            .keys
+            .get(&key_id)
+            .ok_or_else(|| MessagingError::EncryptionError(format!("message {}: unknown key {}", message.id, key_id)))?;
+        let aad = message.id.to_string();
+
+        if fields == WHOLE_PAYLOAD {
+            message.payload = open(cipher, &aad, WHOLE_PAYLOAD, &message.payload)?;
+            return Ok(message);
+        }
+        for field in fields.split(',') {
+            if let Some(value) = message.payload.get_mut(field) {
+                *value = open(cipher, &aad, field, value)?;
+            }
+        }
+        Ok(message)
+    }
+}
+
+fn associated_data(message_id: &str, field: &str) -> Vec<u8> {
+    format!("{}/{}", message_id, field).into_bytes()
+}
+
+fn seal(cipher: &Aes256Gcm, message_id: &str, field: &str, value: &Value) -> Result<String, MessagingError> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let plaintext = serde_json::to_vec(value)?;
+    let aad = associated_data(message_id, field);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: &plaintext, aad: &aad })
+        .map_err(|e| MessagingError::EncryptionError(e.to_string()))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(BASE64.encode(sealed))
+}
+
+fn open(cipher: &Aes256Gcm, message_id: &str, field: &str, value: &Value) -> Result<Value, MessagingError> {
+    let error = |reason: &str| MessagingError::EncryptionError(format!("message {} field {}: {}", message_id, field, reason));
+    let sealed = value
+        .as_str()
+        .and_then(|s| BASE64.decode(s).ok())
+        .filter(|sealed| sealed.len() > NONCE_LEN)
+        .ok_or_else(|| error("not an encrypted value"))?;
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let nonce: [u8; NONCE_LEN] = nonce.try_into().expect("split at nonce length");
+    let aad = associated_data(message_id, field);
+    let plaintext = cipher
+        .decrypt(&Nonce::from(nonce), Payload { msg: ciphertext, aad: &aad })
+        .map_err(|_| error("decryption failed"))?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
@@ -17,4 +17,59 @@ pub enum MessagingError {
     
     #[error("Subscribe error: {0}")]
     SubscribeError(String),
+
+    #[error("Unexpected message type: expected {expected}, got {actual}")]
+    UnexpectedMessageType { expected: String, actual: String },
+
+    #[error("Schema error: {0}")]
+    SchemaError(String),
+
+    #[error("Schema validation failed: {0}")]
+    SchemaValidationError(String),
+
+    #[error("Incompatible schema: {0}")]
+    IncompatibleSchema(String),
+
+    #[error("Upcast error: {0}")]
+    UpcastError(String),
+
+    #[error("CloudEvents error: {0}")]
+    CloudEventError(String),
+
+    #[error("Codec error: {0}")]
+    CodecError(String),
+
+    #[error("Request to {0} timed out")]
+    Timeout(String),
+
+    #[error("No responders for {0}")]
+    NoResponders(String),
+
+    #[error("Remote error: {0}")]
+    Remote(crate::RpcError),
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+
+    #[error("Saga error: {0}")]
+    SagaError(String),
+
+    #[error("Signature error: {0}")]
+    SignatureError(String),
+
+    #[error("Encryption error: {0}")]
+    EncryptionError(String),
+
+    #[error("Catalog error: {0}")]
+    CatalogError(String),
+
+    #[error("Scheduler error: {0}")]
+    SchedulerError(String),
+
+    #[error("Stream {stream_id} is at version {actual}, expected {expected}")]
+    ConcurrencyConflict {
+        stream_id: String,
+        expected: String,
+        actual: i64,
+    },
 }
\ No newline at end of file
@@ -0,0 +1,91 @@
+use crate::{
+    Message, MessagingError, OrderCreatedEvent, OrderStatusChangedEvent, UserCreatedEvent, UserDeletedEvent,
+    UserRestoredEvent, UserUpdatedEvent,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Header carrying the schema version of the payload.
+pub const SCHEMA_VERSION_HEADER: &str = "schema-version";
+
+/// A domain event with a fixed type name, schema version and subject.
+///
+/// Implementors are the payloads carried inside a [`Message`]; the constants
+/// tie each payload to the `message_type` and subject it travels on so that
+/// publishers and subscribers cannot disagree about them.
+pub trait Event: Serialize + DeserializeOwned + Send + Sync + 'static {
+    /// Value used for `Message::message_type`.
+    const TYPE: &'static str;
+    /// Schema version of the payload, starting at 1.
+    const VERSION: u32;
+    /// Subject the event is published on, usually `events.<TYPE>`.
+    const SUBJECT: &'static str;
+}
+
+impl Event for UserCreatedEvent {
+    const TYPE: &'static str = "user_created";
+    const VERSION: u32 = 1;
+    const SUBJECT: &'static str = "events.user_created";
+}
+
+impl Event for UserUpdatedEvent {
+    const TYPE: &'static str = "user_updated";
+    const VERSION: u32 = 1;
+    const SUBJECT: &'static str = "events.user_updated";
+}
+
+impl Event for UserDeletedEvent {
+    const TYPE: &'static str = "user_deleted";
+    const VERSION: u32 = 1;
+    const SUBJECT: &'static str = "events.user_deleted";
+}
+
+impl Event for UserRestoredEvent {
+    const TYPE: &'static str = "user_restored";
+    const VERSION: u32 = 1;
+    const SUBJECT: &'static str = "events.user_restored";
+}
+
+impl Event for OrderCreatedEvent {
+    const TYPE: &'static str = "order_created";
+    const VERSION: u32 = 3;
+    const SUBJECT: &'static str = "events.order_created";
+}
+
+impl Event for OrderStatusChangedEvent {
+    const TYPE: &'static str = "order_status_changed";
+    const VERSION: u32 = 2;
+    const SUBJECT: &'static str = "events.order_status_changed";
+}
+
+impl Message {
+    /// Wraps a typed event in a message envelope.
+    pub fn from_event<E: Event>(source: &str, event: &E) -> Result<Self, MessagingError> {
+        let payload = serde_json::to_value(event)?;
+        Ok(Message::new(
+            E::TYPE.to_string(),
+            source.to_string(),
+            E::SUBJECT.to_string(),
+            payload,
+        )
+        .with_header(SCHEMA_VERSION_HEADER.to_string(), E::VERSION.to_string()))
+    }
+
+    /// Deserializes the payload as `E`, rejecting messages of another type.
+    pub fn decode<E: Event>(&self) -> Result<E, MessagingError> {
+        if self.message_type != E::TYPE {
+            return Err(MessagingError::UnexpectedMessageType {
+                expected: E::TYPE.to_string(),
+                actual: self.message_type.clone(),
+            });
+        }
+
+        Ok(serde_json::from_value(self.payload.clone())?)
+    }
+
+    /// Schema version from the message headers, if present and valid.
+    pub fn schema_version(&self) -> Option<u32> {
+        self.headers
+            .get(SCHEMA_VERSION_HEADER)
+            .and_then(|version| version.parse().ok())
+    }
+}
@@ -0,0 +1,551 @@
+use crate::{Message, MessagingError, Projection};
+use futures::Stream;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use shared::db::{execute_all, from_timestamp, to_timestamp, Backend};
+use sqlx::{AnyPool, Row};
+use std::sync::Arc;
+use std::marker::PhantomData;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// An event about to be appended to a stream.
+#[derive(Debug, Clone)]
+pub struct NewEvent {
+    pub event_type: String,
+    pub payload: Value,
+    /// Correlation ids and other context that is not part of the event itself.
+    pub metadata: Value,
+}
+
+impl NewEvent {
+    pub fn new(event_type: &str, payload: Value) -> Self {
+        Self {
+            event_type: event_type.to_string(),
+            payload,
+            metadata: Value::Object(Default::default()),
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: Value) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+/// An event as stored, with its place in its own stream and in the global feed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    pub global_position: i64,
+    pub stream_id: String,
+    /// 1-based position within the stream.
+    pub version: i64,
+    pub event_type: String,
+    pub payload: Value,
+    pub metadata: Value,
+    #[serde(with = "time::serde::rfc3339")]
+    pub recorded_at: OffsetDateTime,
+}
+
+impl RecordedEvent {
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, MessagingError> {
+        Ok(serde_json::from_value(self.payload.clone())?)
+    }
+}
+
+/// The stream version an append expects to find, for optimistic concurrency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedVersion {
+    Any,
+    NoStream,
+    Exact(i64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub stream_id: String,
+    pub version: i64,
+    pub state: Value,
+}
+
+/// Append-only event streams in SQLite or Postgres.
+///
+/// Every stream is versioned independently, and all events also get a
+/// position in one global feed that projectors read in order.
+#[derive(Clone)]
+pub struct EventStore {
+    pool: AnyPool,
+}
+
+impl EventStore {
+    pub fn new(pool: AnyPool) -> Self {
+        Self { pool }
+    }
+
+    pub fn pool(&self) -> &AnyPool {
+        &self.pool
+    }
+
+    pub async fn init_schema(&self) -> Result<(), MessagingError> {
+        let backend = Backend::of(&self.pool).await?;
+        let events = format!(
+            "CREATE TABLE IF NOT EXISTS events (
+                global_position {},
+                stream_id TEXT NOT NULL,
+                version BIGINT NOT NULL,
+                event_type TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                metadata TEXT NOT NULL,
+                recorded_at DOUBLE PRECISION NOT NULL,
+                UNIQUE (stream_id, version)
+            )",
+            backend.serial_primary_key()
+        );
+        let snapshots = "CREATE TABLE IF NOT EXISTS event_snapshots (
+                stream_id TEXT PRIMARY KEY,
+                version BIGINT NOT NULL,
+                state TEXT NOT NULL,
+                taken_at DOUBLE PRECISION NOT NULL
+            )";
+        Ok(execute_all(&self.pool, &[&events, snapshots]).await?)
+    }
+
+    /// Appends `events` to `stream_id` if its version matches `expected`,
+    /// returning the stream's new version.
+    ///
+    /// A mismatch, or losing a race with a concurrent append to the same
+    /// stream, is a [`MessagingError::ConcurrencyConflict`].
+    pub async fn append(
+        &self,
+        stream_id: &str,
+        expected: ExpectedVersion,
+        events: Vec<NewEvent>,
+    ) -> Result<i64, MessagingError> {
+        self.append_inner(stream_id, expected, events, None).await
+    }
+
+    /// Appends like [`append`](Self::append) and applies the new events to
+    /// `projection` in the same transaction, so the read model commits or
+    /// rolls back together with the stream.
+    pub async fn append_with(
+        &self,
+        stream_id: &str,
+        expected: ExpectedVersion,
+        events: Vec<NewEvent>,
+        projection: &dyn Projection,
+    ) -> Result<i64, MessagingError> {
+        self.append_inner(stream_id, expected, events, Some(projection)).await
+    }
+
+    async fn append_inner(
+        &self,
+        stream_id: &str,
+        expected: ExpectedVersion,
+        events: Vec<NewEvent>,
+        projection: Option<&dyn Projection>,
+    ) -> Result<i64, MessagingError> {
+        let mut attempt = 0;
+        loop {
+            match self.try_append(stream_id, expected, events.clone(), projection).await {
+                // The other writer may not have committed yet, so its version
+                // is only known once it has: try again rather than guess
+                Err(MessagingError::DatabaseError(e)) if is_busy(&e) && attempt < BUSY_RETRIES => {
+                    attempt += 1;
+                    tokio::time::sleep(Duration::from_millis(5 * attempt)).await;
+                }
+                Err(MessagingError::DatabaseError(e)) if lost_race(&e) => {
+                    return Err(MessagingError::ConcurrencyConflict {
+                        stream_id: stream_id.to_string(),
+                        expected: format!("{:?}", expected),
+                        actual: self.current_version(stream_id).await?,
+                    })
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn try_append(
+        &self,
+        stream_id: &str,
+        expected: ExpectedVersion,
+        events: Vec<NewEvent>,
+        projection: Option<&dyn Projection>,
+    ) -> Result<i64, MessagingError> {
+        let mut tx = self.pool.begin().await?;
+
+        // Postgres hands out sequence values before commit, so concurrent
+        // appends could become visible out of global order and a projector
+        // would skip the late one. Appending one transaction at a time keeps
+        // the feed gap-free. SQLite already serializes writers.
+        if tx.backend_name() == "PostgreSQL" {
+            sqlx::query("SELECT pg_advisory_xact_lock(7395821)").execute(&mut *tx).await?;
+        }
+
+        let current: i64 = sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM events WHERE stream_id = $1")
+            .bind(stream_id)
+            .fetch_one(&mut *tx)
+            .await?
+            .try_get("version")?;
+
+        let conflict = match expected {
+            ExpectedVersion::Any => false,
+            ExpectedVersion::NoStream => current != 0,
+            ExpectedVersion::Exact(version) => current != version,
+        };
+        if conflict {
+            return Err(MessagingError::ConcurrencyConflict {
+                stream_id: stream_id.to_string(),
+                expected: format!("{:?}", expected),
+                actual: current,
+            });
+        }
+
+        let recorded_at = to_timestamp(OffsetDateTime::now_utc());
+        let mut version = current;
+        for event in events {
+            version += 1;
+            sqlx::query(
+                "INSERT INTO events (stream_id, version, event_type, payload, metadata, recorded_at)
+                 VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(stream_id)
+            .bind(version)
+            .bind(&event.event_type)
+            .bind(serde_json::to_string(&event.payload)?)
+            .bind(serde_json::to_string(&event.metadata)?)
+            .bind(recorded_at)
+            .execute(&mut *tx)
+            .await?;
+
+            if let Some(projection) = projection {
+                let global_position: i64 =
+                    sqlx::query("SELECT global_position FROM events WHERE stream_id = $1 AND version = $2")
+                        .bind(stream_id)
+                        .bind(version)
+                        .fetch_one(&mut *tx)
+                        .await?
+                        .try_get("global_position")?;
+                let recorded = RecordedEvent {
+                    global_position,
+                    stream_id: stream_id.to_string(),
+                    version,
+                    event_type: event.event_type,
+                    payload: event.payload,
+                    metadata: event.metadata,
+                    recorded_at: from_timestamp(recorded_at),
+                };
+                projection.apply(&mut tx, &recorded).await?;
+            }
+        }
+
+        tx.commit().await?;
+        Ok(version)
+    }
+
+    async fn current_version(&self, stream_id: &str) -> Result<i64, MessagingError> {
+        Ok(sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM events WHERE stream_id = $1")
+            .bind(stream_id)
+            .fetch_one(&self.pool)
+            .await?
+            .try_get("version")?)
+    }
+
+    /// Records an event published by another service so it appears in the
+    /// global feed alongside local events.
+    ///
+    /// Each message gets its own stream keyed by its id, so redeliveries are
+    /// detected and skipped; returns `false` for those.
+    pub async fn record_message(&self, message: &Message) -> Result<bool, MessagingError> {
+        let metadata = serde_json::json!({
+            "message_id": message.id,
+            "source": message.source,
+            "correlation_id": message.correlation_id,
+            "causation_id": message.causation_id,
+        });
+        let event = NewEvent::new(&message.message_type, message.payload.clone()).with_metadata(metadata);
+        match self.append(&format!("message-{}", message.id), ExpectedVersion::NoStream, vec![event]).await {
+            Ok(_) => Ok(true),
+            Err(MessagingError::ConcurrencyConflict { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Events of `stream_id` with a version greater than `after_version`.
+    pub async fn read_stream(&self, stream_id: &str, after_version: i64) -> Result<Vec<RecordedEvent>, MessagingError> {
+        let rows = sqlx::query("SELECT * FROM events WHERE stream_id = $1 AND version > $2 ORDER BY version")
+            .bind(stream_id)
+            .bind(after_version)
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter().map(Self::from_row).collect()
+    }
+
+    /// Up to `limit` events from the global feed after `after_position`.
+    pub async fn read_all(&self, after_position: i64, limit: i64) -> Result<Vec<RecordedEvent>, MessagingError> {
+        let rows = sqlx::query("SELECT * FROM events WHERE global_position > $1 ORDER BY global_position LIMIT $2")
+            .bind(after_position)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter().map(Self::from_row).collect()
+    }
+
+    /// Position of the newest event in the global feed, or 0 when empty.
+    pub async fn head_position(&self) -> Result<i64, MessagingError> {
+        Ok(sqlx::query("SELECT COALESCE(MAX(global_position), 0) AS position FROM events")
+            .fetch_one(&self.pool)
+            .await?
+            .try_get("position")?)
+    }
+
+    /// Follows the global feed from `after_position`, polling for new events
+    /// every `poll_interval` once caught up. The stream never ends on its own.
+    pub fn subscribe_all(
+        &self,
+        after_position: i64,
+        poll_interval: Duration,
+    ) -> impl Stream<Item = Result<RecordedEvent, MessagingError>> + Send + 'static {
+        const BATCH: i64 = 256;
+        let store = self.clone();
+        futures::stream::unfold(
+            (after_position, std::collections::VecDeque::new()),
+            move |(mut position, mut buffered)| {
+                let store = store.clone();
+                async move {
+                    loop {
+                        if let Some(event) = buffered.pop_front() {
+                            let event: RecordedEvent = event;
+                            position = event.global_position;
+                            return Some((Ok(event), (position, buffered)));
+                        }
+                        match store.read_all(position, BATCH).await {
+                            Ok(events) if events.is_empty() => tokio::time::sleep(poll_interval).await,
+                            Ok(events) => buffered.extend(events),
+                            Err(e) => {
+                                tokio::time::sleep(poll_interval).await;
+                                return Some((Err(e), (position, buffered)));
+                            }
+                        }
+                    }
+                }
+            },
+        )
+    }
+
+    pub async fn save_snapshot(&self, stream_id: &str, version: i64, state: &Value) -> Result<(), MessagingError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM event_snapshots WHERE stream_id = $1")
+            .bind(stream_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO event_snapshots (stream_id, version, state, taken_at) VALUES ($1, $2, $3, $4)")
+            .bind(stream_id)
+            .bind(version)
+            .bind(serde_json::to_string(state)?)
+            .bind(to_timestamp(OffsetDateTime::now_utc()))
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn load_snapshot(&self, stream_id: &str) -> Result<Option<Snapshot>, MessagingError> {
+        let row = sqlx::query("SELECT stream_id, version, state FROM event_snapshots WHERE stream_id = $1")
+            .bind(stream_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(|row| {
+            let state: String = row.try_get("state")?;
+            Ok(Snapshot {
+                stream_id: row.try_get("stream_id")?,
+                version: row.try_get("version")?,
+                state: serde_json::from_str(&state)?,
+            })
+        })
+        .transpose()
+    }
+
+    fn from_row(row: &sqlx::any::AnyRow) -> Result<RecordedEvent, MessagingError> {
+        let payload: String = row.try_get("payload")?;
+        let metadata: String = row.try_get("metadata")?;
+        Ok(RecordedEvent {
+            global_position: row.try_get("global_position")?,
+            stream_id: row.try_get("stream_id")?,
+            version: row.try_get("version")?,
+            event_type: row.try_get("event_type")?,
+            payload: serde_json::from_str(&payload)?,
+            metadata: serde_json::from_str(&metadata)?,
+            recorded_at: from_timestamp(row.try_get("recorded_at")?),
+        })
+    }
+}
+
+/// State rebuilt by replaying its own events.
+pub trait Aggregate: Default + Serialize + DeserializeOwned + Send + Sync {
+    /// Prefix of the aggregate's stream ids, e.g. `order`.
+    const TYPE: &'static str;
+
+    type Event: Serialize + DeserializeOwned + Send + Sync;
+
+    fn event_type(event: &Self::Event) -> &'static str;
+
+    fn apply(&mut self, event: &Self::Event);
+}
+
+/// Loads and saves one kind of [`Aggregate`] through an [`EventStore`].
+pub struct AggregateRepository<A> {
+    store: EventStore,
+    snapshot_every: Option<i64>,
+    inline: Option<Arc<dyn Projection>>,
+    _aggregate: PhantomData<A>,
+}
+
+// Not derived, which would require `A: Clone`.
+impl<A> Clone for AggregateRepository<A> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            snapshot_every: self.snapshot_every,
+            inline: self.inline.clone(),
+            _aggregate: PhantomData,
+        }
+    }
+}
+
+impl<A: Aggregate> AggregateRepository<A> {
+    pub fn new(store: EventStore) -> Self {
+        Self {
+            store,
+            snapshot_every: None,
+            inline: None,
+            _aggregate: PhantomData,
+        }
+    }
+
+    /// Snapshots the aggregate whenever its version passes a multiple of `every`.
+    pub fn with_snapshots(mut self, every: i64) -> Self {
+        self.snapshot_every = Some(every.max(1));
+        self
+    }
+
+    /// Applies saved events to `projection` in the transaction that appends
+    /// them, for read models that must never lag behind the streams.
+    pub fn with_inline_projection(mut self, projection: impl Projection + 'static) -> Self {
+        self.inline = Some(Arc::new(projection));
+        self
+    }
+
+    pub fn stream_id(id: &str) -> String {
+        format!("{}-{}", A::TYPE, id)
+    }
+
+    /// Rebuilds the aggregate from its latest snapshot plus later events.
+    /// Returns `None` if the stream has no events.
+    pub async fn load(&self, id: &str) -> Result<Option<(A, i64)>, MessagingError> {
+        let stream_id = Self::stream_id(id);
+        let (mut aggregate, mut version) = match self.store.load_snapshot(&stream_id).await? {
+            Some(snapshot) => (serde_json::from_value(snapshot.state)?, snapshot.version),
+            None => (A::default(), 0),
+        };
+
+        for recorded in self.store.read_stream(&stream_id, version).await? {
+            aggregate.apply(&recorded.decode::<A::Event>()?);
+            version = recorded.version;
+        }
+
+        Ok((version > 0).then_some((aggregate, version)))
+    }
+
+    /// Rebuilds the aggregate as it stood at `version`, ignoring later events.
+    /// Returns `None` if the stream has not reached that version.
+    pub async fn load_at(&self, id: &str, version: i64) -> Result<Option<A>, MessagingError> {
+        let stream_id = Self::stream_id(id);
+        let (mut aggregate, mut at) = match self.store.load_snapshot(&stream_id).await? {
+            Some(snapshot) if snapshot.version <= version => (serde_json::from_value(snapshot.state)?, snapshot.version),
+            _ => (A::default(), 0),
+        };
+
+        for recorded in self.store.read_stream(&stream_id, at).await? {
+            if recorded.version > version {
+                break;
+            }
+            aggregate.apply(&recorded.decode::<A::Event>()?);
+            at = recorded.version;
+        }
+
+        Ok((at == version && version > 0).then_some(aggregate))
+    }
+
+    /// Appends `events` for an aggregate last seen at `expected_version`
+    /// (0 for a new one) and returns the aggregate after applying them.
+    pub async fn save(
+        &self,
+        id: &str,
+        mut aggregate: A,
+        expected_version: i64,
+        events: Vec<A::Event>,
+    ) -> Result<(A, i64), MessagingError> {
+        let stream_id = Self::stream_id(id);
+        let expected = match expected_version {
+            0 => ExpectedVersion::NoStream,
+            version => ExpectedVersion::Exact(version),
+        };
+        let new_events = events
+            .iter()
+            .map(|event| Ok(NewEvent::new(A::event_type(event), serde_json::to_value(event)?)))
+            .collect::<Result<Vec<_>, MessagingError>>()?;
+        let version = match &self.inline {
+            Some(projection) => self.store.append_with(&stream_id, expected, new_events, projection.as_ref()).await?,
+            None => self.store.append(&stream_id, expected, new_events).await?,
+        };
+
+        for event in &events {
+            aggregate.apply(event);
+        }
+
+        if let Some(every) = self.snapshot_every {
+            if version / every > expected_version / every {
+                self.store.save_snapshot(&stream_id, version, &serde_json::to_value(&aggregate)?).await?;
+            }
+        }
+
+        Ok((aggregate, version))
+    }
+}
+
+/// How often an append that SQLite turned away while another writer held the
+/// database is tried again before it counts as a conflict.
+const BUSY_RETRIES: u64 = 5;
+
+/// The name Postgres gives the events table's `UNIQUE (stream_id, version)`.
+const EVENTS_VERSION_CONSTRAINT: &str = "events_stream_id_version_key";
+
+/// Whether `error` means another append to the same stream got there first:
+/// its version already taken, or SQLite refusing to let two writers that
+/// read the same version both proceed.
+fn lost_race(error: &sqlx::Error) -> bool {
+    let sqlx::Error::Database(e) = error else {
+        return false;
+    };
+    if is_busy(error) {
+        return true;
+    }
+    // Only a duplicate version means another writer got there first; any
+    // other unique violation (e.g. from an inline projection) is a real error.
+    e.is_unique_violation()
+        && match e.constraint() {
+            Some(name) => name == EVENTS_VERSION_CONSTRAINT,
+            // SQLite names the columns rather than the constraint
+            None => e.message().contains("events.stream_id, events.version"),
+        }
+}
+
+/// SQLITE_BUSY, SQLITE_LOCKED and SQLITE_BUSY_SNAPSHOT: another writer holds
+/// the database or committed since this transaction started reading.
+fn is_busy(error: &sqlx::Error) -> bool {
+    let sqlx::Error::Database(e) = error else {
+        return false;
+    };
+    matches!(e.code().as_deref(), Some("5" | "6" | "517"))
+}
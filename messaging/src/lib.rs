@@ -1,11 +1,41 @@
 pub mod publisher;
 pub mod subscriber;
+pub mod subscription;
 pub mod message;
+pub mod event;
+pub mod schema;
+pub mod asyncapi;
+pub mod upcast;
+pub mod cloudevents;
+pub mod codec;
+pub mod signing;
+pub mod encryption;
+pub mod propagation;
+pub mod rpc;
+pub mod saga;
+pub mod event_store;
+pub mod projection;
+pub mod scheduler;
 pub mod error;
 
 pub use publisher::*;
 pub use subscriber::*;
+pub use subscription::*;
 pub use message::*;
+pub use event::*;
+pub use schema::*;
+pub use asyncapi::*;
+pub use upcast::*;
+pub use cloudevents::*;
+pub use codec::*;
+pub use signing::*;
+pub use encryption::*;
+pub use propagation::*;
+pub use rpc::*;
+pub use saga::*;
+pub use event_store::*;
+pub use projection::*;
+pub use scheduler::*;
 pub use error::*;
 
 #[cfg(test)]
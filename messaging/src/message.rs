@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use shared::Money;
 use uuid::Uuid;
 use time::OffsetDateTime;
 
@@ -61,12 +62,64 @@ pub struct UserCreatedEvent {
     pub timestamp: OffsetDateTime,
 }
 
+/// A user's profile changed. Carries the whole profile, so consumers can
+/// apply it without having seen earlier events; `timestamp` is the user's
+/// new `updated_at`, which orders changes to the same user.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserUpdatedEvent {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub timestamp: OffsetDateTime,
+}
+
+/// A user was soft deleted. They can be restored later.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserDeletedEvent {
+    pub user_id: Uuid,
+    pub timestamp: OffsetDateTime,
+}
+
+/// A soft-deleted user was restored, with the profile they had.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserRestoredEvent {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub timestamp: OffsetDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OrderLineItem {
+    pub sku: String,
+    pub quantity: i32,
+    pub unit_price: Money,
+}
+
+/// Schema version 3. Older versions carried a single product and a float
+/// total; [`order_created_upcasters`](crate::order_created_upcasters)
+/// converts them.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OrderCreatedEvent {
     pub order_id: Uuid,
     pub user_id: Uuid,
-    pub product_name: String,
-    pub quantity: i32,
-    pub total_price: f64,
+    pub line_items: Vec<OrderLineItem>,
+    pub total: Money,
     pub timestamp: OffsetDateTime,
-}
\ No newline at end of file
+}
+
+/// An order moved from one lifecycle status to another. Statuses are the
+/// snake_case names used by order-service, e.g. `pending` or `shipped`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrderStatusChangedEvent {
+    pub order_id: Uuid,
+    pub user_id: Uuid,
+    pub from: String,
+    pub to: String,
+    pub reason: Option<String>,
+    pub timestamp: OffsetDateTime,
+    /// The order's stream version after the change, so consumers can tell
+    /// which state it produced. `None` in events from before schema v2.
+    #[serde(default)]
+    pub version: Option<i64>,
+}
@@ -0,0 +1,152 @@
+use crate::{EventStore, MessagingError, RecordedEvent};
+use async_trait::async_trait;
+use shared::db::{execute_all, to_timestamp};
+use sqlx::{AnyConnection, Row};
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// Gauge reporting how many feed events a projection has yet to apply.
+pub const PROJECTION_LAG_METRIC: &str = "projection_lag_events";
+
+/// A read model built from the event store's global feed.
+///
+/// `apply` and `reset` run inside the transaction that also moves the
+/// projection's checkpoint, so the read tables and the checkpoint always agree.
+#[async_trait]
+pub trait Projection: Send + Sync {
+    /// Unique name, used as the checkpoint key.
+    fn name(&self) -> &str;
+
+    /// Creates the read tables if they do not exist yet.
+    async fn init(&self, conn: &mut AnyConnection) -> Result<(), MessagingError>;
+
+    /// Applies one event. Events the projection does not care about are ignored.
+    async fn apply(&self, conn: &mut AnyConnection, event: &RecordedEvent) -> Result<(), MessagingError>;
+
+    /// Empties the read tables before a rebuild.
+    async fn reset(&self, conn: &mut AnyConnection) -> Result<(), MessagingError>;
+}
+
+const SCHEMA: &[&str] = &["CREATE TABLE IF NOT EXISTS projection_checkpoints (
+        name TEXT PRIMARY KEY,
+        position BIGINT NOT NULL,
+        updated_at DOUBLE PRECISION NOT NULL
+    )"];
+
+/// Keeps one [`Projection`] up to date with the global feed.
+#[derive(Clone)]
+pub struct Projector {
+    store: EventStore,
+    projection: Arc<dyn Projection>,
+    batch_size: i64,
+}
+
+impl Projector {
+    pub fn new(store: EventStore, projection: impl Projection + 'static) -> Self {
+        Self {
+            store,
+            projection: Arc::new(projection),
+            batch_size: 500,
+        }
+    }
+
+    pub fn with_batch_size(mut self, batch_size: i64) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        self.projection.name()
+    }
+
+    pub async fn init(&self) -> Result<(), MessagingError> {
+        execute_all(self.store.pool(), SCHEMA).await?;
+        let mut conn = self.store.pool().acquire().await?;
+        self.projection.init(&mut conn).await
+    }
+
+    /// Global position of the last event applied, 0 if none.
+    pub async fn checkpoint(&self) -> Result<i64, MessagingError> {
+        let row = sqlx::query("SELECT position FROM projection_checkpoints WHERE name = $1")
+            .bind(self.name())
+            .fetch_optional(self.store.pool())
+            .await?;
+        Ok(match row {
+            Some(row) => row.try_get("position")?,
+            None => 0,
+        })
+    }
+
+    async fn save_checkpoint(&self, conn: &mut AnyConnection, position: i64) -> Result<(), MessagingError> {
+        let updated_at = to_timestamp(OffsetDateTime::now_utc());
+        sqlx::query(
+            "INSERT INTO projection_checkpoints (name, position, updated_at) VALUES ($1, $2, $3)
+             ON CONFLICT (name) DO UPDATE SET position = excluded.position, updated_at = excluded.updated_at",
+        )
+        .bind(self.name())
+        .bind(position)
+        .bind(updated_at)
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Applies every event after the checkpoint, one batch per transaction,
+    /// and returns how many were applied.
+    pub async fn catch_up(&self) -> Result<usize, MessagingError> {
+        let mut applied = 0;
+        let mut position = self.checkpoint().await?;
+        loop {
+            let events = self.store.read_all(position, self.batch_size).await?;
+            let Some(last) = events.last() else {
+                break;
+            };
+            let last_position = last.global_position;
+
+            let mut tx = self.store.pool().begin().await?;
+            for event in &events {
+                self.projection.apply(&mut tx, event).await?;
+            }
+            self.save_checkpoint(&mut tx, last_position).await?;
+            tx.commit().await?;
+
+            applied += events.len();
+            position = last_position;
+        }
+        self.record_lag().await?;
+        Ok(applied)
+    }
+
+    /// Drops the read model and replays the whole feed into it.
+    pub async fn rebuild(&self) -> Result<usize, MessagingError> {
+        tracing::info!("Rebuilding projection {}", self.name());
+        let mut tx = self.store.pool().begin().await?;
+        self.projection.reset(&mut tx).await?;
+        self.save_checkpoint(&mut tx, 0).await?;
+        tx.commit().await?;
+        self.catch_up().await
+    }
+
+    /// Number of feed events not yet applied.
+    pub async fn lag(&self) -> Result<i64, MessagingError> {
+        Ok(self.store.head_position().await? - self.checkpoint().await?)
+    }
+
+    async fn record_lag(&self) -> Result<i64, MessagingError> {
+        let lag = self.lag().await?;
+        observability::set_gauge(PROJECTION_LAG_METRIC, &[("projection", self.name())], lag as f64);
+        Ok(lag)
+    }
+
+    /// Catches up every `poll_interval` until the task is dropped.
+    pub async fn run(&self, poll_interval: Duration) {
+        loop {
+            if let Err(e) = self.catch_up().await {
+                tracing::error!("Projection {} failed: {}", self.name(), e);
+                let _ = self.record_lag().await;
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use crate::{Message, MessagingError};
+use tracing::{Instrument, Span};
+use uuid::Uuid;
+
+/// Carries the current trace context and correlation id over to an outgoing
+/// message. A correlation id the message already has is kept.
+pub(crate) fn stamp_outgoing(mut message: Message) -> Message {
+    observability::inject_context(&Span::current(), &mut message.headers);
+    if message.correlation_id.is_none() {
+        message.correlation_id = observability::current_correlation_id().and_then(|id| Uuid::parse_str(&id).ok());
+    }
+    message
+}
+
+/// Span for handling one consumed message, continuing the trace it was
+/// published from.
+pub fn consumer_span(subject: &str, message: &Message) -> Span {
+    let span = tracing::info_span!(
+        "messaging.process",
+        otel.kind = "consumer",
+        messaging.system = "nats",
+        messaging.destination = %subject,
+        messaging.message_id = %message.id,
+        messaging.message_type = %message.message_type,
+        correlation_id = %message.correlation_id.unwrap_or(message.id),
+    );
+    observability::set_parent_from(&span, &message.headers);
+    span
+}
+
+/// Runs `handler` inside a consumer span, with the message's correlation id
+/// (or its own id when it starts a new conversation) as the current one.
+pub(crate) async fn handle_traced<F, Fut, T>(subject: &str, handler: &F, message: Message) -> Result<T, MessagingError>
+where
+    F: Fn(Message) -> Fut,
+    Fut: std::future::Future<Output = Result<T, MessagingError>>,
+{
+    let span = consumer_span(subject, &message);
+    let correlation_id = message.correlation_id.unwrap_or(message.id).to_string();
+    let future = span.in_scope(|| handler(message));
+    observability::with_correlation_id(correlation_id, future.instrument(span)).await
+}
@@ -1,25 +1,118 @@
-use crate::{Message, MessagingError};
+use crate::{
+    build_request, decode_message, encode_message, map_request_error, parse_reply, propagation::stamp_outgoing, with_deadline, Codec,
+    Compression, EncodedMessage, Event, JsonCodec, Message, MessageSigner, MessagingError, PayloadCipher, Scheduler,
+    SchemaRegistry, TrustedKeys,
+};
 use async_nats::Client;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
 use tracing::info;
 
 pub struct Publisher {
     client: Client,
+    schemas: Option<Arc<SchemaRegistry>>,
+    validate_schemas: bool,
+    codec: Arc<dyn Codec>,
+    compression: Option<Compression>,
+    scheduler: Option<Scheduler>,
+    signer: Option<Arc<MessageSigner>>,
+    encryption: Option<Arc<PayloadCipher>>,
+    trusted_keys: Option<Arc<TrustedKeys>>,
 }
 
 impl Publisher {
     pub fn new(client: Client) -> Self {
-        Self { client }
+        Self {
+            client,
+            schemas: None,
+            validate_schemas: false,
+            codec: Arc::new(JsonCodec),
+            compression: None,
+            scheduler: None,
+            signer: None,
+            encryption: None,
+            trusted_keys: None,
+        }
+    }
+
+    /// Encodes envelopes with `codec` instead of JSON.
+    pub fn with_codec(mut self, codec: impl Codec + 'static) -> Self {
+        self.codec = Arc::new(codec);
+        self
+    }
+
+    /// Compresses encoded envelopes with zstd once they reach the threshold.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Validates the payload of every message of a type `registry` has a
+    /// schema for before it is sent, requests and replies included. Such
+    /// messages without a `schema-version` header are rejected; messages of
+    /// other types go out unchecked.
+    pub fn with_schema_registry(mut self, registry: Arc<SchemaRegistry>) -> Self {
+        self.schemas = Some(registry);
+        self.validate_schemas = true;
+        self
+    }
+
+    /// Stores messages passed to [`Publisher::publish_at`] and
+    /// [`Publisher::publish_after`] in `scheduler`. Something must run the
+    /// scheduler's dispatcher for them to go out.
+    pub fn with_scheduler(mut self, scheduler: Scheduler) -> Self {
+        self.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Signs every outgoing message so subscribers can check it came from us.
+    pub fn with_signer(mut self, signer: MessageSigner) -> Self {
+        self.signer = Some(Arc::new(signer));
+        self
+    }
+
+    /// Encrypts outgoing payloads, or the fields `cipher` was limited to.
+    /// Schemas are validated against the plaintext beforehand. Encrypted
+    /// replies to requests are decrypted with the same cipher.
+    pub fn with_encryption(mut self, cipher: PayloadCipher) -> Self {
+        self.encryption = Some(Arc::new(cipher));
+        self
+    }
+
+    /// Rejects replies to requests whose signature does not verify against
+    /// `keys`, and unsigned ones too if `keys` requires signatures.
+    pub fn with_trusted_keys(mut self, keys: TrustedKeys) -> Self {
+        self.trusted_keys = Some(Arc::new(keys));
+        self
+    }
+
+    /// Turns payload validation on or off without dropping the registry.
+    pub fn set_schema_validation(&mut self, enabled: bool) {
+        self.validate_schemas = enabled;
     }
 
     pub async fn publish(&self, subject: &str, message: Message) -> Result<(), MessagingError> {
-        let payload = serde_json::to_vec(&message)?;
+        self.check_schema(&message).await?;
+
+        let id = message.id;
+        let (headers, payload) = self.encode(message)?;
         let subject_owned = subject.to_string();
-        self.client.publish(subject_owned, payload.into()).await
+        self.client.publish_with_headers(subject_owned, headers, payload.into()).await
             .map_err(|e| MessagingError::NatsError(Box::new(e)))?;
-        info!("Published message {} to subject {}", message.id, subject);
+        info!("Published message {} to subject {}", id, subject);
         Ok(())
     }
 
+    /// Publishes a typed event on its own subject and returns the message id.
+    pub async fn publish_typed<E: Event>(&self, source: &str, event: &E) -> Result<uuid::Uuid, MessagingError> {
+        let message = Message::from_event(source, event)?;
+        let id = message.id;
+        self.publish(E::SUBJECT, message).await?;
+        Ok(id)
+    }
+
     pub async fn publish_event(&self, event_type: &str, message: Message) -> Result<(), MessagingError> {
         let subject = format!("events.{}", event_type);
         self.publish(&subject, message).await
@@ -29,4 +122,123 @@ impl Publisher {
         let subject = format!("commands.{}", command_type);
         self.publish(&subject, message).await
     }
-}
\ No newline at end of file
+
+    /// Publishes `message` on `subject` once `deliver_at` has passed and
+    /// returns the id to cancel it with.
+    pub async fn publish_at(&self, subject: &str, message: Message, deliver_at: OffsetDateTime) -> Result<uuid::Uuid, MessagingError> {
+        // Stamp now so the delayed message continues the trace that scheduled it
+        let message = stamp_outgoing(message);
+        self.check_schema(&message).await?;
+        let id = self.scheduler()?.schedule(subject, &message, deliver_at).await?;
+        info!("Scheduled message {} for subject {} at {}", id, subject, deliver_at);
+        Ok(id)
+    }
+
+    /// Publishes `message` on `subject` after `delay`, measured on the
+    /// scheduler's clock.
+    pub async fn publish_after(&self, subject: &str, message: Message, delay: Duration) -> Result<uuid::Uuid, MessagingError> {
+        let deliver_at = self.scheduler()?.now() + delay;
+        self.publish_at(subject, message, deliver_at).await
+    }
+
+    /// Cancels a message scheduled with [`Publisher::publish_at`]. Returns
+    /// `false` if it already went out or was cancelled before.
+    pub async fn cancel_scheduled(&self, id: uuid::Uuid) -> Result<bool, MessagingError> {
+        self.scheduler()?.cancel(id).await
+    }
+
+    /// Validates `message` against the registry when validation is on.
+    async fn check_schema(&self, message: &Message) -> Result<(), MessagingError> {
+        match self.schemas.as_ref().filter(|_| self.validate_schemas) {
+            Some(registry) => registry.validate_message(message).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Encrypts, then signs, so the signature covers the ciphertext and can
+    /// be checked before anything is decrypted.
+    fn seal(&self, message: Message) -> Result<Message, MessagingError> {
+        let message = match &self.encryption {
+            Some(cipher) => cipher.encrypt(message)?,
+            None => message,
+        };
+        match &self.signer {
+            Some(signer) => signer.sign(message),
+            None => Ok(message),
+        }
+    }
+
+    /// Stamps, seals and encodes `message` the way everything this publisher
+    /// sends goes out, replies from [`crate::Subscriber::serve`] included.
+    pub(crate) fn encode(&self, message: Message) -> Result<EncodedMessage, MessagingError> {
+        let message = self.seal(stamp_outgoing(message))?;
+        encode_message(&message, self.codec.as_ref(), self.compression)
+    }
+
+    /// Verifies and decrypts a reply, in the reverse order of [`Self::seal`].
+    pub(crate) fn open(&self, message: Message) -> Result<Message, MessagingError> {
+        if let Some(keys) = &self.trusted_keys {
+            keys.verify(&message)?;
+        }
+        match &self.encryption {
+            Some(cipher) => cipher.decrypt(message),
+            None => Ok(message),
+        }
+    }
+
+    /// Sends a reply built by [`crate::Subscriber::serve`] to the requester's
+    /// inbox.
+    pub(crate) async fn reply(&self, reply_to: async_nats::Subject, message: Message) -> Result<(), MessagingError> {
+        self.check_schema(&message).await?;
+        let (headers, payload) = self.encode(message)?;
+        self.client
+            .publish_with_headers(reply_to, headers, payload.into())
+            .await
+            .map_err(|e| MessagingError::PublishError(e.to_string()))
+    }
+
+    fn scheduler(&self) -> Result<&Scheduler, MessagingError> {
+        self.scheduler
+            .as_ref()
+            .ok_or_else(|| MessagingError::SchedulerError("Publisher has no scheduler configured".to_string()))
+    }
+
+    /// Sends `request` to whoever serves `subject` and waits up to `timeout`
+    /// for the reply.
+    ///
+    /// Fails with [`MessagingError::NoResponders`] when nobody is subscribed,
+    /// [`MessagingError::Timeout`] when the deadline passes, and
+    /// [`MessagingError::Remote`] when the handler returned an error.
+    pub async fn request<Req, Resp>(
+        &self,
+        subject: &str,
+        source: &str,
+        request: &Req,
+        timeout: Duration,
+    ) -> Result<Resp, MessagingError>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+    {
+        let message = build_request(subject, source, request, timeout)?;
+        let reply = self.send_request(subject, &message, timeout).await?;
+        parse_reply(&message, reply)
+    }
+
+    /// Untyped form of [`Publisher::request`], returning the raw reply
+    /// envelope once its signature is verified and its payload decrypted.
+    pub async fn send_request(&self, subject: &str, message: &Message, timeout: Duration) -> Result<Message, MessagingError> {
+        self.check_schema(message).await?;
+        let id = message.id;
+        let (headers, payload) = self.encode(with_deadline(message.clone(), timeout))?;
+        let request = async_nats::Request::new()
+            .payload(payload.into())
+            .headers(headers)
+            .timeout(Some(timeout));
+
+        let response = self.client.send_request(subject.to_string(), request).await
+            .map_err(|e| map_request_error(subject, e))?;
+        info!("Received reply to request {} on subject {}", id, subject);
+        self.open(decode_message(response.headers.as_ref(), &response.payload)?)
+    }
+}
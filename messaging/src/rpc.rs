@@ -0,0 +1,171 @@
+use crate::{Message, MessagingError};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// Header carrying the caller's deadline as Unix milliseconds.
+pub const DEADLINE_HEADER: &str = "rpc-deadline";
+
+/// Default deadline for requests when the caller has no better idea.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An error returned by a remote handler, carried back to the caller.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    /// Machine-readable error code such as `not_found` or `validation`.
+    pub code: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+impl RpcError {
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            code: code.into(),
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new("bad_request", message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new("internal", message)
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+/// Payload of a reply message.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RpcReply<T> {
+    Ok { body: T },
+    Error { error: RpcError },
+}
+
+impl<T> From<Result<T, RpcError>> for RpcReply<T> {
+    fn from(result: Result<T, RpcError>) -> Self {
+        match result {
+            Ok(body) => RpcReply::Ok { body },
+            Err(error) => RpcReply::Error { error },
+        }
+    }
+}
+
+/// Builds the request envelope for `request` sent to `subject`.
+///
+/// The request's own id doubles as its correlation id unless the caller is
+/// already part of a larger flow.
+pub fn build_request<Req: Serialize>(
+    subject: &str,
+    source: &str,
+    request: &Req,
+    timeout: Duration,
+) -> Result<Message, MessagingError> {
+    let mut message = Message::new(
+        subject.to_string(),
+        source.to_string(),
+        subject.to_string(),
+        serde_json::to_value(request)?,
+    );
+    message.correlation_id = Some(message.id);
+    Ok(with_deadline(message, timeout))
+}
+
+/// Stamps the deadline header on `message` unless it already has one.
+pub fn with_deadline(mut message: Message, timeout: Duration) -> Message {
+    if !message.headers.contains_key(DEADLINE_HEADER) {
+        let deadline = OffsetDateTime::now_utc() + timeout;
+        let millis = deadline.unix_timestamp_nanos() / 1_000_000;
+        message.headers.insert(DEADLINE_HEADER.to_string(), millis.to_string());
+    }
+    message
+}
+
+/// Whether the caller's deadline has already passed, so replying is pointless.
+pub fn deadline_expired(message: &Message) -> bool {
+    message
+        .headers
+        .get(DEADLINE_HEADER)
+        .and_then(|deadline| deadline.parse::<i128>().ok())
+        .map(|deadline| OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000 > deadline)
+        .unwrap_or(false)
+}
+
+/// Builds the reply to `request`, linked through its correlation and causation ids.
+pub fn build_reply<Resp: Serialize>(
+    request: &Message,
+    result: Result<Resp, RpcError>,
+) -> Result<Message, MessagingError> {
+    let payload = serde_json::to_value(RpcReply::from(result))?;
+    let mut reply = Message::new(
+        format!("{}.reply", request.message_type),
+        request.destination.clone(),
+        request.source.clone(),
+        payload,
+    )
+    .with_causation(request.id);
+    reply.correlation_id = request.correlation_id.or(Some(request.id));
+    Ok(reply)
+}
+
+/// Unpacks a reply to `request`, turning remote errors into [`MessagingError::Remote`].
+pub fn parse_reply<Resp: DeserializeOwned>(request: &Message, reply: Message) -> Result<Resp, MessagingError> {
+    let expected = request.correlation_id.or(Some(request.id));
+    if reply.correlation_id != expected {
+        return Err(MessagingError::PublishError(format!(
+            "Reply correlation id {:?} does not match request {}",
+            reply.correlation_id, request.id
+        )));
+    }
+
+    match serde_json::from_value(reply.payload)? {
+        RpcReply::Ok { body } => Ok(body),
+        RpcReply::Error { error } => Err(MessagingError::Remote(error)),
+    }
+}
+
+/// Runs `handler` for one request and builds the reply.
+///
+/// Returns `None` when the caller's deadline has already passed. Payloads
+/// that do not deserialize as `Req` are answered with a `bad_request` error.
+pub async fn handle_request<Req, Resp, F, Fut>(request: Message, handler: &F) -> Result<Option<Message>, MessagingError>
+where
+    Req: DeserializeOwned,
+    Resp: Serialize,
+    F: Fn(Req, Message) -> Fut,
+    Fut: std::future::Future<Output = Result<Resp, RpcError>>,
+{
+    if deadline_expired(&request) {
+        tracing::warn!("Dropping request {} on {}: deadline passed", request.id, request.destination);
+        return Ok(None);
+    }
+
+    let result = match serde_json::from_value::<Req>(request.payload.clone()) {
+        Ok(body) => handler(body, request.clone()).await,
+        Err(e) => Err(RpcError::bad_request(e.to_string())),
+    };
+    build_reply(&request, result).map(Some)
+}
+
+/// Maps NATS request failures onto the caller-facing errors.
+pub fn map_request_error(subject: &str, error: async_nats::RequestError) -> MessagingError {
+    match error.kind() {
+        async_nats::RequestErrorKind::TimedOut => MessagingError::Timeout(subject.to_string()),
+        async_nats::RequestErrorKind::NoResponders => MessagingError::NoResponders(subject.to_string()),
+        async_nats::RequestErrorKind::Other => MessagingError::NatsError(Box::new(error)),
+    }
+}
@@ -0,0 +1,514 @@
+use crate::{build_request, parse_reply, MessagingError, Publisher, DEFAULT_REQUEST_TIMEOUT};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use shared::db::{execute_all, from_timestamp, to_timestamp, try_get_optional};
+use sqlx::{AnyPool, Row};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SagaState {
+    Running,
+    Compensating,
+    Completed,
+    /// A step failed and every completed step was undone.
+    Compensated,
+    /// A compensation failed too; the saga needs manual attention.
+    Failed,
+}
+
+impl SagaState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SagaState::Running => "running",
+            SagaState::Compensating => "compensating",
+            SagaState::Completed => "completed",
+            SagaState::Compensated => "compensated",
+            SagaState::Failed => "failed",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, MessagingError> {
+        Ok(match value {
+            "running" => SagaState::Running,
+            "compensating" => SagaState::Compensating,
+            "completed" => SagaState::Completed,
+            "compensated" => SagaState::Compensated,
+            "failed" => SagaState::Failed,
+            other => return Err(MessagingError::SagaError(format!("Unknown saga state {}", other))),
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, SagaState::Completed | SagaState::Compensated | SagaState::Failed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepState {
+    Pending,
+    Completed,
+    Failed,
+    Compensated,
+    CompensationFailed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepRecord {
+    pub name: String,
+    pub state: StepState,
+    pub attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Persisted progress of one saga instance, keyed by its correlation id.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SagaStatus {
+    pub correlation_id: Uuid,
+    pub saga_type: String,
+    pub state: SagaState,
+    pub input: Value,
+    pub steps: Vec<StepRecord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+}
+
+/// What a step sees: the saga input plus the outputs of earlier steps.
+#[derive(Debug, Clone)]
+pub struct SagaContext {
+    pub correlation_id: Uuid,
+    pub input: Value,
+    pub outputs: HashMap<String, Value>,
+}
+
+impl SagaContext {
+    pub fn output(&self, step: &str) -> Option<&Value> {
+        self.outputs.get(step)
+    }
+}
+
+/// One forward action of a saga and the action that undoes it.
+///
+/// Both may run more than once because of retries and restarts, so they must
+/// be idempotent.
+#[async_trait]
+pub trait SagaStep: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn execute(&self, ctx: &SagaContext) -> Result<Value, MessagingError>;
+
+    async fn compensate(&self, _ctx: &SagaContext) -> Result<(), MessagingError> {
+        Ok(())
+    }
+}
+
+/// Attempts, per-attempt timeout and backoff for a step and its compensation.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub timeout: Duration,
+    /// Delay before the second attempt, doubled for each one after.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            backoff: Duration::from_millis(200),
+        }
+    }
+}
+
+/// Whether an error is worth retrying. Errors returned by the remote handler
+/// are business decisions, such as a declined payment, and are final.
+pub fn is_retryable(error: &MessagingError) -> bool {
+    matches!(
+        error,
+        MessagingError::Timeout(_) | MessagingError::NoResponders(_) | MessagingError::NatsError(_)
+    )
+}
+
+/// A step carried out by sending a command over request/reply.
+///
+/// The command payload is the saga input; the compensation payload is
+/// `{"input": ..., "result": ...}` with the reply to the forward command.
+pub struct CommandStep {
+    name: String,
+    publisher: Arc<Publisher>,
+    subject: String,
+    compensation_subject: Option<String>,
+    timeout: Duration,
+}
+
+impl CommandStep {
+    pub fn new(name: &str, publisher: Arc<Publisher>, subject: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            publisher,
+            subject: subject.to_string(),
+            compensation_subject: None,
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+        }
+    }
+
+    pub fn compensate_with(mut self, subject: &str) -> Self {
+        self.compensation_subject = Some(subject.to_string());
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn send(&self, subject: &str, ctx: &SagaContext, payload: &Value) -> Result<Value, MessagingError> {
+        let mut request = build_request(subject, &self.name, payload, self.timeout)?;
+        request.correlation_id = Some(ctx.correlation_id);
+        let reply = self.publisher.send_request(subject, &request, self.timeout).await?;
+        parse_reply(&request, reply)
+    }
+}
+
+#[async_trait]
+impl SagaStep for CommandStep {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn execute(&self, ctx: &SagaContext) -> Result<Value, MessagingError> {
+        self.send(&self.subject, ctx, &ctx.input).await
+    }
+
+    async fn compensate(&self, ctx: &SagaContext) -> Result<(), MessagingError> {
+        let Some(subject) = &self.compensation_subject else {
+            return Ok(());
+        };
+        let payload = json!({ "input": ctx.input, "result": ctx.output(&self.name) });
+        self.send(subject, ctx, &payload).await.map(|_: Value| ())
+    }
+}
+
+/// The ordered steps of a saga type.
+pub struct SagaDefinition {
+    name: String,
+    steps: Vec<(Arc<dyn SagaStep>, RetryPolicy)>,
+}
+
+impl SagaDefinition {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn step(self, step: impl SagaStep + 'static) -> Self {
+        self.step_with(step, RetryPolicy::default())
+    }
+
+    pub fn step_with(mut self, step: impl SagaStep + 'static, retry: RetryPolicy) -> Self {
+        self.steps.push((Arc::new(step), retry));
+        self
+    }
+}
+
+const SCHEMA: &[&str] = &["CREATE TABLE IF NOT EXISTS sagas (
+        correlation_id TEXT PRIMARY KEY,
+        saga_type TEXT NOT NULL,
+        state TEXT NOT NULL,
+        input TEXT NOT NULL,
+        steps TEXT NOT NULL,
+        error TEXT,
+        created_at DOUBLE PRECISION NOT NULL,
+        updated_at DOUBLE PRECISION NOT NULL
+    )"];
+
+/// sqlx-backed storage for [`SagaStatus`], on SQLite or Postgres.
+#[derive(Clone)]
+pub struct SagaStore {
+    pool: AnyPool,
+}
+
+impl SagaStore {
+    pub fn new(pool: AnyPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn init_schema(&self) -> Result<(), MessagingError> {
+        Ok(execute_all(&self.pool, SCHEMA).await?)
+    }
+
+    pub async fn insert(&self, saga: &SagaStatus) -> Result<(), MessagingError> {
+        sqlx::query(
+            "INSERT INTO sagas (correlation_id, saga_type, state, input, steps, error, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(saga.correlation_id.to_string())
+        .bind(&saga.saga_type)
+        .bind(saga.state.as_str())
+        .bind(serde_json::to_string(&saga.input)?)
+        .bind(serde_json::to_string(&saga.steps)?)
+        .bind(saga.error.clone())
+        .bind(to_timestamp(saga.created_at))
+        .bind(to_timestamp(saga.updated_at))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn update(&self, saga: &SagaStatus) -> Result<(), MessagingError> {
+        sqlx::query("UPDATE sagas SET state = $1, steps = $2, error = $3, updated_at = $4 WHERE correlation_id = $5")
+            .bind(saga.state.as_str())
+            .bind(serde_json::to_string(&saga.steps)?)
+            .bind(saga.error.clone())
+            .bind(to_timestamp(saga.updated_at))
+            .bind(saga.correlation_id.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get(&self, correlation_id: Uuid) -> Result<Option<SagaStatus>, MessagingError> {
+        let row = sqlx::query("SELECT * FROM sagas WHERE correlation_id = $1")
+            .bind(correlation_id.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(|row| Self::from_row(&row)).transpose()
+    }
+
+    /// Sagas of `saga_type` that were interrupted before finishing.
+    pub async fn unfinished(&self, saga_type: &str) -> Result<Vec<SagaStatus>, MessagingError> {
+        let rows = sqlx::query(
+            "SELECT * FROM sagas WHERE saga_type = $1 AND state IN ('running', 'compensating') ORDER BY created_at",
+        )
+        .bind(saga_type)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter().map(Self::from_row).collect()
+    }
+
+    fn from_row(row: &sqlx::any::AnyRow) -> Result<SagaStatus, MessagingError> {
+        let correlation_id: String = row.try_get("correlation_id")?;
+        let state: String = row.try_get("state")?;
+        let input: String = row.try_get("input")?;
+        let steps: String = row.try_get("steps")?;
+        Ok(SagaStatus {
+            correlation_id: Uuid::parse_str(&correlation_id).map_err(|e| MessagingError::SagaError(e.to_string()))?,
+            saga_type: row.try_get("saga_type")?,
+            state: SagaState::parse(&state)?,
+            input: serde_json::from_str(&input)?,
+            steps: serde_json::from_str(&steps)?,
+            error: try_get_optional(row, "error")?,
+            created_at: from_timestamp(row.try_get("created_at")?),
+            updated_at: from_timestamp(row.try_get("updated_at")?),
+        })
+    }
+}
+
+/// Runs sagas of one [`SagaDefinition`], persisting progress after every step
+/// so an interrupted saga can be resumed with [`SagaOrchestrator::resume_unfinished`].
+#[derive(Clone)]
+pub struct SagaOrchestrator {
+    definition: Arc<SagaDefinition>,
+    store: SagaStore,
+}
+
+impl SagaOrchestrator {
+    pub fn new(definition: SagaDefinition, store: SagaStore) -> Self {
+        Self {
+            definition: Arc::new(definition),
+            store,
+        }
+    }
+
+    pub fn store(&self) -> &SagaStore {
+        &self.store
+    }
+
+    /// Records a new saga and runs it in the background.
+    pub async fn start(&self, correlation_id: Uuid, input: Value) -> Result<SagaStatus, MessagingError> {
+        let saga = self.create(correlation_id, input).await?;
+        let orchestrator = self.clone();
+        let running = saga.clone();
+        tokio::spawn(async move {
+            if let Err(e) = orchestrator.run(running).await {
+                tracing::error!("Saga {} stopped: {}", correlation_id, e);
+            }
+        });
+        Ok(saga)
+    }
+
+    /// Records a new saga and runs it to the end.
+    pub async fn execute(&self, correlation_id: Uuid, input: Value) -> Result<SagaStatus, MessagingError> {
+        let saga = self.create(correlation_id, input).await?;
+        self.run(saga).await
+    }
+
+    pub async fn status(&self, correlation_id: Uuid) -> Result<Option<SagaStatus>, MessagingError> {
+        self.store.get(correlation_id).await
+    }
+
+    /// Picks up sagas left running or compensating by a previous process.
+    pub async fn resume_unfinished(&self) -> Result<Vec<SagaStatus>, MessagingError> {
+        let mut resumed = Vec::new();
+        for saga in self.store.unfinished(&self.definition.name).await? {
+            tracing::info!("Resuming saga {} ({:?})", saga.correlation_id, saga.state);
+            resumed.push(self.run(saga).await?);
+        }
+        Ok(resumed)
+    }
+
+    async fn create(&self, correlation_id: Uuid, input: Value) -> Result<SagaStatus, MessagingError> {
+        let now = OffsetDateTime::now_utc();
+        let saga = SagaStatus {
+            correlation_id,
+            saga_type: self.definition.name.clone(),
+            state: SagaState::Running,
+            input,
+            steps: self
+                .definition
+                .steps
+                .iter()
+                .map(|(step, _)| StepRecord {
+                    name: step.name().to_string(),
+                    state: StepState::Pending,
+                    attempts: 0,
+                    output: None,
+                    error: None,
+                })
+                .collect(),
+            error: None,
+            created_at: now,
+            updated_at: now,
+        };
+        self.store.insert(&saga).await?;
+        Ok(saga)
+    }
+
+    async fn save(&self, saga: &mut SagaStatus) -> Result<(), MessagingError> {
+        saga.updated_at = OffsetDateTime::now_utc();
+        self.store.update(saga).await
+    }
+
+    fn context(saga: &SagaStatus) -> SagaContext {
+        SagaContext {
+            correlation_id: saga.correlation_id,
+            input: saga.input.clone(),
+            outputs: saga
+                .steps
+                .iter()
+                .filter_map(|step| step.output.clone().map(|output| (step.name.clone(), output)))
+                .collect(),
+        }
+    }
+
+    async fn run(&self, mut saga: SagaStatus) -> Result<SagaStatus, MessagingError> {
+        if saga.state == SagaState::Running {
+            for index in 0..self.definition.steps.len() {
+                if saga.steps[index].state == StepState::Completed {
+                    continue;
+                }
+                let (step, retry) = &self.definition.steps[index];
+                let ctx = Self::context(&saga);
+                let (result, attempts) = attempt(*retry, || step.execute(&ctx)).await;
+
+                let record = &mut saga.steps[index];
+                record.attempts += attempts;
+                match result {
+                    Ok(output) => {
+                        record.state = StepState::Completed;
+                        record.output = Some(output);
+                        self.save(&mut saga).await?;
+                    }
+                    Err(e) => {
+                        tracing::warn!("Saga {} step {} failed: {}", saga.correlation_id, step.name(), e);
+                        record.state = StepState::Failed;
+                        record.error = Some(e.to_string());
+                        saga.error = Some(format!("{} failed: {}", step.name(), e));
+                        saga.state = SagaState::Compensating;
+                        self.save(&mut saga).await?;
+                        break;
+                    }
+                }
+            }
+            if saga.state == SagaState::Running {
+                saga.state = SagaState::Completed;
+                self.save(&mut saga).await?;
+            }
+        }
+
+        if saga.state == SagaState::Compensating {
+            let mut all_undone = true;
+            for index in (0..self.definition.steps.len()).rev() {
+                if saga.steps[index].state != StepState::Completed {
+                    all_undone &= saga.steps[index].state != StepState::CompensationFailed;
+                    continue;
+                }
+                let (step, retry) = &self.definition.steps[index];
+                let ctx = Self::context(&saga);
+                let (result, _) = attempt(*retry, || step.compensate(&ctx)).await;
+
+                let record = &mut saga.steps[index];
+                match result {
+                    Ok(()) => record.state = StepState::Compensated,
+                    Err(e) => {
+                        tracing::error!("Saga {} could not undo {}: {}", saga.correlation_id, step.name(), e);
+                        record.state = StepState::CompensationFailed;
+                        record.error = Some(e.to_string());
+                        all_undone = false;
+                    }
+                }
+                self.save(&mut saga).await?;
+            }
+            saga.state = if all_undone { SagaState::Compensated } else { SagaState::Failed };
+            self.save(&mut saga).await?;
+        }
+
+        Ok(saga)
+    }
+}
+
+/// Calls `action` until it succeeds, fails with a non-retryable error or runs
+/// out of attempts, returning the last result and the number of attempts.
+async fn attempt<T, F, Fut>(retry: RetryPolicy, mut action: F) -> (Result<T, MessagingError>, u32)
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, MessagingError>>,
+{
+    let mut backoff = retry.backoff;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let result = match tokio::time::timeout(retry.timeout, action()).await {
+            Ok(result) => result,
+            Err(_) => Err(MessagingError::Timeout(format!("step attempt after {:?}", retry.timeout))),
+        };
+        match result {
+            Err(e) if is_retryable(&e) && attempts < retry.max_attempts.max(1) => {
+                tracing::debug!("Retrying after {:?}: {}", backoff, e);
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            result => return (result, attempts),
+        }
+    }
+}
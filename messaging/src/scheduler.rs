@@ -0,0 +1,328 @@
+use crate::{Message, MessagingError, Publisher};
+use serde::{Deserialize, Serialize};
+use shared::db::{execute_all, from_timestamp, to_timestamp, try_get_optional};
+use sqlx::{AnyConnection, AnyPool, Row};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Source of the current time for the scheduler, so tests can move it by hand.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> OffsetDateTime;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+}
+
+/// A clock that only moves when told to.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<OffsetDateTime>>,
+}
+
+impl ManualClock {
+    pub fn new(now: OffsetDateTime) -> Self {
+        Self { now: Arc::new(Mutex::new(now)) }
+    }
+
+    pub fn set(&self, now: OffsetDateTime) {
+        *self.now.lock().expect("clock lock poisoned") = now;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().expect("clock lock poisoned") += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> OffsetDateTime {
+        *self.now.lock().expect("clock lock poisoned")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleState {
+    Pending,
+    Delivered,
+    Cancelled,
+}
+
+impl ScheduleState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ScheduleState::Pending => "pending",
+            ScheduleState::Delivered => "delivered",
+            ScheduleState::Cancelled => "cancelled",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, MessagingError> {
+        Ok(match value {
+            "pending" => ScheduleState::Pending,
+            "delivered" => ScheduleState::Delivered,
+            "cancelled" => ScheduleState::Cancelled,
+            other => return Err(MessagingError::SchedulerError(format!("Unknown schedule state {}", other))),
+        })
+    }
+}
+
+/// A message waiting in the scheduler table, keyed by the message id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledMessage {
+    pub id: Uuid,
+    pub subject: String,
+    pub message: Message,
+    pub state: ScheduleState,
+    #[serde(with = "time::serde::rfc3339")]
+    pub deliver_at: OffsetDateTime,
+    pub attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS scheduled_messages (
+        id TEXT PRIMARY KEY,
+        subject TEXT NOT NULL,
+        message TEXT NOT NULL,
+        state TEXT NOT NULL,
+        deliver_at DOUBLE PRECISION NOT NULL,
+        claimed_until DOUBLE PRECISION NOT NULL DEFAULT 0,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        last_error TEXT,
+        created_at DOUBLE PRECISION NOT NULL,
+        updated_at DOUBLE PRECISION NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS scheduled_messages_due ON scheduled_messages (state, deliver_at)",
+];
+
+/// How long a dispatcher owns a due message before another may retry it.
+pub const DEFAULT_CLAIM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Durable table of messages to publish later.
+///
+/// Delivery is at-least-once: a dispatcher that crashes after publishing but
+/// before recording the delivery leaves the message to be sent again once its
+/// claim expires, with the same message id.
+#[derive(Clone)]
+pub struct Scheduler {
+    pool: AnyPool,
+    clock: Arc<dyn Clock>,
+    claim_timeout: Duration,
+    batch_size: i64,
+}
+
+impl Scheduler {
+    pub fn new(pool: AnyPool) -> Self {
+        Self {
+            pool,
+            clock: Arc::new(SystemClock),
+            claim_timeout: DEFAULT_CLAIM_TIMEOUT,
+            batch_size: 100,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Also the delay before a failed delivery is retried.
+    pub fn with_claim_timeout(mut self, timeout: Duration) -> Self {
+        self.claim_timeout = timeout;
+        self
+    }
+
+    pub fn with_batch_size(mut self, size: i64) -> Self {
+        self.batch_size = size.max(1);
+        self
+    }
+
+    pub fn now(&self) -> OffsetDateTime {
+        self.clock.now()
+    }
+
+    pub async fn init_schema(&self) -> Result<(), MessagingError> {
+        Ok(execute_all(&self.pool, SCHEMA).await?)
+    }
+
+    /// Stores `message` for delivery on `subject` at `deliver_at` and returns
+    /// the id to cancel it with, which is the message id.
+    pub async fn schedule(&self, subject: &str, message: &Message, deliver_at: OffsetDateTime) -> Result<Uuid, MessagingError> {
+        let mut conn = self.pool.acquire().await?;
+        self.schedule_on(&mut conn, subject, message, deliver_at).await
+    }
+
+    /// [`Self::schedule`] on `conn`, so that a message announcing a change can
+    /// be stored in the change's own transaction and relayed by [`Self::run`]
+    /// once it commits: an outbox.
+    pub async fn schedule_on(
+        &self,
+        conn: &mut AnyConnection,
+        subject: &str,
+        message: &Message,
+        deliver_at: OffsetDateTime,
+    ) -> Result<Uuid, MessagingError> {
+        let now = to_timestamp(self.now());
+        sqlx::query(
+            "INSERT INTO scheduled_messages (id, subject, message, state, deliver_at, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(message.id.to_string())
+        .bind(subject)
+        .bind(serde_json::to_string(message)?)
+        .bind(ScheduleState::Pending.as_str())
+        .bind(to_timestamp(deliver_at))
+        .bind(now)
+        .bind(now)
+        .execute(conn)
+        .await?;
+        Ok(message.id)
+    }
+
+    /// Cancels a pending message. Returns `false` if it was already delivered,
+    /// cancelled or never scheduled.
+    pub async fn cancel(&self, id: Uuid) -> Result<bool, MessagingError> {
+        let result = sqlx::query("UPDATE scheduled_messages SET state = $1, updated_at = $2 WHERE id = $3 AND state = $4")
+            .bind(ScheduleState::Cancelled.as_str())
+            .bind(to_timestamp(self.now()))
+            .bind(id.to_string())
+            .bind(ScheduleState::Pending.as_str())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Option<ScheduledMessage>, MessagingError> {
+        let row = sqlx::query("SELECT * FROM scheduled_messages WHERE id = $1")
+            .bind(id.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(|row| Self::from_row(&row)).transpose()
+    }
+
+    /// Pending messages whose time has come and that no dispatcher holds.
+    pub async fn due(&self) -> Result<Vec<ScheduledMessage>, MessagingError> {
+        let now = to_timestamp(self.now());
+        let rows = sqlx::query(
+            "SELECT * FROM scheduled_messages
+             WHERE state = $1 AND deliver_at <= $2 AND claimed_until <= $3
+             ORDER BY deliver_at LIMIT $4",
+        )
+        .bind(ScheduleState::Pending.as_str())
+        .bind(now)
+        .bind(now)
+        .bind(self.batch_size)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter().map(Self::from_row).collect()
+    }
+
+    /// Hands every due message to `deliver` and returns how many were delivered.
+    ///
+    /// Each message is claimed first so that several dispatchers can share the
+    /// table. A failed delivery stays pending and is retried after the claim
+    /// timeout.
+    pub async fn dispatch_due<F, Fut>(&self, deliver: F) -> Result<usize, MessagingError>
+    where
+        F: Fn(String, Message) -> Fut,
+        Fut: std::future::Future<Output = Result<(), MessagingError>>,
+    {
+        let mut delivered = 0;
+        for scheduled in self.due().await? {
+            if !self.claim(scheduled.id).await? {
+                continue;
+            }
+            match deliver(scheduled.subject.clone(), scheduled.message).await {
+                Ok(()) => {
+                    self.finish(scheduled.id, ScheduleState::Delivered, None).await?;
+                    delivered += 1;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to deliver scheduled message {}: {}", scheduled.id, e);
+                    self.finish(scheduled.id, ScheduleState::Pending, Some(e.to_string())).await?;
+                }
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Publishes due messages through `publisher` every `poll` until the task
+    /// is dropped. Messages scheduled before a restart are picked up on the
+    /// first pass.
+    pub async fn run(&self, publisher: Arc<Publisher>, poll: Duration) {
+        loop {
+            let result = self
+                .dispatch_due(|subject, message| {
+                    let publisher = publisher.clone();
+                    async move { publisher.publish(&subject, message).await }
+                })
+                .await;
+            if let Err(e) = result {
+                tracing::error!("Scheduled message dispatch failed: {}", e);
+            }
+            tokio::time::sleep(poll).await;
+        }
+    }
+
+    async fn claim(&self, id: Uuid) -> Result<bool, MessagingError> {
+        let now = self.now();
+        let result = sqlx::query(
+            "UPDATE scheduled_messages SET claimed_until = $1, updated_at = $2
+             WHERE id = $3 AND state = $4 AND claimed_until <= $5",
+        )
+        .bind(to_timestamp(now + self.claim_timeout))
+        .bind(to_timestamp(now))
+        .bind(id.to_string())
+        .bind(ScheduleState::Pending.as_str())
+        .bind(to_timestamp(now))
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Records the outcome of a claimed delivery. A failure keeps the claim
+    /// until it expires, which doubles as the retry delay.
+    async fn finish(&self, id: Uuid, state: ScheduleState, error: Option<String>) -> Result<(), MessagingError> {
+        let attempts = if error.is_some() { "attempts + 1" } else { "attempts" };
+        let sql = format!(
+            "UPDATE scheduled_messages SET state = $1, attempts = {}, last_error = $2, updated_at = $3 WHERE id = $4",
+            attempts
+        );
+        sqlx::query(&sql)
+            .bind(state.as_str())
+            .bind(error)
+            .bind(to_timestamp(self.now()))
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    fn from_row(row: &sqlx::any::AnyRow) -> Result<ScheduledMessage, MessagingError> {
+        let id: String = row.try_get("id")?;
+        let state: String = row.try_get("state")?;
+        let message: String = row.try_get("message")?;
+        let attempts: i32 = row.try_get("attempts")?;
+        Ok(ScheduledMessage {
+            id: Uuid::parse_str(&id).map_err(|e| MessagingError::SchedulerError(e.to_string()))?,
+            subject: row.try_get("subject")?,
+            message: serde_json::from_str(&message)?,
+            state: ScheduleState::parse(&state)?,
+            deliver_at: from_timestamp(row.try_get("deliver_at")?),
+            attempts: attempts as u32,
+            last_error: try_get_optional(row, "last_error")?,
+            created_at: from_timestamp(row.try_get("created_at")?),
+        })
+    }
+}
@@ -0,0 +1,383 @@
+use crate::{Event, Message, MessagingError};
+use async_trait::async_trait;
+use jsonschema::JSONSchema;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Compatibility rule enforced when a new schema version is registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compatibility {
+    /// Any change is accepted.
+    None,
+    /// Consumers on the new version can read events written with the previous one.
+    #[default]
+    Backward,
+    /// Consumers on the previous version can read events written with the new one.
+    Forward,
+    /// Both backward and forward compatible.
+    Full,
+}
+
+/// Storage backend for JSON Schemas, keyed by event type and version.
+#[async_trait]
+pub trait SchemaStore: Send + Sync {
+    async fn versions(&self, event_type: &str) -> Result<Vec<u32>, MessagingError>;
+
+    async fn get(&self, event_type: &str, version: u32) -> Result<Option<Value>, MessagingError>;
+
+    async fn put(&self, event_type: &str, version: u32, schema: &Value) -> Result<(), MessagingError>;
+}
+
+/// Schemas stored as `<root>/<event_type>/v<version>.json`.
+pub struct FileSchemaStore {
+    root: PathBuf,
+}
+
+impl FileSchemaStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, event_type: &str, version: u32) -> PathBuf {
+        schema_path(&self.root, event_type, version)
+    }
+}
+
+fn schema_path(root: &Path, event_type: &str, version: u32) -> PathBuf {
+    root.join(event_type).join(format!("v{}.json", version))
+}
+
+#[async_trait]
+impl SchemaStore for FileSchemaStore {
+    async fn versions(&self, event_type: &str) -> Result<Vec<u32>, MessagingError> {
+        let mut entries = match tokio::fs::read_dir(self.root.join(event_type)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(MessagingError::SchemaError(e.to_string())),
+        };
+
+        let mut versions = Vec::new();
+        while let Some(entry) = entries.next_entry().await
+            .map_err(|e| MessagingError::SchemaError(e.to_string()))?
+        {
+            let name = entry.file_name();
+            let version = name.to_str()
+                .and_then(|name| name.strip_prefix('v'))
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|version| version.parse().ok());
+            if let Some(version) = version {
+                versions.push(version);
+            }
+        }
+        versions.sort_unstable();
+        Ok(versions)
+    }
+
+    async fn get(&self, event_type: &str, version: u32) -> Result<Option<Value>, MessagingError> {
+        match tokio::fs::read(self.path(event_type, version)).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(MessagingError::SchemaError(e.to_string())),
+        }
+    }
+
+    async fn put(&self, event_type: &str, version: u32, schema: &Value) -> Result<(), MessagingError> {
+        let path = self.path(event_type, version);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await
+                .map_err(|e| MessagingError::SchemaError(e.to_string()))?;
+        }
+        tokio::fs::write(path, serde_json::to_vec_pretty(schema)?).await
+            .map_err(|e| MessagingError::SchemaError(e.to_string()))
+    }
+}
+
+/// Client for a registry service exposing
+/// `GET /schemas/{type}/versions`, and `GET`/`PUT /schemas/{type}/versions/{version}`.
+pub struct HttpSchemaStore {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl HttpSchemaStore {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+fn http_error(e: reqwest::Error) -> MessagingError {
+    MessagingError::SchemaError(e.to_string())
+}
+
+#[async_trait]
+impl SchemaStore for HttpSchemaStore {
+    async fn versions(&self, event_type: &str) -> Result<Vec<u32>, MessagingError> {
+        let url = format!("{}/schemas/{}/versions", self.base_url, event_type);
+        let response = self.client.get(&url).send().await.map_err(http_error)?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        let mut versions: Vec<u32> = response.error_for_status().map_err(http_error)?
+            .json().await.map_err(http_error)?;
+        versions.sort_unstable();
+        Ok(versions)
+    }
+
+    async fn get(&self, event_type: &str, version: u32) -> Result<Option<Value>, MessagingError> {
+        let url = format!("{}/schemas/{}/versions/{}", self.base_url, event_type, version);
+        let response = self.client.get(&url).send().await.map_err(http_error)?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let schema = response.error_for_status().map_err(http_error)?
+            .json().await.map_err(http_error)?;
+        Ok(Some(schema))
+    }
+
+    async fn put(&self, event_type: &str, version: u32, schema: &Value) -> Result<(), MessagingError> {
+        let url = format!("{}/schemas/{}/versions/{}", self.base_url, event_type, version);
+        self.client.put(&url).json(schema).send().await.map_err(http_error)?
+            .error_for_status().map_err(http_error)?;
+        Ok(())
+    }
+}
+
+/// Versioned JSON Schemas for event payloads.
+pub struct SchemaRegistry {
+    store: Box<dyn SchemaStore>,
+    compatibility: Compatibility,
+    compiled: RwLock<HashMap<(String, u32), Arc<JSONSchema>>>,
+}
+
+impl SchemaRegistry {
+    pub fn new(store: impl SchemaStore + 'static) -> Self {
+        Self {
+            store: Box::new(store),
+            compatibility: Compatibility::default(),
+            compiled: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_compatibility(mut self, compatibility: Compatibility) -> Self {
+        self.compatibility = compatibility;
+        self
+    }
+
+    /// Registers `schema` as `version` of `event_type`.
+    ///
+    /// The version must be newer than every registered one and compatible with
+    /// the latest of them. Re-registering an identical schema is a no-op.
+    pub async fn register(&self, event_type: &str, version: u32, schema: Value) -> Result<(), MessagingError> {
+        compile(&schema)?;
+
+        if let Some(existing) = self.store.get(event_type, version).await? {
+            if existing == schema {
+                return Ok(());
+            }
+            return Err(MessagingError::IncompatibleSchema(format!(
+                "{} v{} is already registered with a different schema", event_type, version
+            )));
+        }
+
+        let latest = self.store.versions(event_type).await?.into_iter().max();
+        if let Some(latest) = latest {
+            if version < latest {
+                return Err(MessagingError::IncompatibleSchema(format!(
+                    "{} v{} is older than the latest registered version v{}", event_type, version, latest
+                )));
+            }
+            let previous = self.schema(event_type, latest).await?;
+            check_compatibility(&previous, &schema, self.compatibility)?;
+        }
+
+        self.store.put(event_type, version, &schema).await
+    }
+
+    pub async fn schema(&self, event_type: &str, version: u32) -> Result<Value, MessagingError> {
+        self.store.get(event_type, version).await?.ok_or_else(|| {
+            MessagingError::SchemaError(format!("No schema registered for {} v{}", event_type, version))
+        })
+    }
+
+    pub async fn latest_version(&self, event_type: &str) -> Result<Option<u32>, MessagingError> {
+        Ok(self.store.versions(event_type).await?.into_iter().max())
+    }
+
+    /// Validates `payload` against the registered schema for `event_type` at `version`.
+    pub async fn validate(&self, event_type: &str, version: u32, payload: &Value) -> Result<(), MessagingError> {
+        let key = (event_type.to_string(), version);
+        let cached = self.compiled.read().unwrap().get(&key).cloned();
+        let compiled = match cached {
+            Some(compiled) => compiled,
+            None => {
+                let compiled = Arc::new(compile(&self.schema(event_type, version).await?)?);
+                self.compiled.write().unwrap().insert(key, compiled.clone());
+                compiled
+            }
+        };
+
+        validate_against(&compiled, payload)
+            .map_err(|e| MessagingError::SchemaValidationError(format!("{} v{}: {}", event_type, version, e)))
+    }
+
+    /// Validates a message payload using its type and `schema-version` header.
+    ///
+    /// Only types with a registered schema are checked, so commands and
+    /// replies pass untouched. A message of a registered type must say which
+    /// version it follows.
+    pub async fn validate_message(&self, message: &Message) -> Result<(), MessagingError> {
+        let version = message.schema_version();
+        let compiled = version.is_some_and(|version| {
+            self.compiled.read().unwrap().contains_key(&(message.message_type.clone(), version))
+        });
+        if !compiled && self.latest_version(&message.message_type).await?.is_none() {
+            return Ok(());
+        }
+        let version = version.ok_or_else(|| {
+            MessagingError::SchemaValidationError(format!("Message {} has no schema version", message.id))
+        })?;
+        self.validate(&message.message_type, version, &message.payload).await
+    }
+}
+
+fn compile(schema: &Value) -> Result<JSONSchema, MessagingError> {
+    JSONSchema::compile(schema).map_err(|e| MessagingError::SchemaError(format!("Invalid schema: {}", e)))
+}
+
+fn validate_against(schema: &JSONSchema, payload: &Value) -> Result<(), String> {
+    schema.validate(payload).map_err(|errors| {
+        errors
+            .map(|e| format!("{} at '{}'", e, e.instance_path))
+            .collect::<Vec<_>>()
+            .join("; ")
+    })
+}
+
+/// Checks that moving from `previous` to `next` satisfies `compatibility`.
+pub fn check_compatibility(previous: &Value, next: &Value, compatibility: Compatibility) -> Result<(), MessagingError> {
+    let mut problems = Vec::new();
+    if matches!(compatibility, Compatibility::Backward | Compatibility::Full) {
+        can_read(next, previous, "$", &mut problems);
+    }
+    if matches!(compatibility, Compatibility::Forward | Compatibility::Full) {
+        can_read(previous, next, "$", &mut problems);
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(MessagingError::IncompatibleSchema(problems.join("; ")))
+    }
+}
+
+/// Records why data written under `writer` might be rejected by `reader`.
+fn can_read(reader: &Value, writer: &Value, path: &str, problems: &mut Vec<String>) {
+    let reader_types = types(reader);
+    let writer_types = types(writer);
+    if let (Some(reader_types), Some(writer_types)) = (&reader_types, &writer_types) {
+        for writer_type in writer_types {
+            let accepted = reader_types.contains(writer_type)
+                || (writer_type == "integer" && reader_types.iter().any(|t| t == "number"));
+            if !accepted {
+                problems.push(format!("{}: type '{}' is no longer accepted", path, writer_type));
+            }
+        }
+    } else if reader_types.is_some() {
+        problems.push(format!("{}: type was narrowed", path));
+    }
+
+    if let Some(reader_enum) = reader.get("enum").and_then(Value::as_array) {
+        match writer.get("enum").and_then(Value::as_array) {
+            Some(writer_enum) => {
+                for value in writer_enum.iter().filter(|value| !reader_enum.contains(value)) {
+                    problems.push(format!("{}: enum value {} is no longer accepted", path, value));
+                }
+            }
+            None => problems.push(format!("{}: values were restricted to an enum", path)),
+        }
+    }
+
+    let writer_required = required(writer);
+    for field in required(reader) {
+        if !writer_required.contains(&field) {
+            problems.push(format!("{}: field '{}' is required but may be missing", path, field));
+        }
+    }
+
+    let reader_properties = reader.get("properties").and_then(Value::as_object);
+    let writer_properties = writer.get("properties").and_then(Value::as_object);
+    if let Some(writer_properties) = writer_properties {
+        for (name, writer_schema) in writer_properties {
+            match reader_properties.and_then(|properties| properties.get(name)) {
+                Some(reader_schema) => {
+                    can_read(reader_schema, writer_schema, &format!("{}.{}", path, name), problems);
+                }
+                None if reader.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                    problems.push(format!("{}: field '{}' is not allowed", path, name));
+                }
+                None => {}
+            }
+        }
+    }
+
+    if let (Some(reader_items), Some(writer_items)) = (reader.get("items"), writer.get("items")) {
+        can_read(reader_items, writer_items, &format!("{}[]", path), problems);
+    }
+}
+
+fn types(schema: &Value) -> Option<Vec<String>> {
+    match schema.get("type")? {
+        Value::String(t) => Some(vec![t.clone()]),
+        Value::Array(ts) => Some(ts.iter().filter_map(Value::as_str).map(str::to_string).collect()),
+        _ => None,
+    }
+}
+
+fn required(schema: &Value) -> Vec<String> {
+    schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|fields| fields.iter().filter_map(Value::as_str).map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Panics if `sample` no longer matches the schema registered for `E::VERSION`
+/// under `schema_dir`.
+///
+/// Intended for unit tests, so that changing an event struct without
+/// registering a new schema version fails CI.
+pub fn assert_matches_registered_schema<E: Event>(schema_dir: impl AsRef<Path>, sample: &E) {
+    let path = schema_path(schema_dir.as_ref(), E::TYPE, E::VERSION);
+    let schema: Value = std::fs::read(&path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_else(|| panic!("no readable schema for {} v{} at {}", E::TYPE, E::VERSION, path.display()));
+    let payload = serde_json::to_value(sample).expect("event serializes to JSON");
+
+    let compiled = compile(&schema).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    if let Err(e) = validate_against(&compiled, &payload) {
+        panic!("{} v{} no longer matches its registered schema: {}", E::TYPE, E::VERSION, e);
+    }
+
+    let mut declared: Vec<&String> = schema
+        .get("properties")
+        .and_then(Value::as_object)
+        .map(|properties| properties.keys().collect())
+        .unwrap_or_default();
+    let mut actual: Vec<&String> = payload
+        .as_object()
+        .map(|fields| fields.keys().collect())
+        .unwrap_or_default();
+    declared.sort();
+    actual.sort();
+    assert_eq!(
+        actual, declared,
+        "{} v{} fields differ from its registered schema; register a new version",
+        E::TYPE, E::VERSION
+    );
+}
@@ -0,0 +1,288 @@
+use crate::{Message, MessagingError};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::{Signer, Verifier};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::HashMap;
+
+pub const SIGNATURE_HEADER: &str = "signature";
+pub const SIGNATURE_ALGORITHM_HEADER: &str = "signature-alg";
+pub const SIGNATURE_KEY_HEADER: &str = "signature-key";
+
+pub const HMAC_SHA256: &str = "hmac-sha256";
+pub const ED25519: &str = "ed25519";
+
+/// Bytes a signature covers: every envelope field except the signature
+/// headers themselves, as JSON with object keys sorted at every level.
+///
+/// The timestamp is taken as Unix nanoseconds so that codecs which keep the
+/// instant but not the textual form still verify.
+pub fn canonical_envelope(message: &Message) -> Result<Vec<u8>, MessagingError> {
+    let headers: serde_json::Map<String, Value> = message
+        .headers
+        .iter()
+        .filter(|(name, _)| !is_signature_header(name))
+        .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+        .collect();
+    let envelope = serde_json::json!({
+        "id": message.id,
+        "correlation_id": message.correlation_id,
+        "causation_id": message.causation_id,
+        "message_type": message.message_type,
+        "source": message.source,
+        "destination": message.destination,
+        "timestamp": message.timestamp.unix_timestamp_nanos().to_string(),
+        "headers": headers,
+        "payload": message.payload,
+    });
+    let mut out = Vec::new();
+    write_canonical(&envelope, &mut out)?;
+    Ok(out)
+}
+
+fn is_signature_header(name: &str) -> bool {
+    matches!(name, SIGNATURE_HEADER | SIGNATURE_ALGORITHM_HEADER | SIGNATURE_KEY_HEADER)
+}
+
+fn write_canonical(value: &Value, out: &mut Vec<u8>) -> Result<(), MessagingError> {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push(b'{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                serde_json::to_writer(&mut *out, key)?;
+                out.push(b':');
+                write_canonical(value, out)?;
+            }
+            out.push(b'}');
+        }
+        Value::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_canonical(item, out)?;
+            }
+            out.push(b']');
+        }
+        other => serde_json::to_writer(&mut *out, other)?,
+    }
+    Ok(())
+}
+
+fn signature_error(message: &Message, reason: impl std::fmt::Display) -> MessagingError {
+    MessagingError::SignatureError(format!("message {} from {}: {}", message.id, message.source, reason))
+}
+
+fn hmac_sha256(secret: &[u8]) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length")
+}
+
+enum SigningKey {
+    Hmac(Vec<u8>),
+    Ed25519(Box<ed25519_dalek::SigningKey>),
+}
+
+/// Signs outgoing messages with one key. Subscribers look the key up by its
+/// id in their [`TrustedKeys`].
+pub struct MessageSigner {
+    key_id: String,
+    key: SigningKey,
+}
+
+impl MessageSigner {
+    pub fn hmac(key_id: impl Into<String>, secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key_id: key_id.into(),
+            key: SigningKey::Hmac(secret.into()),
+        }
+    }
+
+    pub fn ed25519(key_id: impl Into<String>, key: ed25519_dalek::SigningKey) -> Self {
+        Self {
+            key_id: key_id.into(),
+            key: SigningKey::Ed25519(Box::new(key)),
+        }
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// Adds the signature headers. Anything that changes the envelope
+    /// afterwards, including encryption, invalidates the signature.
+    pub fn sign(&self, mut message: Message) -> Result<Message, MessagingError> {
+        let canonical = canonical_envelope(&message)?;
+        let (algorithm, signature) = match &self.key {
+            SigningKey::Hmac(secret) => {
+                let mut mac = hmac_sha256(secret);
+                mac.update(&canonical);
+                (HMAC_SHA256, mac.finalize().into_bytes().to_vec())
+            }
+            SigningKey::Ed25519(key) => (ED25519, key.sign(&canonical).to_bytes().to_vec()),
+        };
+        message.headers.insert(SIGNATURE_ALGORITHM_HEADER.to_string(), algorithm.to_string());
+        message.headers.insert(SIGNATURE_KEY_HEADER.to_string(), self.key_id.clone());
+        message.headers.insert(SIGNATURE_HEADER.to_string(), BASE64.encode(signature));
+        Ok(message)
+    }
+}
+
+#[derive(Clone)]
+enum VerifyingKey {
+    Hmac(Vec<u8>),
+    Ed25519(ed25519_dalek::VerifyingKey),
+}
+
+#[derive(Clone)]
+struct TrustedKey {
+    source: String,
+    key: VerifyingKey,
+}
+
+/// Keys a subscriber accepts signatures from, each bound to the one message
+/// `source` allowed to use it.
+///
+/// With `require_signatures` off, unsigned messages pass but a signature that
+/// is present must still verify. This allows rolling signing out one
+/// publisher at a time.
+#[derive(Clone, Default)]
+pub struct TrustedKeys {
+    keys: HashMap<String, TrustedKey>,
+    require_signatures: bool,
+}
+
+#[derive(Deserialize)]
+struct TrustedKeysConfig {
+    #[serde(default)]
+    require_signatures: bool,
+    keys: Vec<TrustedKeyConfig>,
+}
+
+#[derive(Deserialize)]
+struct TrustedKeyConfig {
+    source: String,
+    key_id: String,
+    algorithm: String,
+    /// Base64: the shared secret for HMAC, the 32-byte public key for Ed25519.
+    key: String,
+}
+
+impl TrustedKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads keys from JSON of the form
+    /// `{"require_signatures": true, "keys": [{"source", "key_id", "algorithm", "key"}]}`.
+    pub fn from_json(json: &str) -> Result<Self, MessagingError> {
+        let config: TrustedKeysConfig = serde_json::from_str(json)?;
+        let mut trusted = Self::new().require_signatures(config.require_signatures);
+        for entry in config.keys {
+            let key = BASE64
+                .decode(&entry.key)
+                .map_err(|e| MessagingError::SignatureError(format!("key {}: {}", entry.key_id, e)))?;
+            trusted = match entry.algorithm.as_str() {
+                HMAC_SHA256 => trusted.trust_hmac(entry.source, entry.key_id, key),
+                ED25519 => {
+                    let bytes: [u8; 32] = key.try_into().map_err(|_| {
+                        MessagingError::SignatureError(format!("key {}: Ed25519 keys are 32 bytes", entry.key_id))
+                    })?;
+                    let key = ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+                        .map_err(|e| MessagingError::SignatureError(format!("key {}: {}", entry.key_id, e)))?;
+                    trusted.trust_ed25519(entry.source, entry.key_id, key)
+                }
+                other => {
+                    return Err(MessagingError::SignatureError(format!(
+                        "key {}: unsupported algorithm {}",
+                        entry.key_id, other
+                    )))
+                }
+            };
+        }
+        Ok(trusted)
+    }
+
+    pub fn require_signatures(mut self, required: bool) -> Self {
+        self.require_signatures = required;
+        self
+    }
+
+    pub fn trust_hmac(mut self, source: impl Into<String>, key_id: impl Into<String>, secret: impl Into<Vec<u8>>) -> Self {
+        self.keys.insert(
+            key_id.into(),
+            TrustedKey {
+                source: source.into(),
+                key: VerifyingKey::Hmac(secret.into()),
+            },
+        );
+        self
+    }
+
+    pub fn trust_ed25519(mut self, source: impl Into<String>, key_id: impl Into<String>, key: ed25519_dalek::VerifyingKey) -> Self {
+        self.keys.insert(
+            key_id.into(),
+            TrustedKey {
+                source: source.into(),
+                key: VerifyingKey::Ed25519(key),
+            },
+        );
+        self
+    }
+
+    /// Checks that `message` was signed by a trusted key belonging to its
+    /// `source` and has not been altered since.
+    pub fn verify(&self, message: &Message) -> Result<(), MessagingError> {
+        let Some(signature) = message.headers.get(SIGNATURE_HEADER) else {
+            return if self.require_signatures {
+                Err(signature_error(message, "unsigned"))
+            } else {
+                Ok(())
+            };
+        };
+        let key_id = message
+            .headers
+            .get(SIGNATURE_KEY_HEADER)
+            .ok_or_else(|| signature_error(message, "no signing key id"))?;
+        let trusted = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| signature_error(message, format!("untrusted key {}", key_id)))?;
+        if trusted.source != message.source {
+            return Err(signature_error(message, format!("key {} belongs to {}", key_id, trusted.source)));
+        }
+
+        let algorithm = message.headers.get(SIGNATURE_ALGORITHM_HEADER).map(String::as_str);
+        let signature = BASE64.decode(signature).map_err(|e| signature_error(message, e))?;
+        let canonical = canonical_envelope(message)?;
+        let valid = match (&trusted.key, algorithm) {
+            (VerifyingKey::Hmac(secret), Some(HMAC_SHA256)) => {
+                let mut mac = hmac_sha256(secret);
+                mac.update(&canonical);
+                mac.verify_slice(&signature).is_ok()
+            }
+            (VerifyingKey::Ed25519(key), Some(ED25519)) => ed25519_dalek::Signature::from_slice(&signature)
+                .map(|signature| key.verify(&canonical, &signature).is_ok())
+                .unwrap_or(false),
+            (_, algorithm) => {
+                return Err(signature_error(
+                    message,
+                    format!("algorithm {:?} does not match key {}", algorithm, key_id),
+                ))
+            }
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(signature_error(message, "signature mismatch"))
+        }
+    }
+}
@@ -1,56 +1,135 @@
-use crate::{Message, MessagingError};
+use crate::{
+    decode_message, handle_request, propagation::handle_traced, subscription::{spawn_subscription, KeyFn}, Event, Message, MessagingError,
+    PayloadCipher, Publisher, RpcError, SubscriptionHandle, SubscriptionOptions, TrustedKeys, UpcasterChain,
+};
+use async_nats::Subject;
+use serde::{de::DeserializeOwned, Serialize};
 use async_nats::Client;
+use std::sync::Arc;
 use tracing::info;
 use futures::StreamExt;
 
 pub struct Subscriber {
     client: Client,
+    upcasters: Arc<UpcasterChain>,
+    trusted_keys: Option<Arc<TrustedKeys>>,
+    decryption: Option<Arc<PayloadCipher>>,
 }
 
 impl Subscriber {
     pub fn new(client: Client) -> Self {
-        Self { client }
+        Self {
+            client,
+            upcasters: Arc::new(UpcasterChain::new()),
+            trusted_keys: None,
+            decryption: None,
+        }
+    }
+
+    /// Upgrades older payload versions before typed handlers see them.
+    pub fn with_upcasters(mut self, upcasters: UpcasterChain) -> Self {
+        self.upcasters = Arc::new(upcasters);
+        self
+    }
+
+    /// Drops messages whose signature does not verify against `keys`, and
+    /// unsigned ones too if `keys` requires signatures.
+    pub fn with_trusted_keys(mut self, keys: TrustedKeys) -> Self {
+        self.trusted_keys = Some(Arc::new(keys));
+        self
+    }
+
+    /// Decrypts encrypted payloads before handlers see them. Without it,
+    /// handlers receive the ciphertext.
+    pub fn with_decryption(mut self, cipher: PayloadCipher) -> Self {
+        self.decryption = Some(Arc::new(cipher));
+        self
     }
 
+    /// Verifies and decrypts a decoded message, in the reverse order of
+    /// [`crate::Publisher`]'s sealing.
+    fn opener(&self) -> impl Fn(Message) -> Result<Message, MessagingError> + Clone + Send + Sync + 'static {
+        let trusted_keys = self.trusted_keys.clone();
+        let decryption = self.decryption.clone();
+        move |message| {
+            if let Some(keys) = &trusted_keys {
+                keys.verify(&message)?;
+            }
+            match &decryption {
+                Some(cipher) => cipher.decrypt(message),
+                None => Ok(message),
+            }
+        }
+    }
+
+    /// Subscribes to `subject`, handling messages in the background until the
+    /// returned handle is drained or unsubscribed.
     pub async fn subscribe<F, Fut>(
         &self,
         subject: &str,
-        mut handler: F,
-    ) -> Result<(), MessagingError>
+        handler: F,
+    ) -> Result<SubscriptionHandle, MessagingError>
+    where
+        F: Fn(Message) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<(), MessagingError>> + Send + 'static,
+    {
+        self.subscribe_with(subject, SubscriptionOptions::default(), handler).await
+    }
+
+    /// Like [`Subscriber::subscribe`], with a queue group, concurrency limit
+    /// and per-key ordering taken from `options`.
+    pub async fn subscribe_with<F, Fut>(
+        &self,
+        subject: &str,
+        options: SubscriptionOptions,
+        handler: F,
+    ) -> Result<SubscriptionHandle, MessagingError>
     where
         F: Fn(Message) -> Fut + Send + Sync + 'static,
-        Fut: std::future::Future<Output = Result<(), MessagingError>> + Send,
+        Fut: std::future::Future<Output = Result<(), MessagingError>> + Send + 'static,
     {
         let subject_owned = subject.to_string();
-        let mut subscriber = self.client.subscribe(subject_owned).await
-            .map_err(|e| MessagingError::NatsError(Box::new(e)))?;
-        info!("Subscribed to subject {}", subject);
-
-        while let Some(message) = subscriber.next().await {
-            let payload: Message = match serde_json::from_slice(&message.payload) {
-                Ok(msg) => msg,
-                Err(e) => {
-                    tracing::error!("Failed to deserialize message: {}", e);
-                    continue;
-                }
-            };
-
-            if let Err(e) = handler(payload).await {
-                tracing::error!("Error handling message: {:?}", e);
-            }
+        let subscriber = match &options.queue_group {
+            Some(group) => self.client.queue_subscribe(subject_owned, group.clone()).await,
+            None => self.client.subscribe(subject_owned).await,
         }
+        .map_err(|e| MessagingError::NatsError(Box::new(e)))?;
+        info!(
+            "Subscribed to subject {} (queue group {:?}, concurrency {})",
+            subject, options.queue_group, options.concurrency
+        );
 
-        Ok(())
+        let open = self.opener();
+        let messages = subscriber.filter_map(move |message| {
+            let open = open.clone();
+            async move {
+                match decode_message(message.headers.as_ref(), &message.payload).and_then(open) {
+                    Ok(msg) => Some(msg),
+                    Err(e) => {
+                        tracing::error!("Rejected message: {}", e);
+                        None
+                    }
+                }
+            }
+        });
+        // Each message is handled in a child span of the trace it was published in
+        let handler = Arc::new(handler);
+        let traced_subject = subject.to_string();
+        Ok(spawn_subscription(subject, messages, options.concurrency, options.ordering_key, move |message| {
+            let handler = handler.clone();
+            let subject = traced_subject.clone();
+            async move { handle_traced(&subject, handler.as_ref(), message).await }
+        }))
     }
 
     pub async fn subscribe_to_events<F, Fut>(
         &self,
         event_type: &str,
         handler: F,
-    ) -> Result<(), MessagingError>
+    ) -> Result<SubscriptionHandle, MessagingError>
     where
         F: Fn(Message) -> Fut + Send + Sync + 'static,
-        Fut: std::future::Future<Output = Result<(), MessagingError>> + Send,
+        Fut: std::future::Future<Output = Result<(), MessagingError>> + Send + 'static,
     {
         let subject = format!("events.{}", event_type);
         self.subscribe(&subject, handler).await
@@ -60,12 +139,124 @@ impl Subscriber {
         &self,
         command_type: &str,
         handler: F,
-    ) -> Result<(), MessagingError>
+    ) -> Result<SubscriptionHandle, MessagingError>
     where
         F: Fn(Message) -> Fut + Send + Sync + 'static,
-        Fut: std::future::Future<Output = Result<(), MessagingError>> + Send,
+        Fut: std::future::Future<Output = Result<(), MessagingError>> + Send + 'static,
     {
         let subject = format!("commands.{}", command_type);
         self.subscribe(&subject, handler).await
     }
-}
\ No newline at end of file
+
+    /// Subscribes to `E::SUBJECT` and hands decoded events to `handler`.
+    ///
+    /// Older payload versions are upgraded through the registered upcasters
+    /// first. Messages whose `message_type` is not `E::TYPE`, or whose payload
+    /// cannot be upgraded or deserialized as `E`, are logged and dropped.
+    pub async fn subscribe_typed<E, F, Fut>(&self, handler: F) -> Result<SubscriptionHandle, MessagingError>
+    where
+        E: Event,
+        F: Fn(E, Message) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<(), MessagingError>> + Send + 'static,
+    {
+        self.subscribe_typed_with(SubscriptionOptions::default(), handler).await
+    }
+
+    /// Typed counterpart of [`Subscriber::subscribe_with`].
+    pub async fn subscribe_typed_with<E, F, Fut>(&self, options: SubscriptionOptions, handler: F) -> Result<SubscriptionHandle, MessagingError>
+    where
+        E: Event,
+        F: Fn(E, Message) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<(), MessagingError>> + Send + 'static,
+    {
+        let upcasters = self.upcasters.clone();
+        self.subscribe_with(E::SUBJECT, options, move |message: Message| {
+            let fut = upcasters.decode::<E>(message).map(|(event, message)| handler(event, message));
+            async move {
+                match fut {
+                    Ok(fut) => fut.await,
+                    Err(e) => {
+                        tracing::error!("Rejected message on {}: {}", E::SUBJECT, e);
+                        Ok(())
+                    }
+                }
+            }
+        })
+        .await
+    }
+
+    /// Answers requests sent with [`crate::Publisher::request`] on `subject`.
+    ///
+    /// Requests are handled in the background until the returned handle is
+    /// drained or unsubscribed, with the queue group, concurrency limit and
+    /// ordering taken from `options`. Requests are verified and decrypted
+    /// like any other message this subscriber receives.
+    ///
+    /// The handler's result, including a typed [`RpcError`], is sent back to
+    /// the caller's reply inbox with the request's correlation id. Replies go
+    /// out through `replies`, so they are encoded, encrypted and signed like
+    /// everything it publishes. Their source is `subject`, which is what a
+    /// requester's trusted keys bind the signing key to.
+    pub async fn serve<Req, Resp, F, Fut>(
+        &self,
+        subject: &str,
+        options: SubscriptionOptions,
+        replies: Arc<Publisher>,
+        handler: F,
+    ) -> Result<SubscriptionHandle, MessagingError>
+    where
+        Req: DeserializeOwned + Send + 'static,
+        Resp: Serialize + Send + 'static,
+        F: Fn(Req, Message) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<Resp, RpcError>> + Send + 'static,
+    {
+        let subject_owned = subject.to_string();
+        let subscriber = match &options.queue_group {
+            Some(group) => self.client.queue_subscribe(subject_owned, group.clone()).await,
+            None => self.client.subscribe(subject_owned).await,
+        }
+        .map_err(|e| MessagingError::NatsError(Box::new(e)))?;
+        info!(
+            "Serving requests on subject {} (queue group {:?}, concurrency {})",
+            subject, options.queue_group, options.concurrency
+        );
+
+        let serving = subject.to_string();
+        let open = self.opener();
+        let requests = subscriber.filter_map(move |message| {
+            let subject = serving.clone();
+            let open = open.clone();
+            async move {
+                let Some(reply_to) = message.reply.clone() else {
+                    tracing::warn!("Ignoring message without reply subject on {}", subject);
+                    return None;
+                };
+                match decode_message(message.headers.as_ref(), &message.payload).and_then(open) {
+                    Ok(request) => Some((reply_to, request)),
+                    Err(e) => {
+                        tracing::error!("Rejected request: {}", e);
+                        None
+                    }
+                }
+            }
+        });
+
+        let key: Option<KeyFn<(Subject, Message)>> = options.ordering_key.map(|key| {
+            Arc::new(move |(_, request): &(Subject, Message)| key(request)) as KeyFn<(Subject, Message)>
+        });
+        let handler = Arc::new(handler);
+        let traced_subject = subject.to_string();
+        Ok(spawn_subscription(subject, requests, options.concurrency, key, move |(reply_to, request)| {
+            let replies = replies.clone();
+            let handler = handler.clone();
+            let subject = traced_subject.clone();
+            async move {
+                let handle = |request| handle_request(request, handler.as_ref());
+                let Some(reply) = handle_traced(&subject, &handle, request).await? else {
+                    return Ok(());
+                };
+                replies.reply(reply_to, reply).await
+            }
+        }))
+    }
+}
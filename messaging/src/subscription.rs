@@ -0,0 +1,454 @@
+use crate::{Message, MessagingError};
+use futures::{FutureExt, Stream, StreamExt};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Mutex, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::info;
+
+pub(crate) type KeyFn<T = Message> = Arc<dyn Fn(&T) -> Option<String> + Send + Sync>;
+
+/// How a subscription receives and schedules its messages.
+///
+/// The defaults match the original behaviour: every instance receives every
+/// message and handles them one at a time.
+#[derive(Clone)]
+pub struct SubscriptionOptions {
+    /// Instances subscribed with the same queue group share the messages
+    /// between them instead of each receiving a copy.
+    pub queue_group: Option<String>,
+    /// Maximum number of handlers running at once, at least one. When all
+    /// slots are busy the subscription stops pulling messages until one
+    /// frees up.
+    pub(crate) concurrency: usize,
+    pub(crate) ordering_key: Option<KeyFn>,
+}
+
+impl Default for SubscriptionOptions {
+    fn default() -> Self {
+        Self {
+            queue_group: None,
+            concurrency: 1,
+            ordering_key: None,
+        }
+    }
+}
+
+impl SubscriptionOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn queue_group(mut self, group: impl Into<String>) -> Self {
+        self.queue_group = Some(group.into());
+        self
+    }
+
+    /// Limits how many handlers run at once. Zero is treated as one.
+    pub fn concurrency(mut self, limit: usize) -> Self {
+        self.concurrency = limit.max(1);
+        self
+    }
+
+    /// Handles messages sharing a key strictly in arrival order, while
+    /// messages with different keys may still run in parallel.
+    ///
+    /// Messages for which `key` returns `None` have no ordering guarantee.
+    pub fn ordered_by<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&Message) -> Option<String> + Send + Sync + 'static,
+    {
+        self.ordering_key = Some(Arc::new(key));
+        self
+    }
+
+    /// Orders by a top-level payload field such as `user_id`.
+    pub fn ordered_by_field(self, field: &str) -> Self {
+        let field = field.to_string();
+        self.ordered_by(move |message| {
+            message.payload.get(&field).map(|value| match value {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            })
+        })
+    }
+}
+
+/// Where a subscription is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionState {
+    Active,
+    /// No longer receiving; waiting for in-flight handlers to finish.
+    Draining,
+    Closed,
+}
+
+/// Point-in-time view of a subscription, as returned by [`SubscriptionHandle::status`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriptionStatus {
+    pub subject: String,
+    pub state: SubscriptionState,
+    pub in_flight: usize,
+    pub handled: u64,
+    pub failed: u64,
+    /// Messages already delivered when the subscription was drained that no
+    /// handler had started. Core NATS does not redeliver them.
+    pub dropped: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Stats {
+    state: AtomicU8,
+    in_flight: AtomicUsize,
+    handled: AtomicU64,
+    failed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Stats {
+    const ACTIVE: u8 = 0;
+    const DRAINING: u8 = 1;
+    const CLOSED: u8 = 2;
+
+    fn state(&self) -> SubscriptionState {
+        match self.state.load(Ordering::SeqCst) {
+            Self::ACTIVE => SubscriptionState::Active,
+            Self::DRAINING => SubscriptionState::Draining,
+            _ => SubscriptionState::Closed,
+        }
+    }
+}
+
+/// Controls a running subscription.
+///
+/// Dropping the handle leaves the subscription running in the background;
+/// call [`SubscriptionHandle::drain`] or [`SubscriptionHandle::unsubscribe`]
+/// to stop it.
+pub struct SubscriptionHandle {
+    subject: String,
+    stop: Option<oneshot::Sender<()>>,
+    stats: Arc<Stats>,
+    task: JoinHandle<()>,
+}
+
+impl SubscriptionHandle {
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn status(&self) -> SubscriptionStatus {
+        SubscriptionStatus {
+            subject: self.subject.clone(),
+            state: self.stats.state(),
+            in_flight: self.stats.in_flight.load(Ordering::SeqCst),
+            handled: self.stats.handled.load(Ordering::SeqCst),
+            failed: self.stats.failed.load(Ordering::SeqCst),
+            dropped: self.stats.dropped.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Stops receiving and waits for in-flight handlers to finish.
+    ///
+    /// No new handler starts once this is called, even if every slot was busy.
+    /// NATS is told to stop delivering before the wait starts, so queue-group
+    /// peers pick up new messages while this instance finishes its own.
+    ///
+    /// Core NATS has no acknowledgements: messages it had already delivered
+    /// here that no handler had started are lost, not redelivered to a peer.
+    /// They are logged and counted in [`SubscriptionStatus::dropped`].
+    pub async fn drain(mut self) -> SubscriptionStatus {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Err(e) = (&mut self.task).await {
+            tracing::error!("Subscription to {} ended abnormally: {}", self.subject, e);
+        }
+        self.stats.state.store(Stats::CLOSED, Ordering::SeqCst);
+        let dropped = self.stats.dropped.load(Ordering::SeqCst);
+        if dropped > 0 {
+            tracing::warn!("Dropped {} undelivered message(s) while draining {}", dropped, self.subject);
+        }
+        info!("Drained subscription to {}", self.subject);
+        self.status()
+    }
+
+    /// Stops receiving immediately, abandoning any in-flight handlers.
+    pub async fn unsubscribe(mut self) -> SubscriptionStatus {
+        self.stop.take();
+        self.task.abort();
+        let _ = (&mut self.task).await;
+        self.stats.state.store(Stats::CLOSED, Ordering::SeqCst);
+        self.stats.in_flight.store(0, Ordering::SeqCst);
+        info!("Unsubscribed from {}", self.subject);
+        self.status()
+    }
+
+    /// Waits until the subscription ends on its own, e.g. because the
+    /// connection closed.
+    pub async fn closed(mut self) -> SubscriptionStatus {
+        let _ = (&mut self.task).await;
+        self.status()
+    }
+}
+
+/// The subscriptions a service has open, so they can all be drained on shutdown.
+#[derive(Clone, Default)]
+pub struct Subscriptions {
+    handles: Arc<Mutex<Vec<SubscriptionHandle>>>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn add(&self, handle: SubscriptionHandle) {
+        self.handles.lock().await.push(handle);
+    }
+
+    pub async fn statuses(&self) -> Vec<SubscriptionStatus> {
+        self.handles.lock().await.iter().map(SubscriptionHandle::status).collect()
+    }
+
+    /// Drains every subscription concurrently and returns their final status.
+    pub async fn drain_all(&self) -> Vec<SubscriptionStatus> {
+        let handles = std::mem::take(&mut *self.handles.lock().await);
+        futures::future::join_all(handles.into_iter().map(SubscriptionHandle::drain)).await
+    }
+}
+
+/// Runs `handler` over `messages` on a background task until the stream ends
+/// or the returned handle stops it.
+pub(crate) fn spawn_subscription<S, T, F, Fut>(
+    subject: &str,
+    messages: S,
+    concurrency: usize,
+    key: Option<KeyFn<T>>,
+    handler: F,
+) -> SubscriptionHandle
+where
+    S: Stream<Item = T> + Send + 'static,
+    T: Send + 'static,
+    F: Fn(T) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), MessagingError>> + Send + 'static,
+{
+    let (stop, stopped) = oneshot::channel::<()>();
+    let stats = Arc::new(Stats::default());
+
+    // A dropped handle detaches rather than stopping the subscription.
+    let stopped = async move {
+        if stopped.await.is_err() {
+            futures::future::pending::<()>().await;
+        }
+    };
+    let stop_stats = stats.clone();
+    let stopped = async move {
+        stopped.await;
+        stop_stats.state.store(Stats::DRAINING, Ordering::SeqCst);
+    };
+
+    let task_stats = stats.clone();
+    let task = tokio::spawn(async move {
+        dispatch(Box::pin(messages), stopped, concurrency, key, &task_stats, handler).await;
+        task_stats.state.store(Stats::CLOSED, Ordering::SeqCst);
+    });
+
+    SubscriptionHandle {
+        subject: subject.to_string(),
+        stop: Some(stop),
+        stats,
+        task,
+    }
+}
+
+/// Runs `handler` over `messages`, returning once the stream ends or `stop`
+/// completes, and every started handler has finished.
+pub(crate) async fn dispatch<S, T, St, F, Fut>(
+    messages: S,
+    stop: St,
+    concurrency: usize,
+    key: Option<KeyFn<T>>,
+    stats: &Arc<Stats>,
+    handler: F,
+) where
+    S: Stream<Item = T> + Unpin,
+    St: std::future::Future<Output = ()>,
+    T: Send + 'static,
+    F: Fn(T) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), MessagingError>> + Send + 'static,
+{
+    // No slots would never start a handler, and no lanes cannot be hashed onto
+    let concurrency = concurrency.max(1);
+    let handler = Arc::new(handler);
+    match key {
+        Some(key) => dispatch_ordered(messages, stop, concurrency, key, stats, handler).await,
+        None => dispatch_unordered(messages, stop, concurrency, stats, handler).await,
+    }
+}
+
+/// Runs one handler and records its outcome. A panicking handler counts as
+/// a failure rather than taking its worker, or an ordered lane, down with it.
+async fn run_handler<T, F, Fut>(handler: &F, stats: &Stats, message: T)
+where
+    F: Fn(T) -> Fut,
+    Fut: std::future::Future<Output = Result<(), MessagingError>>,
+{
+    stats.in_flight.fetch_add(1, Ordering::SeqCst);
+    let result = AssertUnwindSafe(async { handler(message).await }).catch_unwind().await;
+    stats.in_flight.fetch_sub(1, Ordering::SeqCst);
+    match result {
+        Ok(Ok(())) => {
+            stats.handled.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(Err(e)) => {
+            stats.failed.fetch_add(1, Ordering::SeqCst);
+            tracing::error!("Error handling message: {:?}", e);
+        }
+        Err(panic) => {
+            stats.failed.fetch_add(1, Ordering::SeqCst);
+            let reason = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            tracing::error!("Message handler panicked: {}", reason);
+        }
+    }
+}
+
+/// Counts the messages that had already arrived when the subscription was
+/// stopped, then drops the stream, which unsubscribes.
+fn discard_buffered<S: Stream + Unpin>(mut messages: S, stats: &Stats) {
+    let mut dropped = 0;
+    while let Some(Some(_)) = messages.next().now_or_never() {
+        dropped += 1;
+    }
+    stats.dropped.fetch_add(dropped, Ordering::SeqCst);
+}
+
+async fn dispatch_unordered<S, T, St, F, Fut>(mut messages: S, stop: St, concurrency: usize, stats: &Arc<Stats>, handler: Arc<F>)
+where
+    S: Stream<Item = T> + Unpin,
+    St: std::future::Future<Output = ()>,
+    T: Send + 'static,
+    F: Fn(T) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), MessagingError>> + Send + 'static,
+{
+    let permits = Arc::new(Semaphore::new(concurrency));
+    let mut running = JoinSet::new();
+    tokio::pin!(stop);
+
+    let stopped = loop {
+        // Wait for a free slot before pulling the next message so a slow
+        // handler holds messages back in NATS rather than in memory. Neither
+        // wait holds up a drain.
+        let permit = tokio::select! {
+            biased;
+            _ = &mut stop => break true,
+            permit = permits.clone().acquire_owned() => permit.expect("semaphore is never closed"),
+        };
+        let message = tokio::select! {
+            biased;
+            _ = &mut stop => break true,
+            message = messages.next() => match message {
+                Some(message) => message,
+                None => break false,
+            },
+        };
+        let handler = handler.clone();
+        let stats = stats.clone();
+        running.spawn(async move {
+            run_handler(handler.as_ref(), &stats, message).await;
+            drop(permit);
+        });
+        while running.try_join_next().is_some() {}
+    };
+
+    // Unsubscribe before waiting so nothing new is delivered to us meanwhile.
+    if stopped {
+        discard_buffered(messages, stats);
+    } else {
+        drop(messages);
+    }
+    while running.join_next().await.is_some() {}
+}
+
+/// Each key hashes onto one of `concurrency` lanes. A lane is a single worker
+/// with a one-slot queue, so a key's messages never overtake each other and a
+/// busy lane pushes back on the stream.
+async fn dispatch_ordered<S, T, St, F, Fut>(
+    mut messages: S,
+    stop: St,
+    concurrency: usize,
+    key: KeyFn<T>,
+    stats: &Arc<Stats>,
+    handler: Arc<F>,
+) where
+    S: Stream<Item = T> + Unpin,
+    St: std::future::Future<Output = ()>,
+    T: Send + 'static,
+    F: Fn(T) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Result<(), MessagingError>> + Send + 'static,
+{
+    let mut lanes = Vec::with_capacity(concurrency);
+    let mut workers = JoinSet::new();
+    for _ in 0..concurrency {
+        let (tx, mut rx) = mpsc::channel::<T>(1);
+        let handler = handler.clone();
+        let stats = stats.clone();
+        workers.spawn(async move {
+            while let Some(message) = rx.recv().await {
+                run_handler(handler.as_ref(), &stats, message).await;
+            }
+        });
+        lanes.push(tx);
+    }
+
+    let mut next_unkeyed = 0;
+    tokio::pin!(stop);
+    let stopped = loop {
+        let message = tokio::select! {
+            biased;
+            _ = &mut stop => break true,
+            message = messages.next() => match message {
+                Some(message) => message,
+                None => break false,
+            },
+        };
+        let lane = match key(&message) {
+            Some(key) => {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                (hasher.finish() % concurrency as u64) as usize
+            }
+            None => {
+                next_unkeyed = (next_unkeyed + 1) % concurrency;
+                next_unkeyed
+            }
+        };
+        let sent = tokio::select! {
+            biased;
+            _ = &mut stop => {
+                // Its lane was still busy, so it never reached a handler
+                stats.dropped.fetch_add(1, Ordering::SeqCst);
+                break true;
+            }
+            sent = lanes[lane].send(message) => sent,
+        };
+        if sent.is_err() {
+            tracing::error!("Subscription worker stopped unexpectedly");
+            break false;
+        }
+    };
+
+    if stopped {
+        discard_buffered(messages, stats);
+    } else {
+        drop(messages);
+    }
+    drop(lanes);
+    while workers.join_next().await.is_some() {}
+}
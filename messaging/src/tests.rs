@@ -1,68 +1,1702 @@
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde_json::json;
-    use std::collections::HashMap;
-
-    #[test]
-    fn test_message_creation() {
-        let payload = json!({"test": "data"});
-        let message = crate::Message::new(
-            "test_type".to_string(),
-            "test_source".to_string(),
-            "test_destination".to_string(),
-            payload.clone(),
+use serde_json::json;
+use std::collections::HashMap;
+
+#[test]
+fn test_message_creation() {
+    let payload = json!({"test": "data"});
+    let message = crate::Message::new(
+        "test_type".to_string(),
+        "test_source".to_string(),
+        "test_destination".to_string(),
+        payload.clone(),
+    );
+
+    assert_eq!(message.message_type, "test_type");
+    assert_eq!(message.source, "test_source");
+    assert_eq!(message.destination, "test_destination");
+    assert_eq!(message.payload, payload);
+    assert!(message.correlation_id.is_none());
+    assert!(message.causation_id.is_none());
+}
+
+#[test]
+fn test_message_with_correlation() {
+    let payload = json!({"test": "data"});
+    let correlation_id = uuid::Uuid::new_v4();
+    let message = crate::Message::new(
+        "test_type".to_string(),
+        "test_source".to_string(),
+        "test_destination".to_string(),
+        payload,
+    ).with_correlation(correlation_id);
+
+    assert_eq!(message.correlation_id, Some(correlation_id));
+}
+
+#[test]
+fn test_message_with_causation() {
+    let payload = json!({"test": "data"});
+    let causation_id = uuid::Uuid::new_v4();
+    let message = crate::Message::new(
+        "test_type".to_string(),
+        "test_source".to_string(),
+        "test_destination".to_string(),
+        payload,
+    ).with_causation(causation_id);
+
+    assert_eq!(message.causation_id, Some(causation_id));
+}
+
+#[test]
+fn test_message_with_header() {
+    let payload = json!({"test": "data"});
+    let mut headers = HashMap::new();
+    headers.insert("test_key".to_string(), "test_value".to_string());
+
+    let message = crate::Message::new(
+        "test_type".to_string(),
+        "test_source".to_string(),
+        "test_destination".to_string(),
+        payload,
+    ).with_header("test_key".to_string(), "test_value".to_string());
+
+    assert_eq!(message.headers.get("test_key"), Some(&"test_value".to_string()));
+}
+
+#[test]
+fn test_message_from_event() {
+    let event = crate::UserCreatedEvent {
+        user_id: uuid::Uuid::new_v4(),
+        username: "testuser".to_string(),
+        email: "test@example.com".to_string(),
+        timestamp: time::OffsetDateTime::now_utc(),
+    };
+
+    let message = crate::Message::from_event("user-service", &event).unwrap();
+
+    assert_eq!(message.message_type, "user_created");
+    assert_eq!(message.source, "user-service");
+    assert_eq!(message.destination, "events.user_created");
+    assert_eq!(message.schema_version(), Some(1));
+
+    let decoded: crate::UserCreatedEvent = message.decode().unwrap();
+    assert_eq!(decoded.user_id, event.user_id);
+    assert_eq!(decoded.email, event.email);
+}
+
+#[test]
+fn test_decode_rejects_mismatched_message_type() {
+    let event = crate::UserCreatedEvent {
+        user_id: uuid::Uuid::new_v4(),
+        username: "testuser".to_string(),
+        email: "test@example.com".to_string(),
+        timestamp: time::OffsetDateTime::now_utc(),
+    };
+    let message = crate::Message::from_event("user-service", &event).unwrap();
+
+    let result = message.decode::<crate::OrderCreatedEvent>();
+
+    assert!(matches!(
+        result,
+        Err(crate::MessagingError::UnexpectedMessageType { ref expected, ref actual })
+            if expected == "order_created" && actual == "user_created"
+    ));
+}
+
+#[test]
+fn test_decode_rejects_malformed_payload() {
+    let message = crate::Message::new(
+        "order_created".to_string(),
+        "order-service".to_string(),
+        "events.order_created".to_string(),
+        json!({"order_id": "not-a-uuid"}),
+    );
+
+    assert!(matches!(
+        message.decode::<crate::OrderCreatedEvent>(),
+        Err(crate::MessagingError::SerializationError(_))
+    ));
+}
+
+fn schema_dir() -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("schemas")
+}
+
+fn temp_schema_store() -> crate::FileSchemaStore {
+    crate::FileSchemaStore::new(std::env::temp_dir().join(format!("schemas-{}", uuid::Uuid::new_v4())))
+}
+
+#[test]
+fn test_events_match_registered_schemas() {
+    crate::assert_matches_registered_schema(schema_dir(), &crate::UserCreatedEvent {
+        user_id: uuid::Uuid::new_v4(),
+        username: "testuser".to_string(),
+        email: "test@example.com".to_string(),
+        timestamp: time::OffsetDateTime::now_utc(),
+    });
+    crate::assert_matches_registered_schema(schema_dir(), &crate::UserUpdatedEvent {
+        user_id: uuid::Uuid::new_v4(),
+        username: "renamed".to_string(),
+        email: "renamed@example.com".to_string(),
+        timestamp: time::OffsetDateTime::now_utc(),
+    });
+    crate::assert_matches_registered_schema(schema_dir(), &crate::UserDeletedEvent {
+        user_id: uuid::Uuid::new_v4(),
+        timestamp: time::OffsetDateTime::now_utc(),
+    });
+    crate::assert_matches_registered_schema(schema_dir(), &crate::UserRestoredEvent {
+        user_id: uuid::Uuid::new_v4(),
+        username: "restored".to_string(),
+        email: "restored@example.com".to_string(),
+        timestamp: time::OffsetDateTime::now_utc(),
+    });
+    crate::assert_matches_registered_schema(schema_dir(), &sample_order_created());
+    crate::assert_matches_registered_schema(schema_dir(), &crate::OrderStatusChangedEvent {
+        order_id: uuid::Uuid::new_v4(),
+        user_id: uuid::Uuid::new_v4(),
+        from: "pending".to_string(),
+        to: "cancelled".to_string(),
+        reason: None,
+        timestamp: time::OffsetDateTime::now_utc(),
+        version: Some(4),
+    });
+}
+
+#[test]
+fn test_schema_compatibility_rules() {
+    use crate::{check_compatibility, Compatibility};
+
+    let v1 = json!({
+        "type": "object",
+        "required": ["id"],
+        "properties": {"id": {"type": "string"}, "count": {"type": "integer"}}
+    });
+    let optional_field_added = json!({
+        "type": "object",
+        "required": ["id"],
+        "properties": {
+            "id": {"type": "string"},
+            "count": {"type": "integer"},
+            "note": {"type": "string"}
+        }
+    });
+    let required_field_added = json!({
+        "type": "object",
+        "required": ["id", "note"],
+        "properties": {"id": {"type": "string"}, "note": {"type": "string"}}
+    });
+    let type_widened = json!({
+        "type": "object",
+        "required": ["id"],
+        "properties": {"id": {"type": "string"}, "count": {"type": "number"}}
+    });
+
+    assert!(check_compatibility(&v1, &optional_field_added, Compatibility::Full).is_ok());
+    assert!(check_compatibility(&v1, &required_field_added, Compatibility::Backward).is_err());
+    assert!(check_compatibility(&v1, &required_field_added, Compatibility::Forward).is_ok());
+    assert!(check_compatibility(&v1, &type_widened, Compatibility::Backward).is_ok());
+    assert!(check_compatibility(&v1, &type_widened, Compatibility::Forward).is_err());
+    assert!(check_compatibility(&v1, &required_field_added, Compatibility::None).is_ok());
+}
+
+#[tokio::test]
+async fn test_schema_registry_register_and_validate() {
+    let registry = crate::SchemaRegistry::new(temp_schema_store());
+    let v1 = json!({
+        "type": "object",
+        "required": ["id"],
+        "properties": {"id": {"type": "string"}}
+    });
+
+    registry.register("thing_created", 1, v1.clone()).await.unwrap();
+    // Re-registering the same schema is idempotent.
+    registry.register("thing_created", 1, v1).await.unwrap();
+
+    let breaking = json!({
+        "type": "object",
+        "required": ["id", "name"],
+        "properties": {"id": {"type": "string"}, "name": {"type": "string"}}
+    });
+    assert!(matches!(
+        registry.register("thing_created", 2, breaking).await,
+        Err(crate::MessagingError::IncompatibleSchema(_))
+    ));
+    assert_eq!(registry.latest_version("thing_created").await.unwrap(), Some(1));
+
+    registry.validate("thing_created", 1, &json!({"id": "abc"})).await.unwrap();
+    assert!(matches!(
+        registry.validate("thing_created", 1, &json!({"id": 42})).await,
+        Err(crate::MessagingError::SchemaValidationError(_))
+    ));
+    assert!(matches!(
+        registry.validate("thing_created", 3, &json!({"id": "abc"})).await,
+        Err(crate::MessagingError::SchemaError(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_schema_registry_validates_typed_messages() {
+    let registry = crate::SchemaRegistry::new(crate::FileSchemaStore::new(schema_dir()));
+    let event = sample_order_created();
+    let mut message = crate::Message::from_event("order-service", &event).unwrap();
+    registry.validate_message(&message).await.unwrap();
+
+    message.payload["line_items"][0]["quantity"] = json!("one");
+    assert!(registry.validate_message(&message).await.is_err());
+}
+
+/// A client for a server that is not there. Nothing it publishes is
+/// delivered, but checks made before sending still run.
+async fn offline_client() -> async_nats::Client {
+    async_nats::ConnectOptions::new()
+        .retry_on_initial_connect()
+        .connect("127.0.0.1:1")
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_publisher_rejects_unversioned_messages_when_validating() {
+    let registry = std::sync::Arc::new(crate::SchemaRegistry::new(crate::FileSchemaStore::new(schema_dir())));
+    let publisher = crate::Publisher::new(offline_client().await).with_schema_registry(registry);
+
+    let mut message = crate::Message::from_event("order-service", &sample_order_created()).unwrap();
+    message.headers.remove("schema-version");
+
+    assert!(matches!(
+        publisher.publish("events.order_created", message).await,
+        Err(crate::MessagingError::SchemaValidationError(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_publisher_validates_registered_types_on_every_send_path() {
+    let registry = std::sync::Arc::new(crate::SchemaRegistry::new(crate::FileSchemaStore::new(schema_dir())));
+    let publisher = crate::Publisher::new(offline_client().await).with_schema_registry(registry);
+
+    // Commands have no registered schema, so they go out without a version
+    let command = crate::Message::new(
+        "reserve_stock".to_string(),
+        "order-service".to_string(),
+        "inventory-service".to_string(),
+        json!({"sku": "TEST-1"}),
+    );
+    publisher.publish_command("reserve_stock", command).await.unwrap();
+
+    let mut event = crate::Message::from_event("order-service", &sample_order_created()).unwrap();
+    event.headers.remove("schema-version");
+    assert!(matches!(
+        publisher.send_request("events.order_created", &event, std::time::Duration::from_millis(50)).await,
+        Err(crate::MessagingError::SchemaValidationError(_))
+    ));
+}
+
+fn sample_order_created() -> crate::OrderCreatedEvent {
+    let unit_price = shared::Money::new(9999, "USD").unwrap();
+    crate::OrderCreatedEvent {
+        order_id: uuid::Uuid::new_v4(),
+        user_id: uuid::Uuid::new_v4(),
+        line_items: vec![crate::OrderLineItem {
+            sku: "TEST-1".to_string(),
+            quantity: 1,
+            unit_price: unit_price.clone(),
+        }],
+        total: unit_price,
+        timestamp: time::OffsetDateTime::now_utc(),
+    }
+}
+
+#[test]
+fn test_replay_v1_fixtures_into_v3_handler() {
+    let fixtures: Vec<crate::Message> = serde_json::from_str(include_str!("../fixtures/order_created_v1.json")).unwrap();
+    let chain = crate::order_created_upcasters();
+
+    let mut handled = Vec::new();
+    let mut handler = |event: crate::OrderCreatedEvent, message: crate::Message| {
+        assert_eq!(message.schema_version(), Some(3));
+        handled.push(event);
+    };
+    for message in fixtures {
+        let (event, message) = chain.decode::<crate::OrderCreatedEvent>(message).unwrap();
+        handler(event, message);
+    }
+
+    assert_eq!(handled.len(), 2);
+    assert_eq!(handled[0].total.amount_minor, 14998);
+    assert_eq!(handled[0].line_items[0].sku, "Mechanical Keyboard");
+    assert_eq!(handled[0].line_items[0].quantity, 2);
+    assert_eq!(handled[0].line_items[0].unit_price.amount_minor, 7499);
+    assert_eq!(handled[1].total.amount_minor, 30);
+    assert_eq!(handled[1].total.currency, "USD");
+    assert_eq!(handled[1].line_items[0].unit_price.amount_minor, 10);
+}
+
+#[test]
+fn test_upcast_keeps_uneven_totals() {
+    let message = crate::Message::new(
+        "order_created".to_string(),
+        "order-service".to_string(),
+        "events.order_created".to_string(),
+        json!({
+            "order_id": uuid::Uuid::new_v4(),
+            "user_id": uuid::Uuid::new_v4(),
+            "product_name": "Cable",
+            "quantity": 3,
+            "total_price": 10.0,
+            "timestamp": time::OffsetDateTime::now_utc(),
+        }),
+    );
+
+    let (event, _) = crate::order_created_upcasters().decode::<crate::OrderCreatedEvent>(message).unwrap();
+
+    assert_eq!(event.total.amount_minor, 1000);
+    let quantities: Vec<i32> = event.line_items.iter().map(|item| item.quantity).collect();
+    let prices: Vec<i64> = event.line_items.iter().map(|item| item.unit_price.amount_minor).collect();
+    assert_eq!(quantities, vec![2, 1]);
+    assert_eq!(prices, vec![333, 334]);
+}
+
+#[test]
+fn test_upcast_rounds_float_totals_to_cents() {
+    let v1 = |total_price: f64| {
+        crate::Message::new(
+            "order_created".to_string(),
+            "order-service".to_string(),
+            "events.order_created".to_string(),
+            json!({
+                "order_id": uuid::Uuid::new_v4(),
+                "user_id": uuid::Uuid::new_v4(),
+                "product_name": "Cable",
+                "quantity": 1,
+                "total_price": total_price,
+                "timestamp": time::OffsetDateTime::now_utc(),
+            }),
+        )
+    };
+
+    let (event, _) = crate::order_created_upcasters().decode::<crate::OrderCreatedEvent>(v1(0.1 + 0.2)).unwrap();
+    assert_eq!(event.total, shared::Money::new(30, "USD").unwrap());
+
+    let result = crate::order_created_upcasters().decode::<crate::OrderCreatedEvent>(v1(1e300));
+    assert!(matches!(result, Err(crate::MessagingError::UpcastError(_))));
+}
+
+#[test]
+fn test_upcast_rejects_missing_totals() {
+    let message = crate::Message::new(
+        "order_created".to_string(),
+        "order-service".to_string(),
+        "events.order_created".to_string(),
+        json!({"product_name": "Cable", "quantity": 3}),
+    )
+    .with_header("schema-version".to_string(), "2".to_string());
+
+    assert!(matches!(
+        crate::order_created_upcasters().upcast(message, 3),
+        Err(crate::MessagingError::UpcastError(_))
+    ));
+}
+
+#[test]
+fn test_upcast_leaves_current_version_untouched() {
+    let event = sample_order_created();
+    let message = crate::Message::from_event("order-service", &event).unwrap();
+
+    let (decoded, _) = crate::UpcasterChain::new().decode::<crate::OrderCreatedEvent>(message).unwrap();
+
+    assert_eq!(decoded.order_id, event.order_id);
+}
+
+#[test]
+fn test_upcast_errors() {
+    let message = crate::Message::new(
+        "order_created".to_string(),
+        "order-service".to_string(),
+        "events.order_created".to_string(),
+        json!({}),
+    );
+
+    // No v1 -> v2 step registered.
+    let partial = crate::UpcasterChain::new()
+        .register("order_created", 2, Ok);
+    assert!(matches!(
+        partial.upcast(message.clone(), 3),
+        Err(crate::MessagingError::UpcastError(_))
+    ));
+
+    // Payloads from the future cannot be downgraded.
+    let newer = message.with_header("schema-version".to_string(), "4".to_string());
+    assert!(matches!(
+        crate::order_created_upcasters().upcast(newer, 3),
+        Err(crate::MessagingError::UpcastError(_))
+    ));
+}
+
+fn envelope_json(message: &crate::Message) -> serde_json::Value {
+    serde_json::to_value(message).unwrap()
+}
+
+fn cloudevent_sample() -> crate::Message {
+    crate::Message::new(
+        "order_created".to_string(),
+        "order-service".to_string(),
+        "events.order_created".to_string(),
+        json!({"order_id": uuid::Uuid::new_v4(), "note": "café \"quoted\" 100%"}),
+    )
+    .with_correlation(uuid::Uuid::new_v4())
+    .with_causation(uuid::Uuid::new_v4())
+    .with_header("schema-version".to_string(), "1".to_string())
+    .with_header("tenant".to_string(), "acme".to_string())
+    .with_header("X-Request-Id".to_string(), "req 42".to_string())
+}
+
+#[test]
+fn test_cloudevent_attributes() {
+    let message = cloudevent_sample();
+    let event = crate::CloudEvent::from(&message);
+
+    assert_eq!(event.specversion, "1.0");
+    assert_eq!(event.id, message.id.to_string());
+    assert_eq!(event.event_type, "order_created");
+    assert_eq!(event.source, "order-service");
+    assert_eq!(event.time, Some(message.timestamp));
+    assert_eq!(event.datacontenttype.as_deref(), Some("application/json"));
+    assert_eq!(event.extensions["correlationid"], message.correlation_id.unwrap().to_string());
+    assert_eq!(event.extensions["causationid"], message.causation_id.unwrap().to_string());
+    assert_eq!(event.extensions["schemaversion"], "1");
+    assert_eq!(event.extensions["tenant"], "acme");
+    assert!(crate::is_valid_attribute_name("correlationid"));
+    assert!(!crate::is_valid_attribute_name("X-Request-Id"));
+}
+
+#[test]
+fn test_cloudevent_structured_round_trip() {
+    let message = cloudevent_sample();
+
+    let body = crate::CloudEvent::from(&message).to_structured().unwrap();
+    let structured: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(structured["type"], "order_created");
+    assert!(structured["time"].as_str().is_some());
+
+    let restored = crate::Message::try_from(crate::CloudEvent::from_structured(&body).unwrap()).unwrap();
+    assert_eq!(envelope_json(&restored), envelope_json(&message));
+}
+
+#[test]
+fn test_cloudevent_nats_binary_round_trip() {
+    let message = cloudevent_sample();
+
+    let (headers, body) = crate::CloudEvent::from(&message).to_nats().unwrap();
+    assert_eq!(headers.get("ce-type").map(|v| v.as_str()), Some("order_created"));
+    assert_eq!(headers.get("content-type").map(|v| v.as_str()), Some("application/json"));
+
+    let restored = crate::Message::try_from(crate::CloudEvent::from_nats(&headers, &body).unwrap()).unwrap();
+    assert_eq!(envelope_json(&restored), envelope_json(&message));
+}
+
+#[test]
+fn test_cloudevent_http_binary_round_trip() {
+    let mut message = cloudevent_sample();
+    message.source = "/services/order service".to_string();
+
+    let (headers, body) = crate::CloudEvent::from(&message).to_http().unwrap();
+    assert_eq!(headers["ce-source"], "/services/order%20service");
+
+    let restored = crate::Message::try_from(crate::CloudEvent::from_http(&headers, &body).unwrap()).unwrap();
+    assert_eq!(envelope_json(&restored), envelope_json(&message));
+}
+
+#[test]
+fn test_foreign_cloudevent_round_trip() {
+    let body = br#"{
+        "specversion": "1.0",
+        "type": "com.example.invoice.paid",
+        "source": "https://billing.example.com",
+        "subject": "invoice-7",
+        "id": "5f0c1f0e-6b8a-4d0e-9a7e-2b1f3c4d5e6f",
+        "time": "2025-10-19T08:30:00Z",
+        "datacontenttype": "application/json",
+        "dataschema": "https://billing.example.com/schemas/invoice-paid.json",
+        "traceparent": "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        "data": {"invoice": 7}
+    }"#;
+    let event = crate::CloudEvent::from_structured(body).unwrap();
+
+    let message = crate::Message::try_from(event.clone()).unwrap();
+    assert_eq!(message.message_type, "com.example.invoice.paid");
+    assert_eq!(message.payload, json!({"invoice": 7}));
+    assert_eq!(message.headers["traceparent"], event.extensions["traceparent"]);
+
+    assert_eq!(crate::CloudEvent::from(&message), event);
+}
+
+#[test]
+fn test_cloudevent_rejects_invalid_events() {
+    let missing_id = [("ce-specversion", "1.0"), ("ce-source", "x"), ("ce-type", "t")];
+    assert!(matches!(
+        crate::CloudEvent::from_binary(missing_id, b""),
+        Err(crate::MessagingError::CloudEventError(_))
+    ));
+
+    let mut event = crate::CloudEvent::from(&cloudevent_sample());
+    event.datacontenttype = Some("application/xml".to_string());
+    assert!(crate::Message::try_from(event).is_err());
+}
+
+fn large_order_message() -> crate::Message {
+    let items: Vec<_> = (0..200)
+        .map(|i| json!({"sku": format!("SKU-{:04}", i), "quantity": i % 5 + 1, "unit_price": 1999}))
+        .collect();
+    let mut message = cloudevent_sample();
+    message.payload = json!({"order_id": uuid::Uuid::new_v4(), "line_items": items});
+    message
+}
+
+#[test]
+fn test_codecs_round_trip() {
+    let message = cloudevent_sample();
+    let codecs: Vec<Box<dyn crate::Codec>> = vec![
+        Box::new(crate::JsonCodec),
+        Box::new(crate::MessagePackCodec),
+        Box::new(crate::CborCodec),
+        Box::new(crate::ProtobufCodec),
+    ];
+
+    for codec in codecs {
+        let bytes = codec.encode(&message).unwrap();
+        let decoded = codec.decode(&bytes).unwrap();
+        assert_eq!(envelope_json(&decoded), envelope_json(&message), "{}", codec.content_type());
+
+        let lookup = crate::codec_for(codec.content_type()).unwrap();
+        assert_eq!(lookup.content_type(), codec.content_type());
+    }
+}
+
+#[test]
+fn test_encoded_message_headers_drive_decoding() {
+    let message = cloudevent_sample();
+
+    let (headers, bytes) = crate::encode_message(&message, &crate::CborCodec, None).unwrap();
+    assert_eq!(headers.get("content-type").map(|v| v.as_str()), Some("application/cbor"));
+    assert!(headers.get("content-encoding").is_none());
+
+    let decoded = crate::decode_message(Some(&headers), &bytes).unwrap();
+    assert_eq!(envelope_json(&decoded), envelope_json(&message));
+}
+
+#[test]
+fn test_compression_above_threshold() {
+    let compression = crate::Compression { threshold_bytes: 1024, level: 3 };
+
+    let small = cloudevent_sample();
+    let (headers, _) = crate::encode_message(&small, &crate::MessagePackCodec, Some(compression)).unwrap();
+    assert!(headers.get("content-encoding").is_none());
+
+    let large = large_order_message();
+    let uncompressed = crate::Codec::encode(&crate::MessagePackCodec, &large).unwrap();
+    let (headers, bytes) = crate::encode_message(&large, &crate::MessagePackCodec, Some(compression)).unwrap();
+    assert_eq!(headers.get("content-encoding").map(|v| v.as_str()), Some("zstd"));
+    assert!(bytes.len() < uncompressed.len());
+
+    let decoded = crate::decode_message(Some(&headers), &bytes).unwrap();
+    assert_eq!(envelope_json(&decoded), envelope_json(&large));
+}
+
+#[test]
+fn test_decompression_is_bounded() {
+    // A few kilobytes that would inflate to more than the limit
+    use std::io::Read;
+    let bomb = zstd::encode_all(std::io::repeat(0).take(crate::MAX_DECOMPRESSED_BYTES as u64 + 1), 19).unwrap();
+    assert!(bomb.len() < 64 * 1024);
+
+    let mut headers = async_nats::HeaderMap::new();
+    headers.insert("content-type", "application/json");
+    headers.insert("content-encoding", "zstd");
+    assert!(matches!(
+        crate::decode_message(Some(&headers), &bomb),
+        Err(crate::MessagingError::CodecError(e)) if e.contains("expands beyond")
+    ));
+}
+
+#[test]
+fn test_decode_message_defaults_and_errors() {
+    let message = cloudevent_sample();
+
+    // Publishers that predate codecs sent bare JSON without headers.
+    let legacy = serde_json::to_vec(&message).unwrap();
+    let decoded = crate::decode_message(None, &legacy).unwrap();
+    assert_eq!(envelope_json(&decoded), envelope_json(&message));
+
+    let mut headers = async_nats::HeaderMap::new();
+    headers.insert("content-type", "application/xml");
+    assert!(matches!(
+        crate::decode_message(Some(&headers), &legacy),
+        Err(crate::MessagingError::CodecError(_))
+    ));
+}
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct GetUser {
+    user_id: u32,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct UserName {
+    username: String,
+}
+
+#[test]
+fn test_request_reply_round_trip() {
+    let request = crate::build_request(
+        "rpc.users.get",
+        "order-service",
+        &GetUser { user_id: 7 },
+        crate::DEFAULT_REQUEST_TIMEOUT,
+    )
+    .unwrap();
+    assert_eq!(request.correlation_id, Some(request.id));
+    assert!(request.headers.contains_key(crate::DEADLINE_HEADER));
+    assert!(!crate::deadline_expired(&request));
+
+    let reply = crate::build_reply(&request, Ok(UserName { username: "alice".to_string() })).unwrap();
+    assert_eq!(reply.causation_id, Some(request.id));
+    assert_eq!(reply.destination, "order-service");
+
+    let body: UserName = crate::parse_reply(&request, reply).unwrap();
+    assert_eq!(body.username, "alice");
+}
+
+#[test]
+fn test_remote_errors_and_mismatched_replies() {
+    let request = crate::build_request("rpc.users.get", "order-service", &GetUser { user_id: 7 }, crate::DEFAULT_REQUEST_TIMEOUT).unwrap();
+
+    let error = crate::RpcError::new("not_found", "User 7 not found").with_details(json!({"user_id": 7}));
+    let reply = crate::build_reply::<UserName>(&request, Err(error.clone())).unwrap();
+    match crate::parse_reply::<UserName>(&request, reply) {
+        Err(crate::MessagingError::Remote(remote)) => assert_eq!(remote, error),
+        other => panic!("expected remote error, got {:?}", other),
+    }
+
+    let other = crate::build_request("rpc.users.get", "order-service", &GetUser { user_id: 8 }, crate::DEFAULT_REQUEST_TIMEOUT).unwrap();
+    let stray = crate::build_reply(&other, Ok(UserName { username: "bob".to_string() })).unwrap();
+    assert!(crate::parse_reply::<UserName>(&request, stray).is_err());
+}
+
+#[tokio::test]
+async fn test_handle_request() {
+    let handler = |req: GetUser, _: crate::Message| async move {
+        if req.user_id == 7 {
+            Ok(UserName { username: "alice".to_string() })
+        } else {
+            Err(crate::RpcError::new("not_found", "no such user"))
+        }
+    };
+
+    let request = crate::build_request("rpc.users.get", "web-bff", &GetUser { user_id: 7 }, crate::DEFAULT_REQUEST_TIMEOUT).unwrap();
+    let reply = crate::handle_request(request.clone(), &handler).await.unwrap().unwrap();
+    assert_eq!(crate::parse_reply::<UserName>(&request, reply).unwrap().username, "alice");
+
+    let bad = crate::build_request("rpc.users.get", "web-bff", &json!({"user": "x"}), crate::DEFAULT_REQUEST_TIMEOUT).unwrap();
+    let reply = crate::handle_request(bad.clone(), &handler).await.unwrap().unwrap();
+    match crate::parse_reply::<UserName>(&bad, reply) {
+        Err(crate::MessagingError::Remote(remote)) => assert_eq!(remote.code, "bad_request"),
+        other => panic!("expected bad_request, got {:?}", other),
+    }
+
+    let mut expired = crate::build_request("rpc.users.get", "web-bff", &GetUser { user_id: 7 }, crate::DEFAULT_REQUEST_TIMEOUT).unwrap();
+    expired.headers.insert(crate::DEADLINE_HEADER.to_string(), "0".to_string());
+    assert!(crate::deadline_expired(&expired));
+    assert!(crate::handle_request(expired, &handler).await.unwrap().is_none());
+}
+
+fn user_events(count: usize, users: usize) -> Vec<crate::Message> {
+    (0..count)
+        .map(|seq| {
+            crate::Message::new(
+                "user_updated".to_string(),
+                "user-service".to_string(),
+                "events.user_updated".to_string(),
+                json!({"user_id": format!("user-{}", seq % users), "seq": seq}),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_dispatch_respects_concurrency_limit() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let in_flight = std::sync::Arc::new(AtomicUsize::new(0));
+    let peak = std::sync::Arc::new(AtomicUsize::new(0));
+    let handled = std::sync::Arc::new(AtomicUsize::new(0));
+    let (in_flight_h, peak_h, handled_h) = (in_flight.clone(), peak.clone(), handled.clone());
+
+    let options = crate::SubscriptionOptions::new().concurrency(3);
+    let stream = futures::stream::iter(user_events(20, 5));
+    let handle = crate::subscription::spawn_subscription("events.user_updated", stream, options.concurrency, None, move |_| {
+        let (in_flight, peak, handled) = (in_flight_h.clone(), peak_h.clone(), handled_h.clone());
+        async move {
+            let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            handled.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    });
+    let status = handle.closed().await;
+
+    assert_eq!(status.state, crate::SubscriptionState::Closed);
+    assert_eq!(status.handled, 20);
+    assert_eq!(handled.load(Ordering::SeqCst), 20);
+    assert!(peak.load(Ordering::SeqCst) <= 3);
+    assert!(peak.load(Ordering::SeqCst) > 1);
+}
+
+#[tokio::test]
+async fn test_dispatch_treats_zero_concurrency_as_one() {
+    assert_eq!(crate::SubscriptionOptions::new().concurrency(0).concurrency, 1);
+
+    // Called directly, with and without per-key ordering
+    let key = crate::SubscriptionOptions::new().ordered_by_field("user_id").ordering_key;
+    for key in [None, key] {
+        let stream = futures::stream::iter(user_events(5, 2));
+        let handle = crate::subscription::spawn_subscription("events.user_updated", stream, 0, key, |_| async { Ok(()) });
+        let status = tokio::time::timeout(std::time::Duration::from_secs(5), handle.closed()).await.unwrap();
+        assert_eq!(status.handled, 5);
+    }
+}
+
+#[tokio::test]
+async fn test_dispatch_preserves_order_per_key() {
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(HashMap::<String, Vec<u64>>::new()));
+    let seen_h = seen.clone();
+
+    let options = crate::SubscriptionOptions::new().concurrency(4).ordered_by_field("user_id");
+    let stream = futures::stream::iter(user_events(40, 6));
+    let key = options.ordering_key.clone();
+    let handle = crate::subscription::spawn_subscription("events.user_updated", stream, options.concurrency, key, move |message: crate::Message| {
+        let seen = seen_h.clone();
+        async move {
+            let seq = message.payload["seq"].as_u64().unwrap();
+            // Later messages finish faster, so anything but per-key
+            // sequencing would reorder them.
+            tokio::time::sleep(std::time::Duration::from_millis(40 - seq)).await;
+            let user = message.payload["user_id"].as_str().unwrap().to_string();
+            seen.lock().unwrap().entry(user).or_default().push(seq);
+            Ok(())
+        }
+    });
+    handle.closed().await;
+
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.values().map(Vec::len).sum::<usize>(), 40);
+    for sequence in seen.values() {
+        assert!(sequence.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", sequence);
+    }
+}
+
+#[tokio::test]
+async fn test_panicking_handlers_count_as_failures() {
+    // One lane, so a panic that killed it would strand the rest
+    let key = crate::SubscriptionOptions::new().ordered_by_field("user_id").ordering_key;
+    for key in [None, key] {
+        let stream = futures::stream::iter(user_events(6, 2));
+        let handle = crate::subscription::spawn_subscription("events.user_updated", stream, 1, key, |message: crate::Message| async move {
+            if message.payload["seq"] == json!(0) {
+                panic!("handler bug");
+            }
+            Ok(())
+        });
+        let status = tokio::time::timeout(std::time::Duration::from_secs(5), handle.closed()).await.unwrap();
+        assert_eq!(status.handled, 5);
+        assert_eq!(status.failed, 1);
+        assert_eq!(status.in_flight, 0);
+    }
+}
+
+#[tokio::test]
+async fn test_drain_finishes_in_flight_handlers() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // The stream never ends on its own, like a live NATS subscription.
+    let stream = futures::StreamExt::chain(futures::stream::iter(user_events(2, 2)), futures::stream::pending());
+    let finished = std::sync::Arc::new(AtomicUsize::new(0));
+    let finished_h = finished.clone();
+    let handle = crate::subscription::spawn_subscription("events.user_updated", stream, 2, None, move |_| {
+        let finished = finished_h.clone();
+        async move {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            finished.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    });
+
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let status = handle.status();
+    assert_eq!(status.state, crate::SubscriptionState::Active);
+    assert_eq!(status.in_flight, 2);
+
+    let subscriptions = crate::Subscriptions::new();
+    subscriptions.add(handle).await;
+    let statuses = subscriptions.drain_all().await;
+    assert_eq!(statuses.len(), 1);
+    assert_eq!(statuses[0].state, crate::SubscriptionState::Closed);
+    assert_eq!(statuses[0].handled, 2);
+    assert_eq!(statuses[0].in_flight, 0);
+    assert_eq!(finished.load(Ordering::SeqCst), 2);
+    assert!(subscriptions.statuses().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_drain_counts_buffered_messages_while_slots_are_busy() {
+    // An ordered lane also holds one queued message, which still gets handled
+    let key = crate::SubscriptionOptions::new().ordered_by_field("user_id").ordering_key;
+    for (key, handled) in [(None, 1), (key, 2)] {
+        let stream = futures::StreamExt::chain(futures::stream::iter(user_events(4, 1)), futures::stream::pending());
+        let handle = crate::subscription::spawn_subscription("events.user_updated", stream, 1, key, |_| async {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            Ok(())
+        });
+
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert_eq!(handle.status().in_flight, 1);
+
+        let status = tokio::time::timeout(std::time::Duration::from_secs(5), handle.drain()).await.unwrap();
+        assert_eq!(status.handled, handled);
+        assert_eq!(status.dropped, 4 - handled);
+        assert_eq!(status.state, crate::SubscriptionState::Closed);
+    }
+}
+
+#[tokio::test]
+async fn test_unsubscribe_abandons_in_flight_handlers() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let stream = futures::StreamExt::chain(futures::stream::iter(user_events(1, 1)), futures::stream::pending());
+    let finished = std::sync::Arc::new(AtomicUsize::new(0));
+    let finished_h = finished.clone();
+    let handle = crate::subscription::spawn_subscription("events.user_updated", stream, 1, None, move |_| {
+        let finished = finished_h.clone();
+        async move {
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            finished.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    });
+
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let status = handle.unsubscribe().await;
+    assert_eq!(status.state, crate::SubscriptionState::Closed);
+    assert_eq!(status.handled, 0);
+    assert_eq!(finished.load(Ordering::SeqCst), 0);
+}
+
+type SagaLog = std::sync::Arc<std::sync::Mutex<Vec<String>>>;
+
+/// A saga step that fails its first `failures` attempts with `error`.
+struct ScriptedStep {
+    name: &'static str,
+    log: SagaLog,
+    failures: std::sync::atomic::AtomicU32,
+    error: fn() -> crate::MessagingError,
+}
+
+impl ScriptedStep {
+    fn ok(name: &'static str, log: &SagaLog) -> Self {
+        Self::failing(name, log, 0, || crate::MessagingError::Timeout("unused".to_string()))
+    }
+
+    fn failing(name: &'static str, log: &SagaLog, failures: u32, error: fn() -> crate::MessagingError) -> Self {
+        Self {
+            name,
+            log: log.clone(),
+            failures: std::sync::atomic::AtomicU32::new(failures),
+            error,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::SagaStep for ScriptedStep {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    async fn execute(&self, ctx: &crate::SagaContext) -> Result<serde_json::Value, crate::MessagingError> {
+        self.log.lock().unwrap().push(format!("execute {}", self.name));
+        let remaining = self.failures.load(std::sync::atomic::Ordering::SeqCst);
+        if remaining > 0 {
+            self.failures.store(remaining - 1, std::sync::atomic::Ordering::SeqCst);
+            return Err((self.error)());
+        }
+        Ok(json!({"step": self.name, "order_id": ctx.input["order_id"]}))
+    }
+
+    async fn compensate(&self, ctx: &crate::SagaContext) -> Result<(), crate::MessagingError> {
+        assert!(ctx.output(self.name).is_some());
+        self.log.lock().unwrap().push(format!("compensate {}", self.name));
+        Ok(())
+    }
+}
+
+async fn saga_store() -> crate::SagaStore {
+    let pool = shared::db::connect("sqlite::memory:").await.unwrap();
+    let store = crate::SagaStore::new(pool);
+    store.init_schema().await.unwrap();
+    store
+}
+
+fn fast_retry() -> crate::RetryPolicy {
+    crate::RetryPolicy {
+        max_attempts: 3,
+        timeout: std::time::Duration::from_millis(100),
+        backoff: std::time::Duration::from_millis(1),
+    }
+}
+
+#[tokio::test]
+async fn test_saga_completes_with_retries() {
+    let log = SagaLog::default();
+    let definition = crate::SagaDefinition::new("order_placement")
+        .step_with(ScriptedStep::ok("reserve_inventory", &log), fast_retry())
+        .step_with(
+            ScriptedStep::failing("authorize_payment", &log, 2, || crate::MessagingError::NoResponders("payments".to_string())),
+            fast_retry(),
+        )
+        .step_with(ScriptedStep::ok("confirm_order", &log), fast_retry());
+    let orchestrator = crate::SagaOrchestrator::new(definition, saga_store().await);
+
+    let correlation_id = uuid::Uuid::new_v4();
+    let saga = orchestrator.execute(correlation_id, json!({"order_id": "o-1"})).await.unwrap();
+
+    assert_eq!(saga.state, crate::SagaState::Completed);
+    assert_eq!(saga.steps[1].attempts, 3);
+    assert!(saga.steps.iter().all(|step| step.state == crate::StepState::Completed));
+    assert_eq!(saga.steps[0].output, Some(json!({"step": "reserve_inventory", "order_id": "o-1"})));
+
+    let stored = orchestrator.status(correlation_id).await.unwrap().unwrap();
+    assert_eq!(stored.state, crate::SagaState::Completed);
+    assert_eq!(stored.steps, saga.steps);
+    assert!(orchestrator.status(uuid::Uuid::new_v4()).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_saga_compensates_completed_steps_in_reverse() {
+    let log = SagaLog::default();
+    let definition = crate::SagaDefinition::new("order_placement")
+        .step_with(ScriptedStep::ok("reserve_inventory", &log), fast_retry())
+        .step_with(ScriptedStep::ok("hold_shipping", &log), fast_retry())
+        .step_with(
+            ScriptedStep::failing("authorize_payment", &log, 1, || {
+                crate::MessagingError::Remote(crate::RpcError::new("declined", "card declined"))
+            }),
+            fast_retry(),
         );
+    let orchestrator = crate::SagaOrchestrator::new(definition, saga_store().await);
+
+    let saga = orchestrator.execute(uuid::Uuid::new_v4(), json!({"order_id": "o-2"})).await.unwrap();
+
+    assert_eq!(saga.state, crate::SagaState::Compensated);
+    // Remote errors are business decisions and are not retried.
+    assert_eq!(saga.steps[2].attempts, 1);
+    assert_eq!(saga.steps[2].state, crate::StepState::Failed);
+    assert!(saga.error.as_deref().unwrap().contains("card declined"));
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "execute reserve_inventory",
+            "execute hold_shipping",
+            "execute authorize_payment",
+            "compensate hold_shipping",
+            "compensate reserve_inventory",
+        ]
+    );
+}
+
+struct SlowStep;
+
+#[async_trait::async_trait]
+impl crate::SagaStep for SlowStep {
+    fn name(&self) -> &str {
+        "slow"
+    }
+
+    async fn execute(&self, _: &crate::SagaContext) -> Result<serde_json::Value, crate::MessagingError> {
+        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        Ok(json!(null))
+    }
+}
+
+#[tokio::test]
+async fn test_saga_step_timeout_and_resume() {
+    let log = SagaLog::default();
+    let store = saga_store().await;
+    let retry = crate::RetryPolicy {
+        max_attempts: 2,
+        timeout: std::time::Duration::from_millis(20),
+        backoff: std::time::Duration::from_millis(1),
+    };
+    let definition = crate::SagaDefinition::new("slow_saga")
+        .step_with(ScriptedStep::ok("reserve_inventory", &log), retry)
+        .step_with(SlowStep, retry);
+    let saga = crate::SagaOrchestrator::new(definition, store.clone())
+        .execute(uuid::Uuid::new_v4(), json!({"order_id": "o-3"}))
+        .await
+        .unwrap();
+    assert_eq!(saga.state, crate::SagaState::Compensated);
+    assert_eq!(saga.steps[1].attempts, 2);
+    assert!(saga.steps[1].error.as_deref().unwrap().contains("timed out"));
+
+    // A saga interrupted after its first step picks up where it left off.
+    let mut interrupted = saga.clone();
+    interrupted.correlation_id = uuid::Uuid::new_v4();
+    interrupted.saga_type = "order_placement".to_string();
+    interrupted.state = crate::SagaState::Running;
+    interrupted.error = None;
+    interrupted.steps[0].state = crate::StepState::Completed;
+    interrupted.steps[1] = crate::StepRecord {
+        name: "confirm_order".to_string(),
+        state: crate::StepState::Pending,
+        attempts: 0,
+        output: None,
+        error: None,
+    };
+    store.insert(&interrupted).await.unwrap();
+
+    log.lock().unwrap().clear();
+    let definition = crate::SagaDefinition::new("order_placement")
+        .step_with(ScriptedStep::ok("reserve_inventory", &log), retry)
+        .step_with(ScriptedStep::ok("confirm_order", &log), retry);
+    let resumed = crate::SagaOrchestrator::new(definition, store.clone()).resume_unfinished().await.unwrap();
+
+    assert_eq!(resumed.len(), 1);
+    assert_eq!(resumed[0].state, crate::SagaState::Completed);
+    assert_eq!(*log.lock().unwrap(), vec!["execute confirm_order"]);
+    assert!(store.unfinished("order_placement").await.unwrap().is_empty());
+}
+
+async fn event_store() -> crate::EventStore {
+    let pool = shared::db::connect("sqlite::memory:").await.unwrap();
+    let store = crate::EventStore::new(pool);
+    store.init_schema().await.unwrap();
+    store
+}
+
+#[tokio::test]
+async fn test_event_store_append_and_read() {
+    use crate::ExpectedVersion;
+
+    let store = event_store().await;
+    let placed = |n: u32| crate::NewEvent::new("order_placed", json!({"n": n}));
+
+    assert_eq!(store.append("order-1", ExpectedVersion::NoStream, vec![placed(1), placed(2)]).await.unwrap(), 2);
+    assert_eq!(store.append("order-2", ExpectedVersion::Any, vec![placed(3)]).await.unwrap(), 1);
+    assert_eq!(store.append("order-1", ExpectedVersion::Exact(2), vec![placed(4)]).await.unwrap(), 3);
+
+    match store.append("order-1", ExpectedVersion::Exact(2), vec![placed(5)]).await {
+        Err(crate::MessagingError::ConcurrencyConflict { actual, .. }) => assert_eq!(actual, 3),
+        other => panic!("expected a conflict, got {:?}", other),
+    }
+    assert!(store.append("order-2", ExpectedVersion::NoStream, vec![placed(6)]).await.is_err());
+
+    let stream = store.read_stream("order-1", 1).await.unwrap();
+    assert_eq!(stream.iter().map(|e| e.version).collect::<Vec<_>>(), vec![2, 3]);
+    assert_eq!(stream[1].payload, json!({"n": 4}));
+
+    let feed = store.read_all(0, 10).await.unwrap();
+    assert_eq!(
+        feed.iter().map(|e| (e.stream_id.as_str(), e.payload["n"].as_u64().unwrap())).collect::<Vec<_>>(),
+        vec![("order-1", 1), ("order-1", 2), ("order-2", 3), ("order-1", 4)]
+    );
+    assert!(feed.windows(2).all(|pair| pair[0].global_position < pair[1].global_position));
+    assert_eq!(store.head_position().await.unwrap(), feed[3].global_position);
+
+    let mut live = Box::pin(store.subscribe_all(feed[1].global_position, std::time::Duration::from_millis(5)));
+    assert_eq!(futures::StreamExt::next(&mut live).await.unwrap().unwrap().payload, json!({"n": 3}));
+    assert_eq!(futures::StreamExt::next(&mut live).await.unwrap().unwrap().payload, json!({"n": 4}));
+    store.append("order-3", ExpectedVersion::Any, vec![placed(7)]).await.unwrap();
+    assert_eq!(futures::StreamExt::next(&mut live).await.unwrap().unwrap().payload, json!({"n": 7}));
+}
+
+#[tokio::test]
+async fn test_concurrent_appends_conflict() {
+    use crate::ExpectedVersion;
+
+    // A file, so the appends run on connections of their own
+    let path = std::env::temp_dir().join(format!("events-{}.db", uuid::Uuid::new_v4()));
+    let pool = shared::db::connect(&format!("sqlite:{}?mode=rwc", path.display())).await.unwrap();
+    let store = crate::EventStore::new(pool);
+    store.init_schema().await.unwrap();
+
+    for n in 0..20 {
+        let stream = format!("order-{}", n);
+        let append = |writer: &str| store.append(&stream, ExpectedVersion::NoStream, vec![crate::NewEvent::new("order_placed", json!({"writer": writer}))]);
+        let (first, second) = tokio::join!(append("a"), append("b"));
+
+        // Whoever lost is told so, whichever way SQLite noticed
+        let (won, lost) = match (first, second) {
+            (Ok(version), Err(e)) | (Err(e), Ok(version)) => (version, e),
+            other => panic!("expected one append to win, got {:?}", other),
+        };
+        assert_eq!(won, 1);
+        assert!(matches!(lost, crate::MessagingError::ConcurrencyConflict { actual: 1, .. }), "{:?}", lost);
+    }
+
+    std::fs::remove_file(&path).ok();
+}
+
+#[derive(Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+struct Tally {
+    total: i64,
+    applied: u32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+enum TallyEvent {
+    Added(i64),
+}
+
+impl crate::Aggregate for Tally {
+    const TYPE: &'static str = "tally";
+    type Event = TallyEvent;
+
+    fn event_type(_: &TallyEvent) -> &'static str {
+        "added"
+    }
+
+    fn apply(&mut self, event: &TallyEvent) {
+        let TallyEvent::Added(n) = event;
+        self.total += n;
+        self.applied += 1;
+    }
+}
+
+#[tokio::test]
+async fn test_aggregate_repository_with_snapshots() {
+    let store = event_store().await;
+    let repository = crate::AggregateRepository::<Tally>::new(store.clone()).with_snapshots(3);
+    assert!(repository.load("a").await.unwrap().is_none());
+
+    let (tally, version) = repository.save("a", Tally::default(), 0, vec![TallyEvent::Added(1), TallyEvent::Added(2)]).await.unwrap();
+    assert_eq!((tally.total, version), (3, 2));
+    assert!(store.load_snapshot("tally-a").await.unwrap().is_none());
+
+    let (tally, version) = repository.save("a", tally, 2, vec![TallyEvent::Added(4)]).await.unwrap();
+    assert_eq!(version, 3);
+    let snapshot = store.load_snapshot("tally-a").await.unwrap().unwrap();
+    assert_eq!(snapshot.version, 3);
+
+    assert_eq!(serde_json::from_value::<Tally>(snapshot.state).unwrap(), Tally { total: 7, applied: 3 });
+
+    repository.save("a", tally, 3, vec![TallyEvent::Added(8)]).await.unwrap();
+    let (loaded, version) = repository.load("a").await.unwrap().unwrap();
+    assert_eq!(version, 4);
+    assert_eq!(loaded, Tally { total: 15, applied: 4 });
+
+    // Earlier versions replay from the start when the snapshot is past them
+    assert_eq!(repository.load_at("a", 2).await.unwrap(), Some(Tally { total: 3, applied: 2 }));
+    assert_eq!(repository.load_at("a", 4).await.unwrap(), Some(Tally { total: 15, applied: 4 }));
+    assert_eq!(repository.load_at("a", 5).await.unwrap(), None);
+
+    // Loading starts from the snapshot and replays only the later events.
+    store.save_snapshot("tally-a", 3, &json!({"total": 100, "applied": 0})).await.unwrap();
+    let (loaded, _) = repository.load("a").await.unwrap().unwrap();
+    assert_eq!(loaded, Tally { total: 108, applied: 1 });
+
+    assert!(matches!(
+        repository.save("a", Tally::default(), 3, vec![TallyEvent::Added(1)]).await,
+        Err(crate::MessagingError::ConcurrencyConflict { .. })
+    ));
+}
+
+/// Keeps one row per event type, so a second event of a type violates its key.
+struct FirstOfType;
+
+#[async_trait::async_trait]
+impl crate::Projection for FirstOfType {
+    fn name(&self) -> &str {
+        "first_of_type"
+    }
+
+    async fn init(&self, conn: &mut sqlx::AnyConnection) -> Result<(), crate::MessagingError> {
+        sqlx::query("CREATE TABLE IF NOT EXISTS first_of_type (event_type TEXT PRIMARY KEY)")
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn apply(&self, conn: &mut sqlx::AnyConnection, event: &crate::RecordedEvent) -> Result<(), crate::MessagingError> {
+        sqlx::query("INSERT INTO first_of_type (event_type) VALUES ($1)")
+            .bind(&event.event_type)
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn reset(&self, conn: &mut sqlx::AnyConnection) -> Result<(), crate::MessagingError> {
+        sqlx::query("DELETE FROM first_of_type").execute(conn).await?;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_projection_unique_violation_is_not_a_conflict() {
+    let store = event_store().await;
+    let mut conn = store.pool().acquire().await.unwrap();
+    crate::Projection::init(&FirstOfType, &mut conn).await.unwrap();
+    drop(conn);
+
+    let placed = || vec![crate::NewEvent::new("order_placed", json!({}))];
+    store.append_with("order-1", crate::ExpectedVersion::NoStream, placed(), &FirstOfType).await.unwrap();
+
+    let error = store
+        .append_with("order-2", crate::ExpectedVersion::NoStream, placed(), &FirstOfType)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, crate::MessagingError::DatabaseError(_)), "{:?}", error);
+    assert!(store.read_stream("order-2", 0).await.unwrap().is_empty());
+
+    // A duplicate version is still a conflict.
+    assert!(matches!(
+        store.append("order-1", crate::ExpectedVersion::NoStream, placed()).await,
+        Err(crate::MessagingError::ConcurrencyConflict { .. })
+    ));
+}
+
+/// Counts feed events per type in a `event_counts` read table.
+struct EventCounts;
+
+#[async_trait::async_trait]
+impl crate::Projection for EventCounts {
+    fn name(&self) -> &str {
+        "event_counts"
+    }
+
+    async fn init(&self, conn: &mut sqlx::AnyConnection) -> Result<(), crate::MessagingError> {
+        sqlx::query("CREATE TABLE IF NOT EXISTS event_counts (event_type TEXT PRIMARY KEY, count BIGINT NOT NULL)")
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn apply(&self, conn: &mut sqlx::AnyConnection, event: &crate::RecordedEvent) -> Result<(), crate::MessagingError> {
+        sqlx::query(
+            "INSERT INTO event_counts (event_type, count) VALUES ($1, 1)
+             ON CONFLICT (event_type) DO UPDATE SET count = event_counts.count + 1",
+        )
+        .bind(&event.event_type)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn reset(&self, conn: &mut sqlx::AnyConnection) -> Result<(), crate::MessagingError> {
+        sqlx::query("DELETE FROM event_counts").execute(conn).await?;
+        Ok(())
+    }
+}
+
+async fn event_counts(store: &crate::EventStore) -> Vec<(String, i64)> {
+    use sqlx::Row;
+    sqlx::query("SELECT event_type, count FROM event_counts ORDER BY event_type")
+        .fetch_all(store.pool())
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| (row.get("event_type"), row.get("count")))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_projector_checkpoints_lag_and_rebuild() {
+    let store = event_store().await;
+    let projector = crate::Projector::new(store.clone(), EventCounts).with_batch_size(2);
+    projector.init().await.unwrap();
+
+    let user_created = crate::Message::new(
+        "user_created".to_string(),
+        "user-service".to_string(),
+        "events.user_created".to_string(),
+        json!({"username": "alice"}),
+    );
+    assert!(store.record_message(&user_created).await.unwrap());
+    assert!(!store.record_message(&user_created).await.unwrap());
+    for _ in 0..4 {
+        store
+            .append("order-1", crate::ExpectedVersion::Any, vec![crate::NewEvent::new("order_placed", json!({}))])
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(projector.lag().await.unwrap(), 5);
+    assert_eq!(projector.catch_up().await.unwrap(), 5);
+    assert_eq!(projector.lag().await.unwrap(), 0);
+    assert_eq!(projector.checkpoint().await.unwrap(), store.head_position().await.unwrap());
+    assert_eq!(
+        observability::gauge(crate::PROJECTION_LAG_METRIC, &[("projection", "event_counts")]),
+        Some(0.0)
+    );
+    assert_eq!(event_counts(&store).await, vec![("order_placed".to_string(), 4), ("user_created".to_string(), 1)]);
+
+    // Nothing new: catching up again applies nothing twice.
+    assert_eq!(projector.catch_up().await.unwrap(), 0);
+    store
+        .append("order-2", crate::ExpectedVersion::Any, vec![crate::NewEvent::new("order_placed", json!({}))])
+        .await
+        .unwrap();
+    assert_eq!(projector.lag().await.unwrap(), 1);
+
+    assert_eq!(projector.rebuild().await.unwrap(), 6);
+    assert_eq!(event_counts(&store).await, vec![("order_placed".to_string(), 5), ("user_created".to_string(), 1)]);
+}
+
+fn reminder(user: &str) -> crate::Message {
+    crate::Message::new(
+        "ReminderEmail".to_string(),
+        "test".to_string(),
+        "notification-service".to_string(),
+        json!({ "user": user }),
+    )
+}
+
+#[tokio::test]
+async fn test_scheduler_delivers_when_due_and_survives_restart() {
+    let pool = shared::db::connect("sqlite::memory:").await.unwrap();
+    let start = time::OffsetDateTime::from_unix_timestamp(1_704_110_400).unwrap();
+    let clock = crate::ManualClock::new(start);
+    let scheduler = crate::Scheduler::new(pool.clone()).with_clock(clock.clone());
+    scheduler.init_schema().await.unwrap();
+
+    let soon = scheduler
+        .schedule("commands.email", &reminder("alice"), start + std::time::Duration::from_secs(30 * 60))
+        .await
+        .unwrap();
+    let later = scheduler
+        .schedule("commands.email", &reminder("bob"), start + std::time::Duration::from_secs(60 * 60))
+        .await
+        .unwrap();
+
+    let sent = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let deliver = |subject: String, message: crate::Message| {
+        let sent = sent.clone();
+        async move {
+            sent.lock().unwrap().push((subject, message.id));
+            Ok(())
+        }
+    };
+
+    assert_eq!(scheduler.dispatch_due(deliver).await.unwrap(), 0);
+
+    clock.advance(std::time::Duration::from_secs(30 * 60));
+    assert_eq!(scheduler.dispatch_due(deliver).await.unwrap(), 1);
+    assert_eq!(*sent.lock().unwrap(), vec![("commands.email".to_string(), soon)]);
+    assert_eq!(scheduler.get(soon).await.unwrap().unwrap().state, crate::ScheduleState::Delivered);
+
+    // A new scheduler over the same table, as after a restart, still has the
+    // second message
+    let restarted = crate::Scheduler::new(pool).with_clock(clock.clone());
+    clock.advance(std::time::Duration::from_secs(60 * 60));
+    assert_eq!(restarted.dispatch_due(deliver).await.unwrap(), 1);
+    assert_eq!(sent.lock().unwrap().last().unwrap().1, later);
+    assert_eq!(restarted.dispatch_due(deliver).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_scheduler_cancel_and_retry() {
+    let pool = shared::db::connect("sqlite::memory:").await.unwrap();
+    let start = time::OffsetDateTime::from_unix_timestamp(1_704_110_400).unwrap();
+    let clock = crate::ManualClock::new(start);
+    let scheduler = crate::Scheduler::new(pool)
+        .with_clock(clock.clone())
+        .with_claim_timeout(std::time::Duration::from_secs(60));
+    scheduler.init_schema().await.unwrap();
+
+    let cancelled = scheduler.schedule("commands.expire", &reminder("alice"), start).await.unwrap();
+    let flaky = scheduler.schedule("commands.expire", &reminder("bob"), start).await.unwrap();
+    assert!(scheduler.cancel(cancelled).await.unwrap());
+    assert!(!scheduler.cancel(cancelled).await.unwrap());
+    assert!(!scheduler.cancel(uuid::Uuid::new_v4()).await.unwrap());
+
+    let failing = |_: String, _: crate::Message| async { Err(crate::MessagingError::PublishError("nats down".to_string())) };
+    assert_eq!(scheduler.dispatch_due(failing).await.unwrap(), 0);
+    let pending = scheduler.get(flaky).await.unwrap().unwrap();
+    assert_eq!(pending.state, crate::ScheduleState::Pending);
+    assert_eq!(pending.attempts, 1);
+    assert!(pending.last_error.unwrap().contains("nats down"));
+
+    // The failed message waits out its claim before the next attempt
+    let ok = |_: String, _: crate::Message| async { Ok(()) };
+    assert_eq!(scheduler.dispatch_due(ok).await.unwrap(), 0);
+    clock.advance(std::time::Duration::from_secs(60));
+    assert_eq!(scheduler.dispatch_due(ok).await.unwrap(), 1);
+    assert!(!scheduler.cancel(flaky).await.unwrap());
+    assert_eq!(scheduler.get(cancelled).await.unwrap().unwrap().state, crate::ScheduleState::Cancelled);
+}
+
+fn user_created_message() -> crate::Message {
+    let event = crate::UserCreatedEvent {
+        user_id: uuid::Uuid::new_v4(),
+        username: "alice".to_string(),
+        email: "alice@example.com".to_string(),
+        timestamp: time::OffsetDateTime::now_utc(),
+    };
+    crate::Message::from_event("user-service", &event).unwrap()
+}
+
+#[test]
+fn test_hmac_signature_survives_codecs_and_detects_tampering() {
+    let signer = crate::MessageSigner::hmac("user-2024", b"shared-secret".to_vec());
+    let trusted = crate::TrustedKeys::new()
+        .require_signatures(true)
+        .trust_hmac("user-service", "user-2024", b"shared-secret".to_vec());
+
+    let signed = signer.sign(user_created_message()).unwrap();
+    trusted.verify(&signed).unwrap();
+
+    for codec in [&crate::MessagePackCodec as &dyn crate::Codec, &crate::CborCodec, &crate::ProtobufCodec] {
+        let (headers, bytes) = crate::encode_message(&signed, codec, None).unwrap();
+        let decoded = crate::decode_message(Some(&headers), &bytes).unwrap();
+        trusted.verify(&decoded).unwrap();
+    }
+
+    let mut tampered = signed.clone();
+    tampered.payload["email"] = json!("mallory@example.com");
+    assert!(matches!(trusted.verify(&tampered), Err(crate::MessagingError::SignatureError(_))));
+
+    let mut tampered = signed.clone();
+    tampered.headers.insert("x-extra".to_string(), "1".to_string());
+    assert!(trusted.verify(&tampered).is_err());
+
+    // The key is bound to user-service, so another source cannot use it
+    let mut spoofed = user_created_message();
+    spoofed.source = "order-service".to_string();
+    assert!(trusted.verify(&signer.sign(spoofed).unwrap()).is_err());
+
+    let wrong_secret = crate::MessageSigner::hmac("user-2024", b"guessed".to_vec());
+    assert!(trusted.verify(&wrong_secret.sign(user_created_message()).unwrap()).is_err());
+}
+
+#[test]
+fn test_ed25519_signatures_and_trusted_keys_config() {
+    let key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+    let public = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, key.verifying_key().to_bytes());
+    let config = json!({
+        "keys": [{ "source": "user-service", "key_id": "user-ed", "algorithm": "ed25519", "key": public }]
+    });
+    let trusted = crate::TrustedKeys::from_json(&config.to_string()).unwrap();
+
+    let signed = crate::MessageSigner::ed25519("user-ed", key).sign(user_created_message()).unwrap();
+    assert_eq!(signed.headers[crate::SIGNATURE_ALGORITHM_HEADER], crate::ED25519);
+    trusted.verify(&signed).unwrap();
+
+    let mut tampered = signed;
+    tampered.message_type = "UserDeleted".to_string();
+    assert!(trusted.verify(&tampered).is_err());
+
+    // Signatures are optional unless required, but an unknown key never passes
+    trusted.verify(&user_created_message()).unwrap();
+    let unknown = crate::MessageSigner::hmac("other", b"secret".to_vec()).sign(user_created_message()).unwrap();
+    assert!(trusted.verify(&unknown).is_err());
+    let strict = trusted.require_signatures(true);
+    assert!(strict.verify(&user_created_message()).is_err());
+
+    let bad = json!({ "keys": [{ "source": "a", "key_id": "k", "algorithm": "rsa", "key": "AAAA" }] });
+    assert!(crate::TrustedKeys::from_json(&bad.to_string()).is_err());
+}
+
+#[test]
+fn test_payload_encryption() {
+    let message = user_created_message();
+    let user_id = message.payload["user_id"].clone();
+
+    // Only the email is hidden; the rest stays readable for routing
+    let cipher = crate::PayloadCipher::new("pii-1", [1; 32]).only_fields(["email"]);
+    let encrypted = cipher.encrypt(message.clone()).unwrap();
+    assert_ne!(encrypted.payload["email"], message.payload["email"]);
+    assert_eq!(encrypted.payload["user_id"], user_id);
+    assert_eq!(encrypted.headers[crate::ENCRYPTED_FIELDS_HEADER], "email");
+    let decrypted = cipher.decrypt(encrypted.clone()).unwrap();
+    assert_eq!(decrypted.payload, message.payload);
+    assert!(!decrypted.headers.contains_key(crate::ENCRYPTION_KEY_HEADER));
+
+    // Ciphertext is bound to its message
+    let mut moved = encrypted.clone();
+    moved.id = uuid::Uuid::new_v4();
+    assert!(matches!(cipher.decrypt(moved), Err(crate::MessagingError::EncryptionError(_))));
+
+    // Whole-payload encryption, decrypted after rotating to a new key
+    let old = crate::PayloadCipher::new("pii-1", [1; 32]);
+    let sealed = old.encrypt(message.clone()).unwrap();
+    assert!(sealed.payload.is_string());
+    let rotated = crate::PayloadCipher::new("pii-2", [2; 32]).with_decryption_key("pii-1", [1; 32]);
+    assert_eq!(rotated.decrypt(sealed.clone()).unwrap().payload, message.payload);
+    assert!(crate::PayloadCipher::new("pii-2", [2; 32]).decrypt(sealed).is_err());
+
+    // Encrypt-then-sign verifies before anything is decrypted
+    let signer = crate::MessageSigner::hmac("user-2024", b"secret".to_vec());
+    let trusted = crate::TrustedKeys::new().trust_hmac("user-service", "user-2024", b"secret".to_vec());
+    let sealed = signer.sign(cipher.encrypt(message.clone()).unwrap()).unwrap();
+    trusted.verify(&sealed).unwrap();
+    assert_eq!(cipher.decrypt(sealed).unwrap().payload, message.payload);
+}
+
+#[tokio::test]
+async fn test_replies_are_sealed_and_verified() {
+    let request = crate::build_request("inventory.reserve", "order-service", &json!({"sku": "KB-1"}), std::time::Duration::from_secs(1)).unwrap();
+    let reply = crate::build_reply(&request, Ok::<_, crate::RpcError>(json!({"reserved": true}))).unwrap();
+
+    // The server replies through its publisher's codec, cipher and signer
+    let server = crate::Publisher::new(offline_client().await)
+        .with_codec(crate::MessagePackCodec)
+        .with_encryption(crate::PayloadCipher::new("rpc-1", [3; 32]))
+        .with_signer(crate::MessageSigner::hmac("inventory-2024", b"secret".to_vec()));
+    let (headers, bytes) = server.encode(reply).unwrap();
+    assert_eq!(headers.get("content-type").map(|v| v.as_str()), Some("application/msgpack"));
+
+    let requester = crate::Publisher::new(offline_client().await)
+        .with_encryption(crate::PayloadCipher::new("rpc-1", [3; 32]))
+        .with_trusted_keys(
+            crate::TrustedKeys::new()
+                .require_signatures(true)
+                .trust_hmac("inventory.reserve", "inventory-2024", b"secret".to_vec()),
+        );
+    let opened = requester.open(crate::decode_message(Some(&headers), &bytes).unwrap()).unwrap();
+    assert_eq!(crate::parse_reply::<serde_json::Value>(&request, opened).unwrap(), json!({"reserved": true}));
+
+    // Unsigned replies are refused when signatures are required
+    let unsigned = crate::Publisher::new(offline_client().await);
+    let (headers, bytes) = unsigned.encode(crate::build_reply(&request, Ok::<_, crate::RpcError>(json!({}))).unwrap()).unwrap();
+    assert!(matches!(
+        requester.open(crate::decode_message(Some(&headers), &bytes).unwrap()),
+        Err(crate::MessagingError::SignatureError(_))
+    ));
+}
+
+/// A subscriber that records spans with OpenTelemetry contexts. The provider
+/// must outlive the spans.
+fn otel_tracing() -> (opentelemetry_sdk::trace::TracerProvider, impl tracing::Subscriber + Send + Sync) {
+    use tracing_subscriber::layer::SubscriberExt;
+    let provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+    let tracer = opentelemetry::trace::TracerProvider::tracer(&provider, "messaging-tests");
+    let subscriber = tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    (provider, subscriber)
+}
+
+#[tokio::test]
+async fn test_trace_context_propagates_across_publish_and_consume() {
+    use tracing::Instrument;
+    let (_provider, subscriber) = otel_tracing();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let request_span = tracing::info_span!("http.request");
+    let trace_id = observability::trace_id(&request_span).unwrap();
+    let correlation_id = uuid::Uuid::new_v4();
+
+    let published = observability::with_correlation_id(
+        correlation_id.to_string(),
+        async { crate::propagation::stamp_outgoing(user_created_message()) }.instrument(request_span),
+    )
+    .await;
+    assert!(published.headers[observability::TRACEPARENT_HEADER].contains(&trace_id));
+    assert_eq!(published.correlation_id, Some(correlation_id));
+
+    // The consumer runs in a child span of the same trace, and whatever it
+    // publishes carries the trace and correlation id on
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(None));
+    let handler = |message: crate::Message| {
+        let seen = seen.clone();
+        async move {
+            let consumer_trace = observability::trace_id(&tracing::Span::current());
+            let follow_up = crate::propagation::stamp_outgoing(crate::Message::new(
+                "OrderCreated".to_string(),
+                "order-service".to_string(),
+                "".to_string(),
+                json!({ "user_id": message.payload["user_id"] }),
+            ));
+            *seen.lock().unwrap() = Some((consumer_trace, observability::current_correlation_id(), follow_up));
+            Ok(())
+        }
+    };
+    crate::propagation::handle_traced("events.user.created", &handler, published).await.unwrap();
+
+    let (consumer_trace, consumer_correlation, follow_up) = seen.lock().unwrap().take().unwrap();
+    assert_eq!(consumer_trace.as_deref(), Some(trace_id.as_str()));
+    assert_eq!(consumer_correlation, Some(correlation_id.to_string()));
+    assert!(follow_up.headers[observability::TRACEPARENT_HEADER].contains(&trace_id));
+    assert_eq!(follow_up.correlation_id, Some(correlation_id));
+
+    // Without a trace or correlation scope a message starts its own conversation
+    let plain = user_created_message();
+    let id = plain.id;
+    let handler = |_: crate::Message| async { Ok(observability::current_correlation_id()) };
+    let correlation = crate::propagation::handle_traced("events.user.created", &handler, plain).await.unwrap();
+    assert_eq!(correlation, Some(id.to_string()));
+}
+
+#[test]
+fn test_asyncapi_document_from_catalog() {
+    let catalog = crate::EventCatalog::new("order-service", "0.1.0")
+        .subscribes::<crate::UserCreatedEvent>()
+        .publishes::<crate::OrderCreatedEvent>()
+        .sends_command("commands.inventory.reserve", "Reserve stock for an order");
+    let doc = catalog.to_asyncapi();
+
+    assert_eq!(doc["asyncapi"], "3.0.0");
+    assert_eq!(doc["channels"]["events.order_created"]["address"], "events.order_created");
+    assert_eq!(
+        doc["channels"]["events.order_created"]["messages"]["order_created.v3"]["$ref"],
+        "#/components/messages/order_created.v3"
+    );
+
+    let operation = &doc["operations"]["order-service.receive.user_created.v1"];
+    assert_eq!(operation["action"], "receive");
+    assert_eq!(operation["channel"]["$ref"], "#/channels/events.user_created");
+
+    // Messages describe the envelope, with the registered schema as its payload
+    let message = &doc["components"]["messages"]["order_created.v3"];
+    assert_eq!(message["title"], "OrderCreatedEvent");
+    let envelope = &message["payload"]["schema"];
+    assert_eq!(envelope["properties"]["message_type"]["const"], "order_created");
+    assert_eq!(envelope["properties"]["headers"]["properties"]["schema-version"]["const"], "3");
+    assert_eq!(
+        envelope["properties"]["payload"],
+        {
+            let mut schema = crate::bundled_schema("order_created", 3).unwrap();
+            schema.as_object_mut().unwrap().remove("$schema");
+            schema
+        }
+    );
+
+    let command = &doc["components"]["messages"]["inventory.reserve"];
+    assert_eq!(command["summary"], "Reserve stock for an order");
+    assert_eq!(command["payload"]["schema"]["properties"]["payload"], json!({}));
+    assert_eq!(doc["operations"]["order-service.send.inventory.reserve"]["action"], "send");
+}
+
+#[test]
+fn test_merge_asyncapi_documents() {
+    let users = crate::EventCatalog::new("user-service", "0.1.0")
+        .publishes::<crate::UserCreatedEvent>()
+        .to_asyncapi();
+    let orders = crate::EventCatalog::new("order-service", "0.1.0")
+        .subscribes::<crate::UserCreatedEvent>()
+        .to_asyncapi();
+
+    let merged = crate::merge_asyncapi("Event catalog", &[users.clone(), orders]).unwrap();
+    assert_eq!(merged["info"]["description"], "Merged from: user-service messages, order-service messages");
+    assert_eq!(merged["channels"].as_object().unwrap().len(), 1);
+    assert_eq!(merged["components"]["messages"].as_object().unwrap().len(), 1);
+    assert_eq!(merged["operations"]["user-service.send.user_created.v1"]["action"], "send");
+    assert_eq!(merged["operations"]["order-service.receive.user_created.v1"]["action"], "receive");
 
-        assert_eq!(message.message_type, "test_type");
-        assert_eq!(message.source, "test_source");
-        assert_eq!(message.destination, "test_destination");
-        assert_eq!(message.payload, payload);
-        assert!(message.correlation_id.is_none());
-        assert!(message.causation_id.is_none());
-    }
-
-    #[test]
-    fn test_message_with_correlation() {
-        let payload = json!({"test": "data"});
-        let correlation_id = uuid::Uuid::new_v4();
-        let message = crate::Message::new(
-            "test_type".to_string(),
-            "test_source".to_string(),
-            "test_destination".to_string(),
-            payload,
-        ).with_correlation(correlation_id);
-
-        assert_eq!(message.correlation_id, Some(correlation_id));
-    }
-
-    #[test]
-    fn test_message_with_causation() {
-        let payload = json!({"test": "data"});
-        let causation_id = uuid::Uuid::new_v4();
-        let message = crate::Message::new(
-            "test_type".to_string(),
-            "test_source".to_string(),
-            "test_destination".to_string(),
-            payload,
-        ).with_causation(causation_id);
-
-        assert_eq!(message.causation_id, Some(causation_id));
-    }
-
-    #[test]
-    fn test_message_with_header() {
-        let payload = json!({"test": "data"});
-        let mut headers = HashMap::new();
-        headers.insert("test_key".to_string(), "test_value".to_string());
-
-        let message = crate::Message::new(
-            "test_type".to_string(),
-            "test_source".to_string(),
-            "test_destination".to_string(),
-            payload,
-        ).with_header("test_key".to_string(), "test_value".to_string());
-
-        assert_eq!(message.headers.get("test_key"), Some(&"test_value".to_string()));
-    }
-}
\ No newline at end of file
+    // Two services disagreeing about a message is reported
+    let mut drifted = crate::EventCatalog::new("audit-service", "0.1.0")
+        .subscribes::<crate::UserCreatedEvent>()
+        .to_asyncapi();
+    drifted["components"]["messages"]["user_created.v1"]["payload"]["schema"]["properties"]["payload"] = json!({});
+    assert!(matches!(
+        crate::merge_asyncapi("Event catalog", &[users, drifted]),
+        Err(crate::MessagingError::CatalogError(_))
+    ));
+}
@@ -0,0 +1,141 @@
+use crate::{Event, Message, MessagingError, OrderLineItem, SCHEMA_VERSION_HEADER};
+use serde_json::Value;
+use shared::Money;
+use std::collections::HashMap;
+
+type UpcastFn = Box<dyn Fn(Value) -> Result<Value, MessagingError> + Send + Sync>;
+
+/// Converts payloads of older schema versions into newer ones, one version at a time.
+///
+/// Each step is keyed by `message_type` and the version it upgrades from.
+/// Messages without a `schema-version` header predate versioning and are
+/// treated as version 1.
+#[derive(Default)]
+pub struct UpcasterChain {
+    steps: HashMap<(String, u32), UpcastFn>,
+}
+
+impl UpcasterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the step turning `from_version` payloads into `from_version + 1`.
+    pub fn register<F>(mut self, message_type: &str, from_version: u32, upcast: F) -> Self
+    where
+        F: Fn(Value) -> Result<Value, MessagingError> + Send + Sync + 'static,
+    {
+        self.steps.insert((message_type.to_string(), from_version), Box::new(upcast));
+        self
+    }
+
+    /// Upgrades `message` to `target_version`, rewriting its schema version header.
+    pub fn upcast(&self, mut message: Message, target_version: u32) -> Result<Message, MessagingError> {
+        let mut version = message.schema_version().unwrap_or(1);
+        if version > target_version {
+            return Err(MessagingError::UpcastError(format!(
+                "{} v{} is newer than the supported v{}",
+                message.message_type, version, target_version
+            )));
+        }
+
+        while version < target_version {
+            let step = self.steps.get(&(message.message_type.clone(), version)).ok_or_else(|| {
+                MessagingError::UpcastError(format!(
+                    "No upcaster for {} v{}",
+                    message.message_type, version
+                ))
+            })?;
+            message.payload = step(message.payload)?;
+            version += 1;
+        }
+
+        message.headers.insert(SCHEMA_VERSION_HEADER.to_string(), version.to_string());
+        Ok(message)
+    }
+
+    /// Upgrades `message` to `E::VERSION` and deserializes it as `E`.
+    pub fn decode<E: Event>(&self, message: Message) -> Result<(E, Message), MessagingError> {
+        if message.message_type != E::TYPE {
+            return Err(MessagingError::UnexpectedMessageType {
+                expected: E::TYPE.to_string(),
+                actual: message.message_type,
+            });
+        }
+
+        let message = self.upcast(message, E::VERSION)?;
+        let event = message.decode::<E>()?;
+        Ok((event, message))
+    }
+}
+
+/// Upgrades `order_created` payloads to the current [`crate::OrderCreatedEvent`].
+///
+/// v1 had one product and a float `total_price` and v2 replaced the total
+/// with money in minor units. Totals before v2 are taken to be in USD.
+pub fn order_created_upcasters() -> UpcasterChain {
+    UpcasterChain::new()
+        // v1 -> v2: float total becomes money in minor units.
+        .register("order_created", 1, |mut payload| {
+            let total_price = payload["total_price"]
+                .as_f64()
+                .ok_or_else(|| MessagingError::UpcastError("total_price is not a number".to_string()))?;
+            let object = payload
+                .as_object_mut()
+                .ok_or_else(|| MessagingError::UpcastError("payload is not an object".to_string()))?;
+            let total = Money::from_major_units(total_price, "USD")
+                .map_err(|e| MessagingError::UpcastError(format!("total_price {}: {}", total_price, e)))?;
+            object.remove("total_price");
+            object.insert("total".to_string(), serde_json::to_value(total)?);
+            Ok(payload)
+        })
+        // v2 -> v3: the single product becomes line items.
+        .register("order_created", 2, |mut payload| {
+            let object = payload
+                .as_object_mut()
+                .ok_or_else(|| MessagingError::UpcastError("payload is not an object".to_string()))?;
+            let missing = |field: &str| MessagingError::UpcastError(format!("{} is missing or invalid", field));
+            let product_name = object.remove("product_name").ok_or_else(|| missing("product_name"))?;
+            let product_name = product_name.as_str().ok_or_else(|| missing("product_name"))?;
+            let quantity = object
+                .remove("quantity")
+                .and_then(|q| q.as_i64())
+                .and_then(|q| i32::try_from(q).ok())
+                .ok_or_else(|| missing("quantity"))?;
+            let total = object.get("total").ok_or_else(|| missing("total"))?;
+            let total = Money {
+                amount_minor: total.get("amount_minor").and_then(Value::as_i64).ok_or_else(|| missing("total.amount_minor"))?,
+                currency: total
+                    .get("currency")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| missing("total.currency"))?
+                    .to_string(),
+            };
+            let line_items = single_product_line_items(product_name, quantity, &total)?;
+            object.insert("line_items".to_string(), serde_json::to_value(line_items)?);
+            Ok(payload)
+        })
+}
+
+/// Line items for `quantity` units of one product costing `total`
+/// altogether, as orders were placed before line items existed.
+///
+/// When `total` does not divide evenly, the last unit goes on a second line
+/// that also carries the remainder, so the items always add up to `total`.
+pub fn single_product_line_items(sku: &str, quantity: i32, total: &Money) -> Result<Vec<OrderLineItem>, MessagingError> {
+    if quantity < 1 {
+        return Err(MessagingError::UpcastError(format!("quantity {} is not positive", quantity)));
+    }
+    let unit = total.amount_minor / quantity as i64;
+    let remainder = total.amount_minor % quantity as i64;
+    let line = |quantity: i32, amount_minor: i64| OrderLineItem {
+        sku: sku.to_string(),
+        quantity,
+        unit_price: Money { amount_minor, currency: total.currency.clone() },
+    };
+    Ok(if remainder == 0 {
+        vec![line(quantity, unit)]
+    } else {
+        vec![line(quantity - 1, unit), line(1, unit + remainder)]
+    })
+}
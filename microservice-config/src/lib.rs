@@ -58,4 +58,5 @@ impl Config {
 }
 
 #[cfg(test)]
-mod tests;
\ No newline at end of file
+#[path = "tests.rs"]
+mod unit;
\ No newline at end of file
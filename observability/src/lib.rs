@@ -1,10 +1,15 @@
 mod tracing;
 mod metrics;
 mod logging;
+mod propagation;
 
 pub use tracing::*;
 pub use metrics::*;
 pub use logging::*;
+pub use propagation::*;
 
 #[cfg(test)]
-mod tests;
\ No newline at end of file
+#[path = "tests.rs"]
+// The logging tests import the log wrappers but call tracing directly
+#[allow(unused_imports, clippy::io_other_error)]
+mod unit;
\ No newline at end of file
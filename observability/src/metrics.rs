@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
 use tracing::info;
 
 pub fn init_metrics(_service_name: &str) -> Result<(), Box<dyn std::error::Error>> {
@@ -8,4 +10,50 @@ pub fn init_metrics(_service_name: &str) -> Result<(), Box<dyn std::error::Error
 
 pub fn shutdown_metrics() {
     // Nothing to shutdown for simple metrics
-}
\ No newline at end of file
+}
+
+type Labels = Vec<(String, String)>;
+
+fn gauges() -> &'static Mutex<BTreeMap<String, BTreeMap<Labels, f64>>> {
+    static GAUGES: OnceLock<Mutex<BTreeMap<String, BTreeMap<Labels, f64>>>> = OnceLock::new();
+    GAUGES.get_or_init(Default::default)
+}
+
+fn to_labels(labels: &[(&str, &str)]) -> Labels {
+    let mut labels: Labels = labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    labels.sort();
+    labels
+}
+
+/// Sets the current value of a process-wide gauge.
+pub fn set_gauge(name: &str, labels: &[(&str, &str)], value: f64) {
+    let mut gauges = gauges().lock().unwrap_or_else(|e| e.into_inner());
+    gauges.entry(name.to_string()).or_default().insert(to_labels(labels), value);
+}
+
+pub fn gauge(name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+    let gauges = gauges().lock().unwrap_or_else(|e| e.into_inner());
+    gauges.get(name).and_then(|series| series.get(&to_labels(labels)).copied())
+}
+
+/// All gauges in the Prometheus text exposition format, for a `/metrics` endpoint.
+pub fn render_metrics() -> String {
+    let gauges = gauges().lock().unwrap_or_else(|e| e.into_inner());
+    let mut out = String::new();
+    for (name, series) in gauges.iter() {
+        out.push_str(&format!("# TYPE {} gauge\n", name));
+        for (labels, value) in series {
+            let labels = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+                .collect::<Vec<_>>()
+                .join(",");
+            if labels.is_empty() {
+                out.push_str(&format!("{} {}\n", name, value));
+            } else {
+                out.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+            }
+        }
+    }
+    out
+}
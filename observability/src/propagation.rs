@@ -0,0 +1,130 @@
+use axum::extract::Request;
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::Context;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use std::collections::HashMap;
+use std::future::Future;
+use tracing::{Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+pub const TRACESTATE_HEADER: &str = "tracestate";
+pub const CORRELATION_ID_HEADER: &str = "x-correlation-id";
+
+tokio::task_local! {
+    static CORRELATION_ID: String;
+}
+
+/// Runs `future` with `correlation_id` as the current correlation id, so
+/// messages it publishes and requests it sends carry it along.
+pub async fn with_correlation_id<F: Future>(correlation_id: String, future: F) -> F::Output {
+    CORRELATION_ID.scope(correlation_id, future).await
+}
+
+pub fn current_correlation_id() -> Option<String> {
+    CORRELATION_ID.try_with(Clone::clone).ok()
+}
+
+/// Writes the W3C `traceparent` and `tracestate` of `span` into `carrier`.
+/// Nothing is written when the span is not recorded by an OpenTelemetry
+/// layer, so a carrier's existing trace context survives untraced hops.
+pub fn inject_context(span: &Span, carrier: &mut dyn Injector) {
+    TraceContextPropagator::new().inject_context(&span.context(), carrier);
+}
+
+/// Reads a W3C trace context from `carrier`.
+pub fn extract_context(carrier: &dyn Extractor) -> Context {
+    TraceContextPropagator::new().extract(carrier)
+}
+
+/// Makes `span` a child of the trace context in `carrier`, if it has one.
+pub fn set_parent_from(span: &Span, carrier: &dyn Extractor) {
+    let parent = extract_context(carrier);
+    if parent.span().span_context().is_valid() {
+        span.set_parent(parent);
+    }
+}
+
+/// Hex trace id of `span`, if it is being traced.
+pub fn trace_id(span: &Span) -> Option<String> {
+    let context = span.context();
+    let span_context = context.span().span_context().clone();
+    span_context.is_valid().then(|| span_context.trace_id().to_string())
+}
+
+/// Headers that continue the current trace and correlation id on an outgoing
+/// call, for HTTP clients that do not share axum's header types.
+pub fn outgoing_headers() -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    inject_context(&Span::current(), &mut headers);
+    if let Some(correlation_id) = current_correlation_id() {
+        headers.insert(CORRELATION_ID_HEADER.to_string(), correlation_id);
+    }
+    headers
+}
+
+/// A gRPC request for `message` that continues the current trace and
+/// correlation id, as [`outgoing_headers`] does for HTTP.
+pub fn outgoing_request<T>(message: T) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    for (name, value) in outgoing_headers() {
+        if let (Ok(key), Ok(value)) = (tonic::metadata::AsciiMetadataKey::from_bytes(name.as_bytes()), value.parse()) {
+            request.metadata_mut().insert(key, value);
+        }
+    }
+    request
+}
+
+/// [`Injector`] and [`Extractor`] over HTTP headers.
+pub struct HeaderCarrier<'a>(pub &'a mut HeaderMap);
+
+impl Injector for HeaderCarrier<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(key.as_bytes()), HeaderValue::from_str(&value)) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+impl Extractor for HeaderCarrier<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+/// Axum middleware that continues the caller's trace and correlation id.
+///
+/// Each request gets a server span parented on its `traceparent` header and
+/// runs with the `x-correlation-id` it came with, or a new one, which is
+/// echoed back on the response.
+pub async fn trace_requests(mut request: Request, next: Next) -> Response {
+    let correlation_id = request
+        .headers()
+        .get(CORRELATION_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let span = tracing::info_span!(
+        "http.request",
+        otel.kind = "server",
+        http.method = %request.method(),
+        http.target = %request.uri().path(),
+        correlation_id = %correlation_id,
+    );
+    set_parent_from(&span, &HeaderCarrier(request.headers_mut()));
+
+    let mut response = with_correlation_id(correlation_id.clone(), next.run(request).instrument(span)).await;
+    if let Ok(value) = HeaderValue::from_str(&correlation_id) {
+        response.headers_mut().insert(CORRELATION_ID_HEADER, value);
+    }
+    response
+}
@@ -26,4 +26,47 @@ mod tests {
         tracing::error!("{}", error);
         // If we get here without panicking, the test passes
     }
+
+    #[test]
+    fn test_gauges_render_as_prometheus_text() {
+        crate::set_gauge("test_projection_lag", &[("projection", "orders")], 3.0);
+        crate::set_gauge("test_projection_lag", &[("projection", "orders")], 2.0);
+        crate::set_gauge("test_projection_lag", &[("projection", "users")], 0.0);
+
+        assert_eq!(crate::gauge("test_projection_lag", &[("projection", "orders")]), Some(2.0));
+        assert_eq!(crate::gauge("test_projection_lag", &[("projection", "missing")]), None);
+
+        let text = crate::render_metrics();
+        assert!(text.contains("# TYPE test_projection_lag gauge\n"));
+        assert!(text.contains("test_projection_lag{projection=\"orders\"} 2\n"));
+        assert!(text.contains("test_projection_lag{projection=\"users\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn test_trace_context_round_trips_through_http_headers() {
+        use tracing_subscriber::layer::SubscriberExt;
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+        let tracer = opentelemetry::trace::TracerProvider::tracer(&provider, "observability-tests");
+        let subscriber = tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert(
+            crate::TRACEPARENT_HEADER,
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap(),
+        );
+        let span = tracing::info_span!("incoming");
+        crate::set_parent_from(&span, &crate::HeaderCarrier(&mut headers));
+        assert_eq!(crate::trace_id(&span).as_deref(), Some("4bf92f3577b34da6a3ce929d0e0e4736"));
+
+        let mut outgoing = axum::http::HeaderMap::new();
+        crate::inject_context(&span, &mut crate::HeaderCarrier(&mut outgoing));
+        let traceparent = outgoing[crate::TRACEPARENT_HEADER].to_str().unwrap();
+        assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+
+        assert_eq!(crate::current_correlation_id(), None);
+        let inside = crate::with_correlation_id("abc".to_string(), async { crate::current_correlation_id() }).await;
+        assert_eq!(inside.as_deref(), Some("abc"));
+    }
 }
\ No newline at end of file
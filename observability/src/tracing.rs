@@ -1,17 +1,49 @@
-use tracing_subscriber::{EnvFilter, FmtSubscriber};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{self as sdktrace, TracerProvider};
+use opentelemetry_sdk::Resource;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::EnvFilter;
 
-pub fn init_tracing(_service_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize simple tracing
-    let subscriber = FmtSubscriber::builder()
-        .with_env_filter(EnvFilter::from_default_env())
-        .finish();
+/// Installs the global subscriber: formatted logs plus OpenTelemetry spans,
+/// so trace context can be propagated across HTTP and messaging hops.
+///
+/// Spans are exported over OTLP when `OTEL_EXPORTER_OTLP_ENDPOINT` is set,
+/// and only used for propagation otherwise.
+pub fn init_tracing(service_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
 
-    tracing::subscriber::set_global_default(subscriber)
-        .expect("Failed to set global default subscriber");
+    let config = sdktrace::config().with_resource(Resource::new(vec![KeyValue::new(
+        "service.name",
+        service_name.to_string(),
+    )]));
+    let tracer = match std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(endpoint) => opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+            .with_trace_config(config)
+            .install_batch(opentelemetry_sdk::runtime::Tokio)?,
+        Err(_) => {
+            let provider = TracerProvider::builder().with_config(config).build();
+            let tracer = provider.tracer(service_name.to_string());
+            opentelemetry::global::set_tracer_provider(provider);
+            tracer
+        }
+    };
+
+    let subscriber = tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+    tracing::subscriber::set_global_default(subscriber)?;
 
     Ok(())
 }
 
-pub fn shutdown_tracing(_tracer: ()) {
-    // Nothing to shutdown for simple tracing
-}
\ No newline at end of file
+/// Flushes spans that have not been exported yet.
+pub fn shutdown_tracing() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
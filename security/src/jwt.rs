@@ -1,7 +1,6 @@
 use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey};
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
-use uuid::Uuid;
 use crate::SecurityError;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -9,4 +9,5 @@ pub use auth::*;
 pub use error::*;
 
 #[cfg(test)]
-mod tests;
\ No newline at end of file
+#[path = "tests.rs"]
+mod unit;
\ No newline at end of file
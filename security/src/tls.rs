@@ -1,6 +1,5 @@
 use rustls::{ClientConfig, ServerConfig, RootCertStore};
 use std::sync::Arc;
-use webpki::TrustAnchor;
 
 pub struct TlsConfig {
     pub client_config: Arc<ClientConfig>,
@@ -10,7 +9,7 @@ pub struct TlsConfig {
 impl TlsConfig {
     pub fn new() -> Result<Self, crate::SecurityError> {
         // Create a root certificate store
-        let mut root_store = RootCertStore::empty();
+        let root_store = RootCertStore::empty();
         
         // In a real implementation, you would add trusted certificates here
         // For now, we'll create a basic config without specific certificates
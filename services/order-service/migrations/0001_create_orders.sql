@@ -0,0 +1,15 @@
+-- Current state of each order, written alongside its event stream so orders
+-- can be queried by user. The stream stays the source of truth.
+-- Portable across SQLite and Postgres: ids as TEXT, timestamps as DOUBLE
+-- PRECISION microseconds since the Unix epoch (see shared/src/db.rs).
+CREATE TABLE IF NOT EXISTS orders (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    product_name TEXT NOT NULL,
+    quantity INTEGER NOT NULL,
+    total_price DOUBLE PRECISION NOT NULL,
+    created_at DOUBLE PRECISION NOT NULL,
+    updated_at DOUBLE PRECISION NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS orders_by_user ON orders (user_id, created_at, id);
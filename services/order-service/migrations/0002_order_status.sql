@@ -0,0 +1,14 @@
+-- Orders placed before statuses existed were never moved on from pending.
+ALTER TABLE orders ADD COLUMN status TEXT NOT NULL DEFAULT 'pending';
+
+-- Every status an order has been in. `version` is the version of the
+-- order's event stream after the change, so it orders the history.
+CREATE TABLE IF NOT EXISTS order_status_history (
+    order_id TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    from_status TEXT,
+    to_status TEXT NOT NULL,
+    reason TEXT,
+    changed_at DOUBLE PRECISION NOT NULL,
+    PRIMARY KEY (order_id, version)
+);
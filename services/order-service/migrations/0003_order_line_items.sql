@@ -0,0 +1,42 @@
+-- Orders have line items priced in minor units instead of one product and a
+-- float total. Amounts are whole numbers of minor units kept as TEXT, so they
+-- are exact on both backends (see shared/src/db.rs).
+CREATE TABLE IF NOT EXISTS order_line_items (
+    order_id TEXT NOT NULL,
+    line_number INTEGER NOT NULL,
+    sku TEXT NOT NULL,
+    quantity INTEGER NOT NULL,
+    unit_price TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    PRIMARY KEY (order_id, line_number)
+);
+
+ALTER TABLE orders ADD COLUMN total TEXT NOT NULL DEFAULT '0';
+ALTER TABLE orders ADD COLUMN currency TEXT NOT NULL DEFAULT 'USD';
+
+UPDATE orders SET total = CAST(CAST(ROUND(total_price * 100) AS BIGINT) AS TEXT);
+
+-- Existing orders become USD line items, the same way their events are read
+-- (see messaging::single_product_line_items): one line when the total divides
+-- evenly by the quantity, otherwise the last unit on a second line that also
+-- carries the remainder.
+--
+-- An order with no positive quantity has no unit price to split its total
+-- into. It keeps its total and gets no line items, and its events fail to
+-- load the same way. Postgres may evaluate the modulo before the quantity
+-- check, so NULLIF keeps a zero quantity from dividing by zero.
+INSERT INTO order_line_items (order_id, line_number, sku, quantity, unit_price, currency)
+SELECT id, 1, product_name, quantity, CAST(CAST(total AS BIGINT) / quantity AS TEXT), 'USD' FROM orders
+WHERE quantity > 0 AND CAST(total AS BIGINT) % NULLIF(quantity, 0) = 0;
+INSERT INTO order_line_items (order_id, line_number, sku, quantity, unit_price, currency)
+SELECT id, 1, product_name, quantity - 1, CAST(CAST(total AS BIGINT) / quantity AS TEXT), 'USD' FROM orders
+WHERE quantity > 0 AND CAST(total AS BIGINT) % NULLIF(quantity, 0) <> 0;
+INSERT INTO order_line_items (order_id, line_number, sku, quantity, unit_price, currency)
+SELECT id, 2, product_name, 1,
+       CAST(CAST(total AS BIGINT) / quantity + CAST(total AS BIGINT) % quantity AS TEXT), 'USD'
+FROM orders
+WHERE quantity > 0 AND CAST(total AS BIGINT) % NULLIF(quantity, 0) <> 0;
+
+ALTER TABLE orders DROP COLUMN product_name;
+ALTER TABLE orders DROP COLUMN quantity;
+ALTER TABLE orders DROP COLUMN total_price;
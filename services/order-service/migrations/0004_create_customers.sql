@@ -0,0 +1,11 @@
+-- Local copy of user-service's users, kept up to date from user events and
+-- backfilled from GET /users/export. `changed_at` is when the user last
+-- changed upstream; older changes arriving late are ignored. Deleted users
+-- stay as tombstones so a late event cannot bring them back.
+CREATE TABLE IF NOT EXISTS customers (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    deleted INTEGER NOT NULL DEFAULT 0,
+    changed_at DOUBLE PRECISION NOT NULL
+);
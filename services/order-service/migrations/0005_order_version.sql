@@ -0,0 +1,7 @@
+-- The version of each order's event stream, served as its ETag.
+ALTER TABLE orders ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+
+UPDATE orders SET version = (
+    SELECT MAX(version) FROM order_status_history WHERE order_status_history.order_id = orders.id
+)
+WHERE EXISTS (SELECT 1 FROM order_status_history WHERE order_status_history.order_id = orders.id);
@@ -0,0 +1,144 @@
+use messaging::{Aggregate, MessagingError};
+use serde::{Deserialize, Serialize};
+use shared::db::{from_timestamp, to_timestamp};
+use shared::Money;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{error::AppError, models::{LineItem, Order, OrderStatus}};
+
+/// Everything that has happened to an order, in the order it happened.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OrderEvent {
+    OrderPlaced {
+        order_id: Uuid,
+        user_id: Uuid,
+        #[serde(flatten)]
+        items: PlacedItems,
+        placed_at: OffsetDateTime,
+    },
+    StatusChanged {
+        order_id: Uuid,
+        from: OrderStatus,
+        to: OrderStatus,
+        reason: Option<String>,
+        changed_at: OffsetDateTime,
+    },
+}
+
+/// What an order was placed for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "StoredItems")]
+pub struct PlacedItems {
+    pub line_items: Vec<LineItem>,
+    pub total: Money,
+}
+
+/// `PlacedItems` as stored, including orders placed before line items: one
+/// product and a float total, which was always in USD.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredItems {
+    LineItems { line_items: Vec<LineItem>, total: Money },
+    SingleProduct { product_name: String, quantity: i32, total_price: f64 },
+}
+
+impl TryFrom<StoredItems> for PlacedItems {
+    type Error = MessagingError;
+
+    fn try_from(items: StoredItems) -> Result<Self, MessagingError> {
+        match items {
+            StoredItems::LineItems { line_items, total } => Ok(PlacedItems { line_items, total }),
+            StoredItems::SingleProduct { product_name, quantity, total_price } => {
+                // Same conversion as messaging::order_created_upcasters
+                let total = Money::from_major_units(total_price, "USD")
+                    .map_err(|e| MessagingError::UpcastError(format!("total_price {}: {}", total_price, e)))?;
+                let line_items = messaging::single_product_line_items(&product_name, quantity, &total)?
+                    .into_iter()
+                    .map(|item| LineItem { sku: item.sku, quantity: item.quantity, unit_price: item.unit_price })
+                    .collect();
+                Ok(PlacedItems { line_items, total })
+            }
+        }
+    }
+}
+
+/// The write model of an order, rebuilt from its event stream.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct OrderAggregate {
+    pub order: Option<Order>,
+}
+
+impl OrderAggregate {
+    /// Places the order, totalling its line items, which must share one
+    /// currency.
+    pub fn place(&self, order_id: Uuid, user_id: Uuid, line_items: Vec<LineItem>) -> Result<Vec<OrderEvent>, AppError> {
+        if self.order.is_some() {
+            return Err(AppError::ValidationError(format!("Order {} already exists", order_id)));
+        }
+        let currency = line_items
+            .first()
+            .map(|item| item.unit_price.currency.clone())
+            .ok_or_else(|| AppError::ValidationError("An order needs at least one line item".to_string()))?;
+        let line_totals = line_items.iter().map(LineItem::line_total).collect::<Result<Vec<_>, _>>()?;
+        let total = Money::sum(&currency, &line_totals)?;
+        Ok(vec![OrderEvent::OrderPlaced {
+            order_id,
+            user_id,
+            items: PlacedItems { line_items, total },
+            // At the precision the orders table stores
+            placed_at: from_timestamp(to_timestamp(OffsetDateTime::now_utc())),
+        }])
+    }
+
+    /// Moves the order to `to`, if its current status allows it.
+    pub fn transition(&self, to: OrderStatus, reason: Option<String>) -> Result<Vec<OrderEvent>, AppError> {
+        let order = self.order.as_ref().ok_or(AppError::OrderNotFound)?;
+        if !order.status.can_transition_to(to) {
+            return Err(AppError::InvalidTransition { from: order.status, to });
+        }
+        Ok(vec![OrderEvent::StatusChanged {
+            order_id: order.id,
+            from: order.status,
+            to,
+            reason,
+            changed_at: from_timestamp(to_timestamp(OffsetDateTime::now_utc())),
+        }])
+    }
+}
+
+impl Aggregate for OrderAggregate {
+    const TYPE: &'static str = "order";
+    type Event = OrderEvent;
+
+    fn event_type(event: &OrderEvent) -> &'static str {
+        match event {
+            OrderEvent::OrderPlaced { .. } => "order_placed",
+            OrderEvent::StatusChanged { .. } => "order_status_changed",
+        }
+    }
+
+    fn apply(&mut self, event: &OrderEvent) {
+        match event {
+            OrderEvent::OrderPlaced { order_id, user_id, items, placed_at } => {
+                self.order = Some(Order {
+                    id: *order_id,
+                    user_id: *user_id,
+                    line_items: items.line_items.clone(),
+                    total: items.total.clone(),
+                    status: OrderStatus::Pending,
+                    created_at: *placed_at,
+                    updated_at: *placed_at,
+                    version: 0,
+                });
+            }
+            OrderEvent::StatusChanged { to, changed_at, .. } => {
+                if let Some(order) = &mut self.order {
+                    order.status = *to;
+                    order.updated_at = *changed_at;
+                }
+            }
+        }
+    }
+}
@@ -1,21 +1,89 @@
 use serde::Deserialize;
 use microservice_config::ConfigError;
+use shared::IfMatchPolicy;
+
+use crate::customers::{FallbackPolicy, UserLookup};
 
 #[derive(Deserialize, Clone)]
 pub struct Config {
     pub database_url: String,
     pub host: String,
     pub port: u16,
+    /// Port of the gRPC server, which runs next to the HTTP one.
+    pub grpc_port: u16,
+    pub nats_url: Option<String>,
+    /// JSON accepted by `messaging::TrustedKeys::from_json`. Incoming
+    /// messages are not verified when unset.
+    pub trusted_keys: Option<String>,
+    /// Base URL of user-service. Required with `UserLookup::Http`; with
+    /// `UserLookup::Replica` it is only used to backfill the local copy.
+    pub user_service_url: Option<String>,
+    /// gRPC URL of user-service. Required with `UserLookup::Grpc`.
+    pub user_service_grpc_url: Option<String>,
+    pub user_lookup: UserLookup,
+    pub user_lookup_timeout_ms: u64,
+    /// What to do with orders while user-service cannot be reached.
+    pub user_lookup_fallback: FallbackPolicy,
+    /// Whether status transitions must send `If-Match`.
+    pub if_match: IfMatchPolicy,
 }
 
 impl Config {
     pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Builds the configuration from `var`, which looks up one environment
+    /// variable. Unknown lookup settings and missing user-service URLs are
+    /// errors rather than falling back to the defaults.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
         // For now, we'll create a simple config without using the config crate
         // In a real implementation, you would use the config crate properly
-        Ok(Config {
-            database_url: "sqlite:order_service.db".to_string(),
+        let user_lookup = match var("USER_LOOKUP") {
+            Some(value) => UserLookup::parse(&value).ok_or_else(|| {
+                ConfigError(format!("USER_LOOKUP must be http, grpc, replica or off, not {:?}", value))
+            })?,
+            None => UserLookup::default(),
+        };
+        let user_lookup_fallback = match var("USER_LOOKUP_FALLBACK") {
+            Some(value) => FallbackPolicy::parse(&value).ok_or_else(|| {
+                ConfigError(format!("USER_LOOKUP_FALLBACK must be reject or accept_unverified, not {:?}", value))
+            })?,
+            None => FallbackPolicy::default(),
+        };
+
+        let config = Config {
+            database_url: var("DATABASE_URL").unwrap_or_else(|| "sqlite:order_service.db?mode=rwc".to_string()),
             host: "0.0.0.0".to_string(),
             port: 3002,
-        })
+            grpc_port: var("GRPC_PORT")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(50052),
+            nats_url: var("NATS_URL"),
+            trusted_keys: var("MESSAGING_TRUSTED_KEYS"),
+            user_service_url: var("USER_SERVICE_URL"),
+            user_service_grpc_url: var("USER_SERVICE_GRPC_URL"),
+            user_lookup,
+            user_lookup_timeout_ms: var("USER_LOOKUP_TIMEOUT_MS")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(2000),
+            user_lookup_fallback,
+            if_match: var("REQUIRE_IF_MATCH")
+                .map(|value| IfMatchPolicy::parse(&value))
+                .unwrap_or_default(),
+        };
+
+        match (config.user_lookup, &config.user_service_url, &config.user_service_grpc_url) {
+            (UserLookup::Http, None, _) => Err(ConfigError(
+                "USER_SERVICE_URL is required with USER_LOOKUP=http; set USER_LOOKUP=off to skip user checks".to_string(),
+            )),
+            (UserLookup::Grpc, _, None) => Err(ConfigError(
+                "USER_SERVICE_GRPC_URL is required with USER_LOOKUP=grpc; set USER_LOOKUP=off to skip user checks".to_string(),
+            )),
+            (UserLookup::Grpc, _, Some(url)) if tonic::transport::Endpoint::from_shared(url.clone()).is_err() => {
+                Err(ConfigError(format!("USER_SERVICE_GRPC_URL is not a valid URL: {:?}", url)))
+            }
+            _ => Ok(config),
+        }
     }
-}
\ No newline at end of file
+}
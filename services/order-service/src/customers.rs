@@ -0,0 +1,425 @@
+use async_trait::async_trait;
+use messaging::{
+    Event, Message, MessagingError, UserCreatedEvent, UserDeletedEvent, UserRestoredEvent, UserUpdatedEvent,
+};
+use moka::future::Cache;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use shared::db::to_timestamp;
+use shared::Page;
+use sqlx::{AnyPool, Row};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use time::OffsetDateTime;
+use tonic::transport::{Channel, Endpoint};
+use tonic::Code;
+use user_client::user_service_client::UserServiceClient;
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+// Anti-corruption layer between orders and user-service.
+//
+// Orders only care about the customer placing them, so user-service's `User`
+// is translated into a [`Customer`] here and nothing outside this module
+// depends on its shape. Lookups are cached, bounded by a timeout and fall
+// back to a configurable policy when user-service cannot be reached.
+//
+// Customers can come straight from user-service or from the local
+// `customers` table, which user events keep up to date.
+
+/// Whoever places an order, as the order domain sees them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Customer {
+    pub id: Uuid,
+    pub name: String,
+    pub email: String,
+}
+
+/// A user as user-service returns it from `GET /users/:id`. Fields orders
+/// have no use for are left out.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UserRecord {
+    pub id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub updated_at: OffsetDateTime,
+    /// Set for soft-deleted users, which only the bulk export includes.
+    #[serde(default)]
+    pub deleted_at: Option<OffsetDateTime>,
+}
+
+impl From<UserRecord> for Customer {
+    fn from(user: UserRecord) -> Self {
+        Customer { id: user.id, name: user.username, email: user.email }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum LookupError {
+    #[error("User service unavailable: {0}")]
+    Unavailable(String),
+    #[error("User service did not answer within {0:?}")]
+    Timeout(Duration),
+}
+
+/// Where customers come from.
+#[async_trait]
+pub trait CustomerSource: Send + Sync {
+    /// `Ok(None)` when there is no such user.
+    async fn fetch_customer(&self, id: Uuid) -> Result<Option<Customer>, LookupError>;
+}
+
+/// Pages through every user, for backfilling a local copy.
+#[async_trait]
+pub trait UserExport: Send + Sync {
+    async fn export_page(&self, cursor: Option<String>) -> Result<Page<UserRecord>, LookupError>;
+}
+
+/// Reads users from user-service's REST API.
+pub struct HttpUserSource {
+    client: Client,
+    base_url: String,
+}
+
+impl HttpUserSource {
+    pub fn new(base_url: &str) -> Self {
+        Self { client: Client::new(), base_url: base_url.trim_end_matches('/').to_string() }
+    }
+
+    /// GETs `path` and decodes the body, or `None` on 404 or 410.
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<Option<T>, LookupError> {
+        let request = observability::outgoing_headers()
+            .into_iter()
+            .fold(self.client.get(format!("{}{}", self.base_url, path)), |request, (name, value)| {
+                request.header(name, value)
+            });
+        let response = request.send().await.map_err(|e| LookupError::Unavailable(e.to_string()))?;
+        match response.status() {
+            // Deleted users are as good as unknown to orders
+            StatusCode::NOT_FOUND | StatusCode::GONE => Ok(None),
+            status if status.is_success() => {
+                let body = response.bytes().await.map_err(|e| LookupError::Unavailable(e.to_string()))?;
+                serde_json::from_slice(&body).map(Some).map_err(|e| LookupError::Unavailable(e.to_string()))
+            }
+            status => Err(LookupError::Unavailable(format!("GET {} returned {}", path, status))),
+        }
+    }
+}
+
+#[async_trait]
+impl CustomerSource for HttpUserSource {
+    async fn fetch_customer(&self, id: Uuid) -> Result<Option<Customer>, LookupError> {
+        let user: Option<UserRecord> = self.get(&format!("/users/{}", id)).await?;
+        Ok(user.map(Customer::from))
+    }
+}
+
+#[async_trait]
+impl UserExport for HttpUserSource {
+    async fn export_page(&self, cursor: Option<String>) -> Result<Page<UserRecord>, LookupError> {
+        let path = match cursor {
+            Some(cursor) => format!("/users/export?limit=100&cursor={}", cursor),
+            None => "/users/export?limit=100".to_string(),
+        };
+        self.get(&path)
+            .await?
+            .ok_or_else(|| LookupError::Unavailable("user-service has no export endpoint".to_string()))
+    }
+}
+
+/// Reads users from user-service's gRPC API.
+pub struct GrpcUserSource {
+    client: UserServiceClient<Channel>,
+}
+
+impl GrpcUserSource {
+    /// Connects on first use, so user-service need not be up yet.
+    pub fn new(url: &str) -> Result<Self, LookupError> {
+        let endpoint = Endpoint::from_shared(url.to_string()).map_err(|e| LookupError::Unavailable(e.to_string()))?;
+        Ok(Self { client: UserServiceClient::new(endpoint.connect_lazy()) })
+    }
+}
+
+#[async_trait]
+impl CustomerSource for GrpcUserSource {
+    async fn fetch_customer(&self, id: Uuid) -> Result<Option<Customer>, LookupError> {
+        let request = observability::outgoing_request(user_client::GetUserRequest { id: id.to_string() });
+        match self.client.clone().get_user(request).await {
+            Ok(response) => {
+                let user = response.into_inner();
+                Ok(Some(Customer { id, name: user.username, email: user.email }))
+            }
+            // Deleted users answer NOT_FOUND too
+            Err(status) if status.code() == Code::NotFound => Ok(None),
+            Err(status) => Err(LookupError::Unavailable(format!("{:?}: {}", status.code(), status.message()))),
+        }
+    }
+}
+
+/// What to do with an order when its user cannot be checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FallbackPolicy {
+    /// Refuse the order with 503 so the client retries later.
+    #[default]
+    Reject,
+    /// Take the order without checking the user, and log that.
+    AcceptUnverified,
+}
+
+impl FallbackPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "reject" => Some(FallbackPolicy::Reject),
+            "accept_unverified" => Some(FallbackPolicy::AcceptUnverified),
+            _ => None,
+        }
+    }
+}
+
+/// Where order-service looks users up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserLookup {
+    /// Ask user-service on every cache miss.
+    #[default]
+    Http,
+    /// The same over user-service's gRPC API.
+    Grpc,
+    /// Read the local `customers` table kept up to date by user events.
+    Replica,
+    /// Do not check order user ids at all. Has to be asked for explicitly.
+    Off,
+}
+
+impl UserLookup {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "http" => Some(UserLookup::Http),
+            "grpc" => Some(UserLookup::Grpc),
+            "replica" => Some(UserLookup::Replica),
+            "off" => Some(UserLookup::Off),
+            _ => None,
+        }
+    }
+}
+
+pub const DEFAULT_LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long a looked-up customer is trusted without asking again.
+pub const DEFAULT_FRESH_FOR: Duration = Duration::from_secs(5 * 60);
+/// How long a customer is kept to fall back on while user-service is down.
+pub const DEFAULT_STALE_FOR: Duration = Duration::from_secs(60 * 60);
+
+/// Cached customer lookups.
+///
+/// An entry younger than `fresh_for` is used as is. Older entries are looked
+/// up again, but still answer for the customer while user-service is down.
+/// Unknown users are not cached, since they may sign up a moment later.
+#[derive(Clone)]
+pub struct CustomerDirectory {
+    source: Arc<dyn CustomerSource>,
+    cache: Cache<Uuid, (Customer, Instant)>,
+    fresh_for: Duration,
+    timeout: Duration,
+    fallback: FallbackPolicy,
+}
+
+impl CustomerDirectory {
+    pub fn new(source: impl CustomerSource + 'static) -> Self {
+        Self {
+            source: Arc::new(source),
+            cache: Self::build_cache(DEFAULT_STALE_FOR),
+            fresh_for: DEFAULT_FRESH_FOR,
+            timeout: DEFAULT_LOOKUP_TIMEOUT,
+            fallback: FallbackPolicy::default(),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_fallback(mut self, fallback: FallbackPolicy) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// `fresh_for` must not exceed `stale_for`.
+    pub fn with_cache_ttl(mut self, fresh_for: Duration, stale_for: Duration) -> Self {
+        self.fresh_for = fresh_for;
+        self.cache = Self::build_cache(stale_for);
+        self
+    }
+
+    fn build_cache(stale_for: Duration) -> Cache<Uuid, (Customer, Instant)> {
+        Cache::builder().max_capacity(10_000).time_to_live(stale_for).build()
+    }
+
+    /// The customer `id`, or `None` if user-service could not be asked and
+    /// the fallback policy lets the order through anyway.
+    ///
+    /// Fails with a validation error for users that do not exist, and with
+    /// `ServiceUnavailable` when user-service is down and the policy rejects.
+    pub async fn require(&self, id: Uuid) -> Result<Option<Customer>, AppError> {
+        let cached = self.cache.get(&id).await;
+        if let Some((customer, fetched_at)) = &cached {
+            if fetched_at.elapsed() < self.fresh_for {
+                return Ok(Some(customer.clone()));
+            }
+        }
+
+        let error = match tokio::time::timeout(self.timeout, self.source.fetch_customer(id)).await {
+            Ok(Ok(Some(customer))) => {
+                self.cache.insert(id, (customer.clone(), Instant::now())).await;
+                return Ok(Some(customer));
+            }
+            Ok(Ok(None)) => {
+                self.cache.invalidate(&id).await;
+                return Err(AppError::ValidationError(format!("User {} does not exist", id)));
+            }
+            Ok(Err(e)) => e,
+            Err(_) => LookupError::Timeout(self.timeout),
+        };
+
+        if let Some((customer, _)) = cached {
+            tracing::warn!("Using cached customer {} because the lookup failed: {}", id, error);
+            return Ok(Some(customer));
+        }
+        match self.fallback {
+            FallbackPolicy::Reject => Err(AppError::ServiceUnavailable(error.to_string())),
+            FallbackPolicy::AcceptUnverified => {
+                tracing::warn!("Accepting unverified user {}: {}", id, error);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// order-service's own copy of user-service's users, fed by user events and
+/// backfilled from user-service's bulk export.
+///
+/// Every change carries the time the user changed upstream, and a row only
+/// takes changes newer than the one it holds, so events may arrive late,
+/// twice or out of order. A deletion wins over a change at the same instant.
+#[derive(Clone)]
+pub struct CustomerReplica {
+    pool: AnyPool,
+}
+
+impl CustomerReplica {
+    pub fn new(pool: AnyPool) -> Self {
+        Self { pool }
+    }
+
+    /// Records `customer` as of `changed_at`. Returns `false` if a newer
+    /// change was already applied.
+    pub async fn upsert(&self, customer: &Customer, changed_at: OffsetDateTime) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO customers (id, name, email, deleted, changed_at) VALUES ($1, $2, $3, 0, $4)
+             ON CONFLICT (id) DO UPDATE SET
+                 name = excluded.name,
+                 email = excluded.email,
+                 deleted = 0,
+                 changed_at = excluded.changed_at
+             WHERE customers.changed_at < excluded.changed_at",
+        )
+        .bind(customer.id.to_string())
+        .bind(&customer.name)
+        .bind(&customer.email)
+        .bind(to_timestamp(changed_at))
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Marks the customer deleted as of `deleted_at`, leaving a tombstone
+    /// even if the customer was never seen.
+    pub async fn remove(&self, id: Uuid, deleted_at: OffsetDateTime) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO customers (id, name, email, deleted, changed_at) VALUES ($1, '', '', 1, $2)
+             ON CONFLICT (id) DO UPDATE SET
+                 deleted = 1,
+                 changed_at = excluded.changed_at
+             WHERE customers.changed_at <= excluded.changed_at",
+        )
+        .bind(id.to_string())
+        .bind(to_timestamp(deleted_at))
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// The customer, unless unknown or deleted.
+    pub async fn get(&self, id: Uuid) -> Result<Option<Customer>, sqlx::Error> {
+        let row = sqlx::query("SELECT * FROM customers WHERE id = $1 AND deleted = 0")
+            .bind(id.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(|row| {
+            Ok(Customer { id, name: row.try_get("name")?, email: row.try_get("email")? })
+        })
+        .transpose()
+    }
+
+    /// Applies a `user_created`, `user_updated`, `user_deleted` or
+    /// `user_restored` message. Other messages are ignored.
+    pub async fn apply(&self, message: &Message) -> Result<bool, MessagingError> {
+        let applied = match message.message_type.as_str() {
+            UserCreatedEvent::TYPE => {
+                let event: UserCreatedEvent = message.decode()?;
+                let customer = Customer { id: event.user_id, name: event.username, email: event.email };
+                self.upsert(&customer, event.timestamp).await?
+            }
+            UserUpdatedEvent::TYPE => {
+                let event: UserUpdatedEvent = message.decode()?;
+                let customer = Customer { id: event.user_id, name: event.username, email: event.email };
+                self.upsert(&customer, event.timestamp).await?
+            }
+            UserDeletedEvent::TYPE => {
+                let event: UserDeletedEvent = message.decode()?;
+                self.remove(event.user_id, event.timestamp).await?
+            }
+            UserRestoredEvent::TYPE => {
+                let event: UserRestoredEvent = message.decode()?;
+                let customer = Customer { id: event.user_id, name: event.username, email: event.email };
+                self.upsert(&customer, event.timestamp).await?
+            }
+            _ => false,
+        };
+        Ok(applied)
+    }
+
+    /// Copies every user from `export` and returns how many there were.
+    /// Safe to run while events are flowing and on every start, since
+    /// neither side can overwrite a newer change.
+    pub async fn backfill(&self, export: &dyn UserExport) -> Result<usize, LookupError> {
+        let mut cursor = None;
+        let mut count = 0;
+        loop {
+            let page = export.export_page(cursor).await?;
+            for user in page.items {
+                let changed_at = user.updated_at;
+                let result = match user.deleted_at {
+                    Some(_) => self.remove(user.id, changed_at).await,
+                    None => self.upsert(&Customer::from(user), changed_at).await,
+                };
+                result.map_err(|e| LookupError::Unavailable(e.to_string()))?;
+                count += 1;
+            }
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return Ok(count),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl CustomerSource for CustomerReplica {
+    async fn fetch_customer(&self, id: Uuid) -> Result<Option<Customer>, LookupError> {
+        self.get(id).await.map_err(|e| LookupError::Unavailable(e.to_string()))
+    }
+}
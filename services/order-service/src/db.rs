@@ -0,0 +1,20 @@
+use messaging::{EventStore, SagaStore, Scheduler};
+use sqlx::migrate::Migrator;
+use shared::IdempotencyStore;
+use sqlx::AnyPool;
+
+/// Schema migrations, embedded at compile time from `migrations/`.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Opens the database named by `database_url` and brings its schema up to
+/// date, including the tables of the messaging components this service
+/// runs and its idempotency keys. `sqlite:` and `postgres:` URLs are supported.
+pub async fn connect(database_url: &str) -> Result<AnyPool, messaging::MessagingError> {
+    let pool = shared::db::connect(database_url).await?;
+    MIGRATOR.run(&pool).await.map_err(sqlx::Error::from)?;
+    EventStore::new(pool.clone()).init_schema().await?;
+    SagaStore::new(pool.clone()).init_schema().await?;
+    Scheduler::new(pool.clone()).init_schema().await?;
+    IdempotencyStore::new(pool.clone()).init_schema().await?;
+    Ok(pool)
+}
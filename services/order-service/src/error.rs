@@ -5,6 +5,8 @@ use axum::{
 use thiserror::Error;
 use sqlx::Error as SqlxError;
 
+use crate::models::OrderStatus;
+
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("Database error: {0}")]
@@ -13,16 +15,65 @@ pub enum AppError {
     ValidationError(String),
     #[error("Order not found")]
     OrderNotFound,
+    #[error("Saga not found")]
+    SagaNotFound,
+    #[error("Cannot move order from {from} to {to}")]
+    InvalidTransition { from: OrderStatus, to: OrderStatus },
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
+    #[error("Messaging error: {0}")]
+    MessagingError(#[from] messaging::MessagingError),
+    #[error("{0}")]
+    Precondition(#[from] shared::PreconditionError),
+}
+
+impl From<shared::MoneyError> for AppError {
+    fn from(error: shared::MoneyError) -> Self {
+        AppError::ValidationError(error.to_string())
+    }
+}
+
+impl From<shared::SharedError> for AppError {
+    fn from(error: shared::SharedError) -> Self {
+        AppError::ValidationError(error.to_string())
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let status = match &self {
+            // Carries the current ETag along with the status
+            AppError::Precondition(e) => return e.clone().into_response(),
             AppError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::ValidationError(_) => StatusCode::BAD_REQUEST,
-            AppError::OrderNotFound => StatusCode::NOT_FOUND,
+            AppError::OrderNotFound | AppError::SagaNotFound => StatusCode::NOT_FOUND,
+            AppError::InvalidTransition { .. } => StatusCode::CONFLICT,
+            AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            // Another request changed the order between our read and write
+            AppError::MessagingError(messaging::MessagingError::ConcurrencyConflict { .. }) => StatusCode::CONFLICT,
+            AppError::MessagingError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
         (status, self.to_string()).into_response()
     }
-}
\ No newline at end of file
+}
+
+impl From<AppError> for tonic::Status {
+    fn from(error: AppError) -> Self {
+        let message = error.to_string();
+        match error {
+            AppError::DatabaseError(_) => tonic::Status::internal(message),
+            AppError::ValidationError(_) => tonic::Status::invalid_argument(message),
+            AppError::OrderNotFound | AppError::SagaNotFound => tonic::Status::not_found(message),
+            AppError::InvalidTransition { .. } | AppError::Precondition(_) => {
+                tonic::Status::failed_precondition(message)
+            }
+            AppError::ServiceUnavailable(_) => tonic::Status::unavailable(message),
+            // Another request changed the order between our read and write
+            AppError::MessagingError(messaging::MessagingError::ConcurrencyConflict { .. }) => {
+                tonic::Status::aborted(message)
+            }
+            AppError::MessagingError(_) => tonic::Status::internal(message),
+        }
+    }
+}
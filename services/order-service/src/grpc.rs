@@ -0,0 +1,157 @@
+// Status is large, but it is what every gRPC handler returns anyway
+#![allow(clippy::result_large_err)]
+
+use futures::Stream;
+use order_client::order_service_server::{self, OrderServiceServer};
+use shared::grpc::{idempotent, page_request, parse_id, parse_optional_id, to_timestamp};
+use shared::{IdempotencyStore, Money};
+use std::pin::Pin;
+use tokio::sync::broadcast::error::RecvError;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use crate::{
+    models::{CreateOrderRequest, LineItem, Order, OrderUpdate},
+    services::OrderService,
+};
+
+/// order-service over gRPC, as described by the `order-client` crate's
+/// `proto/order.proto`.
+#[derive(Clone)]
+pub struct GrpcOrders {
+    orders: OrderService,
+    idempotency: IdempotencyStore,
+}
+
+impl GrpcOrders {
+    /// Creating orders honours `idempotency-key` metadata, with keys kept
+    /// in `idempotency`.
+    pub fn new(orders: OrderService, idempotency: IdempotencyStore) -> Self {
+        Self { orders, idempotency }
+    }
+
+    pub fn into_server(self) -> OrderServiceServer<Self> {
+        OrderServiceServer::new(self)
+    }
+}
+
+fn money(money: Money) -> order_client::Money {
+    order_client::Money { amount_minor: money.amount_minor, currency: money.currency }
+}
+
+impl From<LineItem> for order_client::LineItem {
+    fn from(item: LineItem) -> Self {
+        Self { sku: item.sku, quantity: item.quantity, unit_price: Some(money(item.unit_price)) }
+    }
+}
+
+impl From<Order> for order_client::Order {
+    fn from(order: Order) -> Self {
+        Self {
+            id: order.id.to_string(),
+            user_id: order.user_id.to_string(),
+            line_items: order.line_items.into_iter().map(Into::into).collect(),
+            total: Some(money(order.total)),
+            status: order.status.to_string(),
+            created_at: Some(to_timestamp(order.created_at)),
+            updated_at: Some(to_timestamp(order.updated_at)),
+            version: order.version,
+        }
+    }
+}
+
+impl From<OrderUpdate> for order_client::OrderUpdate {
+    fn from(update: OrderUpdate) -> Self {
+        Self {
+            order: Some(update.order.into()),
+            from_status: update.change.from.map(|status| status.to_string()).unwrap_or_default(),
+            to_status: update.change.to.to_string(),
+            reason: update.change.reason.unwrap_or_default(),
+            changed_at: Some(to_timestamp(update.change.changed_at)),
+        }
+    }
+}
+
+fn line_item(item: order_client::LineItem) -> Result<LineItem, Status> {
+    let unit_price = item
+        .unit_price
+        .ok_or_else(|| Status::invalid_argument(format!("Line item {} has no unit price", item.sku)))?;
+    Ok(LineItem {
+        sku: item.sku,
+        quantity: item.quantity,
+        unit_price: Money { amount_minor: unit_price.amount_minor, currency: unit_price.currency },
+    })
+}
+
+/// Whether `update` is about the order, or an order of the user, asked for.
+fn wanted(update: &OrderUpdate, order_id: Option<Uuid>, user_id: Option<Uuid>) -> bool {
+    order_id.is_none_or(|id| update.order.id == id) && user_id.is_none_or(|id| update.order.user_id == id)
+}
+
+#[tonic::async_trait]
+impl order_service_server::OrderService for GrpcOrders {
+    async fn create_order(
+        &self,
+        request: Request<order_client::CreateOrderRequest>,
+    ) -> Result<Response<order_client::Order>, Status> {
+        idempotent(&self.idempotency, "/order.v1.OrderService/CreateOrder", request, |request| async move {
+            let request = CreateOrderRequest {
+                user_id: parse_id("user_id", &request.user_id)?,
+                line_items: request.line_items.into_iter().map(line_item).collect::<Result<_, _>>()?,
+            };
+            Ok(self.orders.create_order(request).await?.into())
+        })
+        .await
+    }
+
+    async fn get_order(
+        &self,
+        request: Request<order_client::GetOrderRequest>,
+    ) -> Result<Response<order_client::Order>, Status> {
+        let id = parse_id("id", &request.into_inner().id)?;
+        Ok(Response::new(self.orders.get_order_by_id(id).await?.into()))
+    }
+
+    async fn list_orders(
+        &self,
+        request: Request<order_client::ListOrdersRequest>,
+    ) -> Result<Response<order_client::ListOrdersResponse>, Status> {
+        let request = request.into_inner();
+        let user_id = parse_id("user_id", &request.user_id)?;
+        let page = page_request(request.limit, request.cursor);
+        let orders = self.orders.list_orders_by_user(user_id, page.cursor()?, page.limit()).await?;
+        Ok(Response::new(order_client::ListOrdersResponse {
+            orders: orders.items.into_iter().map(Into::into).collect(),
+            next_cursor: orders.next_cursor.unwrap_or_default(),
+        }))
+    }
+
+    type StreamOrderUpdatesStream = Pin<Box<dyn Stream<Item = Result<order_client::OrderUpdate, Status>> + Send>>;
+
+    async fn stream_order_updates(
+        &self,
+        request: Request<order_client::StreamOrderUpdatesRequest>,
+    ) -> Result<Response<Self::StreamOrderUpdatesStream>, Status> {
+        let request = request.into_inner();
+        let order_id = parse_optional_id("order_id", request.order_id)?;
+        let user_id = parse_optional_id("user_id", request.user_id)?;
+
+        // Ends after telling a client that fell behind, which can then
+        // reread the orders it follows and subscribe again
+        let stream = futures::stream::unfold(Some(self.orders.subscribe()), move |updates| async move {
+            let mut updates = updates?;
+            loop {
+                match updates.recv().await {
+                    Ok(update) if wanted(&update, order_id, user_id) => return Some((Ok(update.into()), Some(updates))),
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(missed)) => {
+                        let status = Status::data_loss(format!("Missed {} order updates", missed));
+                        return Some((Err(status), None));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
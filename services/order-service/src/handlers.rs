@@ -1,57 +1,177 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
     Json,
 };
-use serde_json::json;
 use uuid::Uuid;
 
 use crate::{
-    models::{Order, CreateOrderRequest},
-    services::OrderService,
-    config::Config,
+    models::{CreateOrderRequest, Order, OrderTransition, TransitionRequest},
     error::AppError,
+    projections::OrderSummaries,
+    saga,
+    services::OrderService,
 };
+use messaging::{
+    EventCatalog, OrderCreatedEvent, OrderStatusChangedEvent, SagaStore, UserCreatedEvent, UserDeletedEvent, UserRestoredEvent,
+    UserUpdatedEvent,
+};
+use serde::Deserialize;
+use shared::{IfMatch, IfNoneMatch, PageRequest, Versioned};
+use sqlx::AnyPool;
 
 pub async fn health_check() -> impl IntoResponse {
     (StatusCode::OK, "Order service is healthy")
 }
 
 pub async fn create_order(
-    State(config): State<Config>,
+    State(orders): State<OrderService>,
     Json(request): Json<CreateOrderRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    // In a real implementation, we would initialize the repository and service here
-    // For now, we'll just return a mock response
-    let order = Order {
-        id: Uuid::new_v4(),
-        user_id: request.user_id,
-        product_name: request.product_name,
-        quantity: request.quantity,
-        total_price: request.total_price,
-        created_at: time::OffsetDateTime::now_utc(),
-        updated_at: time::OffsetDateTime::now_utc(),
-    };
-    
-    Ok((StatusCode::CREATED, Json(order)))
+    let order = orders.create_order(request).await?;
+    Ok((StatusCode::CREATED, Versioned(order.version, order)))
 }
 
 pub async fn get_order_by_id(
-    State(config): State<Config>,
-    Path(id): Path<Uuid>,
-) -> Result<impl IntoResponse, AppError> {
-    // In a real implementation, we would fetch from the database
-    // For now, we'll just return a mock response
-    let order = Order {
-        id,
-        user_id: Uuid::new_v4(),
-        product_name: "Test Product".to_string(),
-        quantity: 1,
-        total_price: 99.99,
-        created_at: time::OffsetDateTime::now_utc(),
-        updated_at: time::OffsetDateTime::now_utc(),
-    };
-    
-    Ok(Json(order))
-}
\ No newline at end of file
+    State(orders): State<OrderService>,
+    Path(id): Path<Uuid>,
+    if_none_match: IfNoneMatch,
+) -> Result<impl IntoResponse, AppError> {
+    let order = orders.get_order_by_id(id).await?;
+    Ok(if_none_match.respond(order.version, order))
+}
+
+async fn transition_order(
+    orders: OrderService,
+    id: Uuid,
+    transition: OrderTransition,
+    if_match: IfMatch,
+    request: Option<Json<TransitionRequest>>,
+) -> Result<Versioned<Order>, AppError> {
+    let reason = request.and_then(|Json(request)| request.reason);
+    let order = orders.transition(id, transition, reason, &if_match).await?;
+    Ok(Versioned(order.version, order))
+}
+
+pub async fn confirm_order(
+    State(orders): State<OrderService>,
+    Path(id): Path<Uuid>,
+    if_match: IfMatch,
+    request: Option<Json<TransitionRequest>>,
+) -> Result<impl IntoResponse, AppError> {
+    transition_order(orders, id, OrderTransition::Confirm, if_match, request).await
+}
+
+pub async fn pay_order(
+    State(orders): State<OrderService>,
+    Path(id): Path<Uuid>,
+    if_match: IfMatch,
+    request: Option<Json<TransitionRequest>>,
+) -> Result<impl IntoResponse, AppError> {
+    transition_order(orders, id, OrderTransition::Pay, if_match, request).await
+}
+
+pub async fn ship_order(
+    State(orders): State<OrderService>,
+    Path(id): Path<Uuid>,
+    if_match: IfMatch,
+    request: Option<Json<TransitionRequest>>,
+) -> Result<impl IntoResponse, AppError> {
+    transition_order(orders, id, OrderTransition::Ship, if_match, request).await
+}
+
+pub async fn deliver_order(
+    State(orders): State<OrderService>,
+    Path(id): Path<Uuid>,
+    if_match: IfMatch,
+    request: Option<Json<TransitionRequest>>,
+) -> Result<impl IntoResponse, AppError> {
+    transition_order(orders, id, OrderTransition::Deliver, if_match, request).await
+}
+
+pub async fn cancel_order(
+    State(orders): State<OrderService>,
+    Path(id): Path<Uuid>,
+    if_match: IfMatch,
+    request: Option<Json<TransitionRequest>>,
+) -> Result<impl IntoResponse, AppError> {
+    transition_order(orders, id, OrderTransition::Cancel, if_match, request).await
+}
+
+pub async fn refund_order(
+    State(orders): State<OrderService>,
+    Path(id): Path<Uuid>,
+    if_match: IfMatch,
+    request: Option<Json<TransitionRequest>>,
+) -> Result<impl IntoResponse, AppError> {
+    transition_order(orders, id, OrderTransition::Refund, if_match, request).await
+}
+
+pub async fn get_order_history(
+    State(orders): State<OrderService>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(orders.get_status_history(id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListOrdersQuery {
+    pub user_id: Uuid,
+}
+
+pub async fn list_orders(
+    State(orders): State<OrderService>,
+    Query(query): Query<ListOrdersQuery>,
+    Query(page): Query<PageRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let orders = orders.list_orders_by_user(query.user_id, page.cursor()?, page.limit()).await?;
+    Ok(Json(orders))
+}
+
+pub async fn get_saga_status(
+    State(sagas): State<SagaStore>,
+    Path(correlation_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let saga = sagas.get(correlation_id).await?.ok_or(AppError::SagaNotFound)?;
+    Ok(Json(saga))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DashboardQuery {
+    pub limit: Option<i64>,
+}
+
+pub async fn get_dashboard_orders(
+    State(pool): State<AnyPool>,
+    Query(query): Query<DashboardQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+    Ok(Json(OrderSummaries::dashboard(&pool, limit).await?))
+}
+
+pub async fn metrics() -> impl IntoResponse {
+    observability::render_metrics()
+}
+
+/// Messages this service sends and receives.
+pub fn event_catalog() -> EventCatalog {
+    EventCatalog::new("order-service", env!("CARGO_PKG_VERSION"))
+        .description("Order placement, the order lifecycle and the order read models")
+        .publishes::<OrderCreatedEvent>()
+        .publishes::<OrderStatusChangedEvent>()
+        .subscribes::<OrderCreatedEvent>()
+        .subscribes::<UserCreatedEvent>()
+        .subscribes::<UserUpdatedEvent>()
+        .subscribes::<UserDeletedEvent>()
+        .subscribes::<UserRestoredEvent>()
+        .sends_command(saga::RESERVE_INVENTORY, "Reserve stock for a new order")
+        .sends_command(saga::RELEASE_INVENTORY, "Release stock reserved for a failed order")
+        .sends_command(saga::AUTHORIZE_PAYMENT, "Authorize payment for a new order")
+        .sends_command(saga::VOID_PAYMENT, "Void the payment of a failed order")
+        .sends_command(saga::CONFIRM_ORDER, "Confirm an order once stock and payment are secured")
+}
+
+pub async fn asyncapi() -> impl IntoResponse {
+    Json(event_catalog().to_asyncapi())
+}
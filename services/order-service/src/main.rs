@@ -2,33 +2,236 @@ use axum::{
     routing::{get, post},
     Router,
 };
+use messaging::{
+    Event, EventStore, OrderCreatedEvent, OrderStatusChangedEvent, Projector, Publisher, SagaOrchestrator, SagaStore,
+    Scheduler, Subscriber, SubscriptionOptions, TrustedKeys, UserCreatedEvent, UserDeletedEvent, UserRestoredEvent,
+    UserUpdatedEvent,
+};
 use std::net::SocketAddr;
-use tracing_subscriber;
+use std::sync::Arc;
+use std::time::Duration;
 
 mod handlers;
 mod services;
 mod repositories;
 mod models;
+mod aggregate;
+mod customers;
 mod config;
+mod db;
 mod error;
+mod grpc;
+mod projections;
+mod saga;
+mod state;
 
 #[cfg(test)]
-mod tests;
+#[path = "tests.rs"]
+// The tests still glob-import their parent module
+#[allow(unused_imports)]
+mod unit;
 
 #[tokio::main]
 async fn main() {
     // Initialize tracing
-    tracing_subscriber::fmt::init();
+    observability::init_tracing("order-service").expect("Failed to initialize tracing");
 
     // Load configuration
     let config = config::Config::from_env().expect("Failed to load configuration");
 
+    // Orders, their event streams and saga state live in the service database
+    let pool = db::connect(&config.database_url).await.expect("Failed to connect to database");
+    let events = EventStore::new(pool.clone());
+    let sagas = SagaStore::new(pool.clone());
+    let scheduler = Scheduler::new(pool.clone());
+    let mut orders = services::OrderService::new(repositories::OrderRepository::new(events.clone()));
+
+    // Orders are only taken from users that user-service knows about, asked
+    // directly or read from the local copy that user events keep current
+    let replica = customers::CustomerReplica::new(pool.clone());
+    // Config::from_env has already refused lookups without the URL they need
+    match (config.user_lookup, &config.user_service_url, &config.user_service_grpc_url) {
+        (customers::UserLookup::Http, Some(url), _) => {
+            let customers = customers::CustomerDirectory::new(customers::HttpUserSource::new(url))
+                .with_timeout(Duration::from_millis(config.user_lookup_timeout_ms))
+                .with_fallback(config.user_lookup_fallback);
+            orders = orders.with_customers(customers);
+        }
+        (customers::UserLookup::Grpc, _, Some(url)) => {
+            let source = customers::GrpcUserSource::new(url).expect("USER_SERVICE_GRPC_URL is checked by Config::from_env");
+            let customers = customers::CustomerDirectory::new(source)
+                .with_timeout(Duration::from_millis(config.user_lookup_timeout_ms))
+                .with_fallback(config.user_lookup_fallback);
+            orders = orders.with_customers(customers);
+        }
+        (customers::UserLookup::Http, None, _) | (customers::UserLookup::Grpc, _, None) => {
+            unreachable!("Config::from_env requires the user-service URL for the lookup")
+        }
+        (customers::UserLookup::Off, _, _) => {
+            tracing::warn!("USER_LOOKUP=off: orders are accepted without checking their user exists");
+        }
+        (customers::UserLookup::Replica, url, _) => {
+            if let Some(url) = url {
+                let export = customers::HttpUserSource::new(url);
+                let replica = replica.clone();
+                tokio::spawn(async move {
+                    loop {
+                        match replica.backfill(&export).await {
+                            Ok(count) => break tracing::info!("Backfilled {} customers from user-service", count),
+                            Err(e) => tracing::warn!("Customer backfill failed, retrying: {}", e),
+                        }
+                        tokio::time::sleep(Duration::from_secs(30)).await;
+                    }
+                });
+            }
+            // The replica is local, so there is nothing to keep fresh in memory
+            let customers = customers::CustomerDirectory::new(replica.clone())
+                .with_cache_ttl(Duration::ZERO, customers::DEFAULT_STALE_FOR)
+                .with_fallback(config.user_lookup_fallback);
+            orders = orders.with_customers(customers);
+        }
+    }
+
+    // Keep the dashboard read model up to date with the event feed
+    let projector = Projector::new(events.clone(), projections::OrderSummaries);
+    projector.init().await.expect("Failed to create read model tables");
+    tokio::spawn(async move { projector.run(Duration::from_secs(1)).await });
+
+    // Message subscriptions register here so shutdown can drain them
+    let subscriptions = messaging::Subscriptions::new();
+
+    if let Some(nats_url) = &config.nats_url {
+        let client = async_nats::connect(nats_url).await.expect("Failed to connect to NATS");
+
+        // Finish placement sagas interrupted by the last shutdown, then keep
+        // starting those that could not be started when their order was placed
+        let publisher = Arc::new(Publisher::new(client.clone()).with_scheduler(scheduler.clone()));
+        let placement = SagaOrchestrator::new(saga::order_placement(publisher.clone()), sagas);
+        orders = orders.with_placement_saga(placement.clone()).with_publisher(publisher.clone());
+        let placing = orders.clone();
+        tokio::spawn(async move {
+            if let Err(e) = placement.resume_unfinished().await {
+                tracing::error!("Failed to resume order placement sagas: {}", e);
+            }
+            loop {
+                match placing.start_missing_placements(Duration::from_secs(60)).await {
+                    Ok(0) => {}
+                    Ok(started) => tracing::info!("Started {} missing order placement sagas", started),
+                    Err(e) => tracing::error!("Failed to start missing order placement sagas: {}", e),
+                }
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+        });
+
+        // Send delayed messages, including any that came due while we were down
+        let dispatcher = publisher.clone();
+        tokio::spawn(async move { scheduler.run(dispatcher, Duration::from_secs(1)).await });
+
+        // Record user events in the local feed for the read models. Emails
+        // stay encrypted if user-service encrypts them; nothing here reads them.
+        let mut subscriber = Subscriber::new(client).with_upcasters(messaging::order_created_upcasters());
+        if let Some(keys) = &config.trusted_keys {
+            subscriber = subscriber.with_trusted_keys(TrustedKeys::from_json(keys).expect("Invalid trusted keys"));
+        }
+        // Instances share one database, so each event is recorded and
+        // applied by only one of them: every consumer has a queue group.
+        let recorders = SubscriptionOptions::new().queue_group("order-service-feed");
+        let user_subjects = [
+            UserCreatedEvent::SUBJECT,
+            UserUpdatedEvent::SUBJECT,
+            UserDeletedEvent::SUBJECT,
+            UserRestoredEvent::SUBJECT,
+        ];
+        for subject in user_subjects {
+            let recorder = events.clone();
+            let handle = subscriber
+                .subscribe_with(subject, recorders.clone(), move |message| {
+                    let recorder = recorder.clone();
+                    async move { recorder.record_message(&message).await.map(|_| ()) }
+                })
+                .await
+                .expect("Failed to subscribe to user events");
+            subscriptions.add(handle).await;
+        }
+
+        // Orders from every producer feed the dashboard. Older payload
+        // versions are upcast first, so the feed only holds the current one.
+        let recorder = events.clone();
+        let handle = subscriber
+            .subscribe_typed_with(recorders, move |_: OrderCreatedEvent, message| {
+                let recorder = recorder.clone();
+                async move { recorder.record_message(&message).await.map(|_| ()) }
+            })
+            .await
+            .expect("Failed to subscribe to order events");
+        subscriptions.add(handle).await;
+
+        // Keep the local customers table in step with user-service
+        let replicas = SubscriptionOptions::new().queue_group("order-service-customers");
+        for subject in user_subjects {
+            let replica = replica.clone();
+            let handle = subscriber
+                .subscribe_with(subject, replicas.clone(), move |message| {
+                    let replica = replica.clone();
+                    async move { replica.apply(&message).await.map(|_| ()) }
+                })
+                .await
+                .expect("Failed to subscribe to user events");
+            subscriptions.add(handle).await;
+        }
+
+        // Live order updates for gRPC streams. Every instance relays every
+        // event, without a queue group, since any of them may hold a stream.
+        let relay = orders.clone();
+        let handle = subscriber
+            .subscribe_typed(move |event: OrderCreatedEvent, _| {
+                let relay = relay.clone();
+                async move {
+                    if let Err(e) = relay.relay_placement(&event).await {
+                        tracing::warn!("Failed to relay placement of order {}: {}", event.order_id, e);
+                    }
+                    Ok(())
+                }
+            })
+            .await
+            .expect("Failed to subscribe to order events");
+        subscriptions.add(handle).await;
+        let relay = orders.clone();
+        let handle = subscriber
+            .subscribe_typed(move |event: OrderStatusChangedEvent, _| {
+                let relay = relay.clone();
+                async move {
+                    if let Err(e) = relay.relay_status_change(&event).await {
+                        tracing::warn!("Failed to relay status change of order {}: {}", event.order_id, e);
+                    }
+                    Ok(())
+                }
+            })
+            .await
+            .expect("Failed to subscribe to order events");
+        subscriptions.add(handle).await;
+    }
+
+    // The same orders over gRPC, on a port of its own
+    let grpc_addr = SocketAddr::from(([0, 0, 0, 0], config.grpc_port));
+    let (stop_grpc, grpc_stopped) = tokio::sync::oneshot::channel::<()>();
+    let grpc_server = tonic::transport::Server::builder()
+        .add_service(grpc::GrpcOrders::new(orders.clone(), shared::IdempotencyStore::new(pool.clone())).into_server())
+        .serve_with_shutdown(grpc_addr, async {
+            grpc_stopped.await.ok();
+        });
+    tracing::info!("Order service gRPC listening on {}", grpc_addr);
+    let grpc_server = tokio::spawn(async move {
+        if let Err(e) = grpc_server.await {
+            tracing::error!("gRPC server failed: {}", e);
+        }
+    });
+
     // Build our application with routes
-    let app = Router::new()
-        .route("/health", get(handlers::health_check))
-        .route("/orders", post(handlers::create_order))
-        .route("/orders/:id", get(handlers::get_order_by_id))
-        .with_state(config);
+    let state = state::AppState::new(config, pool, orders);
+    let idempotency = state.idempotency.clone();
+    tokio::spawn(async move { idempotency.run_purge(Duration::from_secs(60 * 60)).await });
+    let app = app(state);
 
     // Run our app with hyper, listening globally on port 3002
     let addr = SocketAddr::from(([0, 0, 0, 0], 3002));
@@ -36,12 +239,43 @@ async fn main() {
     
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
     axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
+        .with_graceful_shutdown(async move {
+            shutdown_signal(subscriptions).await;
+            stop_grpc.send(()).ok();
+        })
         .await
         .unwrap();
+    // Update streams never finish on their own, so give them a moment only
+    if tokio::time::timeout(Duration::from_secs(5), grpc_server).await.is_err() {
+        tracing::warn!("Closing gRPC streams still open at shutdown");
+    }
+
+    observability::shutdown_tracing();
 }
 
-async fn shutdown_signal() {
+fn app(state: state::AppState) -> Router {
+    // Retried order submissions with the same Idempotency-Key place one order
+    let idempotent = axum::middleware::from_fn_with_state(state.idempotency.clone(), shared::idempotency);
+    Router::new()
+        .route("/health", get(handlers::health_check))
+        .route("/orders", post(handlers::create_order).layer(idempotent).get(handlers::list_orders))
+        .route("/orders/:id", get(handlers::get_order_by_id))
+        .route("/orders/:id/confirm", post(handlers::confirm_order))
+        .route("/orders/:id/pay", post(handlers::pay_order))
+        .route("/orders/:id/ship", post(handlers::ship_order))
+        .route("/orders/:id/deliver", post(handlers::deliver_order))
+        .route("/orders/:id/cancel", post(handlers::cancel_order))
+        .route("/orders/:id/refund", post(handlers::refund_order))
+        .route("/orders/:id/history", get(handlers::get_order_history))
+        .route("/sagas/:correlation_id", get(handlers::get_saga_status))
+        .route("/dashboard/orders", get(handlers::get_dashboard_orders))
+        .route("/metrics", get(handlers::metrics))
+        .route(messaging::ASYNCAPI_PATH, get(handlers::asyncapi))
+        .layer(axum::middleware::from_fn(observability::trace_requests))
+        .with_state(state)
+}
+
+async fn shutdown_signal(subscriptions: messaging::Subscriptions) {
     let ctrl_c = async {
         tokio::signal::ctrl_c()
             .await
@@ -65,4 +299,9 @@ async fn shutdown_signal() {
     }
     
     tracing::info!("Signal received, starting graceful shutdown");
+
+    // Finish in-flight message handlers while the HTTP server is still up
+    for status in subscriptions.drain_all().await {
+        tracing::info!("Drained {} ({} handled, {} failed)", status.subject, status.handled, status.failed);
+    }
 }
\ No newline at end of file
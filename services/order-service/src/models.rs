@@ -1,23 +1,176 @@
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use shared::{Money, MoneyError};
+use std::fmt;
 use uuid::Uuid;
 use time::OffsetDateTime;
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
     pub id: Uuid,
     pub user_id: Uuid,
-    pub product_name: String,
-    pub quantity: i32,
-    pub total_price: f64,
+    pub line_items: Vec<LineItem>,
+    /// Sum of the line totals, computed by the service.
+    pub total: Money,
+    // Snapshots taken before orders had a status are pending.
+    #[serde(default)]
+    pub status: OrderStatus,
     pub created_at: OffsetDateTime,
     pub updated_at: OffsetDateTime,
+    /// The version of the order's event stream. Not kept in snapshots;
+    /// the repository sets it whenever it loads or saves the order.
+    #[serde(default)]
+    pub version: i64,
+}
+
+/// `quantity` units of the product `sku` at `unit_price` each.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LineItem {
+    pub sku: String,
+    pub quantity: i32,
+    pub unit_price: Money,
+}
+
+impl LineItem {
+    pub fn line_total(&self) -> Result<Money, MoneyError> {
+        self.unit_price.checked_mul(self.quantity as i64)
+    }
+}
+
+impl From<LineItem> for messaging::OrderLineItem {
+    fn from(item: LineItem) -> Self {
+        Self { sku: item.sku, quantity: item.quantity, unit_price: item.unit_price }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateOrderRequest {
     pub user_id: Uuid,
-    pub product_name: String,
-    pub quantity: i32,
-    pub total_price: f64,
-}
\ No newline at end of file
+    pub line_items: Vec<LineItem>,
+}
+
+/// Where an order is in its lifecycle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    #[default]
+    Pending,
+    Confirmed,
+    Paid,
+    Shipped,
+    Delivered,
+    Cancelled,
+    Refunded,
+}
+
+impl OrderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Pending => "pending",
+            OrderStatus::Confirmed => "confirmed",
+            OrderStatus::Paid => "paid",
+            OrderStatus::Shipped => "shipped",
+            OrderStatus::Delivered => "delivered",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Refunded => "refunded",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "pending" => OrderStatus::Pending,
+            "confirmed" => OrderStatus::Confirmed,
+            "paid" => OrderStatus::Paid,
+            "shipped" => OrderStatus::Shipped,
+            "delivered" => OrderStatus::Delivered,
+            "cancelled" => OrderStatus::Cancelled,
+            "refunded" => OrderStatus::Refunded,
+            _ => return None,
+        })
+    }
+
+    /// Whether an order may move from this status to `next`. Orders can be
+    /// cancelled until they are paid for; after that they are refunded.
+    pub fn can_transition_to(&self, next: OrderStatus) -> bool {
+        use OrderStatus::*;
+        matches!(
+            (self, next),
+            (Pending, Confirmed)
+                | (Pending, Cancelled)
+                | (Confirmed, Paid)
+                | (Confirmed, Cancelled)
+                | (Paid, Shipped)
+                | (Paid, Refunded)
+                | (Shipped, Delivered)
+                | (Delivered, Refunded)
+        )
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The lifecycle actions exposed as `POST /orders/:id/<action>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderTransition {
+    Confirm,
+    Pay,
+    Ship,
+    Deliver,
+    Cancel,
+    Refund,
+}
+
+impl OrderTransition {
+    pub fn target(&self) -> OrderStatus {
+        match self {
+            OrderTransition::Confirm => OrderStatus::Confirmed,
+            OrderTransition::Pay => OrderStatus::Paid,
+            OrderTransition::Ship => OrderStatus::Shipped,
+            OrderTransition::Deliver => OrderStatus::Delivered,
+            OrderTransition::Cancel => OrderStatus::Cancelled,
+            OrderTransition::Refund => OrderStatus::Refunded,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TransitionRequest {
+    pub reason: Option<String>,
+}
+
+/// One entry of an order's status history. `from` is absent for the
+/// status the order was placed in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusChange {
+    pub order_id: Uuid,
+    pub version: i64,
+    pub from: Option<OrderStatus>,
+    pub to: OrderStatus,
+    pub reason: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub changed_at: OffsetDateTime,
+}
+
+impl StatusChange {
+    /// The entry for placing `order`.
+    pub fn placed(order: &Order) -> Self {
+        StatusChange {
+            order_id: order.id,
+            version: order.version,
+            from: None,
+            to: order.status,
+            reason: None,
+            changed_at: order.created_at,
+        }
+    }
+}
+
+/// An order as it stands after a status change, for live subscribers.
+#[derive(Debug, Clone)]
+pub struct OrderUpdate {
+    pub order: Order,
+    pub change: StatusChange,
+}
@@ -0,0 +1,329 @@
+use async_trait::async_trait;
+use messaging::{
+    MessagingError, OrderCreatedEvent, Projection, RecordedEvent, UserCreatedEvent, UserDeletedEvent, UserRestoredEvent,
+    UserUpdatedEvent,
+};
+use serde::Serialize;
+use shared::db::{from_timestamp, get_amount, to_amount, to_timestamp, try_get_optional};
+use shared::Money;
+use sqlx::{AnyConnection, AnyPool, Row};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::aggregate::{OrderEvent, PlacedItems};
+use crate::models::{LineItem, OrderStatus};
+
+/// Orders joined with their customer's name, for the web-bff dashboard.
+///
+/// Customer names come from user events recorded from user-service; orders
+/// placed before their customer's event arrives get the name filled in once
+/// it does. Renames and restores update the name, and deleting a user
+/// removes it from their orders.
+///
+/// The tables carry a version suffix: changing their shape means a new
+/// projection name, which is then rebuilt from the start of the feed.
+pub struct OrderSummaries;
+
+#[derive(Debug, Serialize)]
+pub struct OrderSummary {
+    pub order_id: Uuid,
+    pub user_id: Uuid,
+    pub customer_name: Option<String>,
+    /// Units across all line items.
+    pub item_count: i32,
+    pub total: Money,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DashboardOrders {
+    pub order_count: i64,
+    /// One total per currency.
+    pub revenue: Vec<Money>,
+    pub recent_orders: Vec<OrderSummary>,
+}
+
+impl OrderSummaries {
+    async fn upsert_order(
+        conn: &mut AnyConnection,
+        order_id: Uuid,
+        user_id: Uuid,
+        line_items: &[LineItem],
+        total: &Money,
+        created_at: OffsetDateTime,
+    ) -> Result<(), MessagingError> {
+        let item_count: i32 = line_items.iter().map(|item| item.quantity).sum();
+        sqlx::query(
+            "INSERT INTO order_summaries_v3 (order_id, user_id, customer_name, item_count, total, currency, created_at)
+             VALUES ($1, $2, (SELECT username FROM customer_names WHERE user_id = $2), $3, $4, $5, $6)
+             ON CONFLICT (order_id) DO UPDATE SET
+                item_count = excluded.item_count,
+                total = excluded.total,
+                currency = excluded.currency",
+        )
+        .bind(order_id.to_string())
+        .bind(user_id.to_string())
+        .bind(item_count)
+        .bind(to_amount(total.amount_minor))
+        .bind(&total.currency)
+        .bind(to_timestamp(created_at))
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Records `username` as the customer's name, on orders already placed too.
+    async fn set_customer_name(conn: &mut AnyConnection, user_id: Uuid, username: &str) -> Result<(), MessagingError> {
+        sqlx::query(
+            "INSERT INTO customer_names (user_id, username) VALUES ($1, $2)
+             ON CONFLICT (user_id) DO UPDATE SET username = excluded.username",
+        )
+        .bind(user_id.to_string())
+        .bind(username)
+        .execute(&mut *conn)
+        .await?;
+        sqlx::query("UPDATE order_summaries_v3 SET customer_name = $1 WHERE user_id = $2")
+            .bind(username)
+            .bind(user_id.to_string())
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Totals and the `limit` most recent orders.
+    pub async fn dashboard(pool: &AnyPool, limit: i64) -> Result<DashboardOrders, MessagingError> {
+        let totals = sqlx::query(
+            "SELECT currency, COUNT(*) AS order_count, CAST(SUM(CAST(total AS BIGINT)) AS TEXT) AS revenue
+             FROM order_summaries_v3 GROUP BY currency ORDER BY currency",
+        )
+        .fetch_all(pool)
+        .await?;
+        let mut order_count = 0;
+        let mut revenue = Vec::new();
+        for row in &totals {
+            order_count += row.try_get::<i64, _>("order_count")?;
+            revenue.push(Money { amount_minor: get_amount(row, "revenue")?, currency: row.try_get("currency")? });
+        }
+
+        let rows = sqlx::query("SELECT * FROM order_summaries_v3 ORDER BY created_at DESC LIMIT $1")
+            .bind(limit)
+            .fetch_all(pool)
+            .await?;
+        let recent_orders = rows
+            .iter()
+            .map(|row| {
+                let parse = |column: &str| -> Result<Uuid, MessagingError> {
+                    let value: String = row.try_get(column)?;
+                    Uuid::parse_str(&value).map_err(|e| sqlx::Error::Decode(Box::new(e)).into())
+                };
+                Ok(OrderSummary {
+                    order_id: parse("order_id")?,
+                    user_id: parse("user_id")?,
+                    customer_name: try_get_optional(row, "customer_name")?,
+                    item_count: row.try_get("item_count")?,
+                    total: Money { amount_minor: get_amount(row, "total")?, currency: row.try_get("currency")? },
+                    created_at: from_timestamp(row.try_get("created_at")?),
+                })
+            })
+            .collect::<Result<_, MessagingError>>()?;
+
+        Ok(DashboardOrders {
+            order_count,
+            revenue,
+            recent_orders,
+        })
+    }
+}
+
+#[async_trait]
+impl Projection for OrderSummaries {
+    fn name(&self) -> &str {
+        "order_summaries_v3"
+    }
+
+    async fn init(&self, conn: &mut AnyConnection) -> Result<(), MessagingError> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS customer_names (
+                user_id TEXT PRIMARY KEY,
+                username TEXT NOT NULL
+            )",
+        )
+        .execute(&mut *conn)
+        .await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS order_summaries_v3 (
+                order_id TEXT PRIMARY KEY,
+                user_id TEXT NOT NULL,
+                customer_name TEXT,
+                item_count INTEGER NOT NULL,
+                total TEXT NOT NULL,
+                currency TEXT NOT NULL,
+                created_at DOUBLE PRECISION NOT NULL
+            )",
+        )
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn apply(&self, conn: &mut AnyConnection, event: &RecordedEvent) -> Result<(), MessagingError> {
+        match event.event_type.as_str() {
+            "order_placed" => {
+                let OrderEvent::OrderPlaced { order_id, user_id, items, placed_at } = event.decode::<OrderEvent>()? else {
+                    return Ok(());
+                };
+                Self::upsert_order(conn, order_id, user_id, &items.line_items, &items.total, placed_at).await
+            }
+            "order_created" => {
+                let order: OrderCreatedEvent = event.decode()?;
+                let line_items: Vec<LineItem> = order
+                    .line_items
+                    .into_iter()
+                    .map(|item| LineItem { sku: item.sku, quantity: item.quantity, unit_price: item.unit_price })
+                    .collect();
+                Self::upsert_order(conn, order.order_id, order.user_id, &line_items, &order.total, order.timestamp).await
+            }
+            "user_created" => {
+                let user: UserCreatedEvent = event.decode()?;
+                Self::set_customer_name(conn, user.user_id, &user.username).await
+            }
+            "user_updated" => {
+                let user: UserUpdatedEvent = event.decode()?;
+                Self::set_customer_name(conn, user.user_id, &user.username).await
+            }
+            "user_restored" => {
+                let user: UserRestoredEvent = event.decode()?;
+                Self::set_customer_name(conn, user.user_id, &user.username).await
+            }
+            "user_deleted" => {
+                let user: UserDeletedEvent = event.decode()?;
+                sqlx::query("DELETE FROM customer_names WHERE user_id = $1")
+                    .bind(user.user_id.to_string())
+                    .execute(&mut *conn)
+                    .await?;
+                sqlx::query("UPDATE order_summaries_v3 SET customer_name = NULL WHERE user_id = $1")
+                    .bind(user.user_id.to_string())
+                    .execute(&mut *conn)
+                    .await?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn reset(&self, conn: &mut AnyConnection) -> Result<(), MessagingError> {
+        sqlx::query("DELETE FROM order_summaries_v3").execute(&mut *conn).await?;
+        sqlx::query("DELETE FROM customer_names").execute(&mut *conn).await?;
+        Ok(())
+    }
+}
+
+/// The `orders`, `order_line_items` and `order_status_history` tables,
+/// applied inline by the order repository so they commit together with each
+/// order's events. Their schema belongs to the migrations.
+///
+/// Writes are guarded by the stream version, so applying an event twice, or
+/// an older one after a newer one, leaves the tables as they were.
+pub struct OrderTables;
+
+impl OrderTables {
+    async fn record_change(
+        conn: &mut AnyConnection,
+        order_id: Uuid,
+        version: i64,
+        from: Option<OrderStatus>,
+        to: OrderStatus,
+        reason: Option<&str>,
+        changed_at: OffsetDateTime,
+    ) -> Result<(), MessagingError> {
+        sqlx::query(
+            "INSERT INTO order_status_history (order_id, version, from_status, to_status, reason, changed_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (order_id, version) DO NOTHING",
+        )
+        .bind(order_id.to_string())
+        .bind(version)
+        .bind(from.map(|status| status.as_str()))
+        .bind(to.as_str())
+        .bind(reason)
+        .bind(to_timestamp(changed_at))
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Projection for OrderTables {
+    fn name(&self) -> &str {
+        "orders"
+    }
+
+    async fn init(&self, _conn: &mut AnyConnection) -> Result<(), MessagingError> {
+        Ok(())
+    }
+
+    async fn apply(&self, conn: &mut AnyConnection, event: &RecordedEvent) -> Result<(), MessagingError> {
+        if !matches!(event.event_type.as_str(), "order_placed" | "order_status_changed") {
+            return Ok(());
+        }
+        match event.decode::<OrderEvent>()? {
+            OrderEvent::OrderPlaced { order_id, user_id, items: PlacedItems { line_items, total }, placed_at } => {
+                sqlx::query(
+                    "INSERT INTO orders (id, user_id, total, currency, status, created_at, updated_at, version)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                     ON CONFLICT (id) DO UPDATE SET
+                         status = excluded.status,
+                         updated_at = excluded.updated_at,
+                         version = excluded.version
+                     WHERE orders.version < excluded.version",
+                )
+                .bind(order_id.to_string())
+                .bind(user_id.to_string())
+                .bind(to_amount(total.amount_minor))
+                .bind(&total.currency)
+                .bind(OrderStatus::Pending.as_str())
+                .bind(to_timestamp(placed_at))
+                .bind(to_timestamp(placed_at))
+                .bind(event.version)
+                .execute(&mut *conn)
+                .await?;
+                // Line items never change once the order is placed
+                for (index, item) in line_items.iter().enumerate() {
+                    sqlx::query(
+                        "INSERT INTO order_line_items (order_id, line_number, sku, quantity, unit_price, currency)
+                         VALUES ($1, $2, $3, $4, $5, $6)
+                         ON CONFLICT (order_id, line_number) DO NOTHING",
+                    )
+                    .bind(order_id.to_string())
+                    .bind(index as i32 + 1)
+                    .bind(&item.sku)
+                    .bind(item.quantity)
+                    .bind(to_amount(item.unit_price.amount_minor))
+                    .bind(&item.unit_price.currency)
+                    .execute(&mut *conn)
+                    .await?;
+                }
+                Self::record_change(conn, order_id, event.version, None, OrderStatus::Pending, None, placed_at).await
+            }
+            OrderEvent::StatusChanged { order_id, from, to, reason, changed_at } => {
+                sqlx::query("UPDATE orders SET status = $1, updated_at = $2, version = $3 WHERE id = $4 AND version < $3")
+                    .bind(to.as_str())
+                    .bind(to_timestamp(changed_at))
+                    .bind(event.version)
+                    .bind(order_id.to_string())
+                    .execute(&mut *conn)
+                    .await?;
+                Self::record_change(conn, order_id, event.version, Some(from), to, reason.as_deref(), changed_at).await
+            }
+        }
+    }
+
+    async fn reset(&self, conn: &mut AnyConnection) -> Result<(), MessagingError> {
+        sqlx::query("DELETE FROM order_status_history").execute(&mut *conn).await?;
+        sqlx::query("DELETE FROM order_line_items").execute(&mut *conn).await?;
+        sqlx::query("DELETE FROM orders").execute(&mut *conn).await?;
+        Ok(())
+    }
+}
@@ -1,40 +1,217 @@
-use crate::{models::Order, error::AppError};
-use sqlx::PgPool;
-use uuid::Uuid;
+use crate::{
+    aggregate::OrderAggregate,
+    models::{LineItem, Order, OrderStatus, StatusChange},
+    error::AppError,
+    projections::OrderTables,
+};
+use messaging::{AggregateRepository, EventStore};
+use shared::db::{from_timestamp, get_amount, to_timestamp, try_get_optional};
+use shared::{Cursor, IfMatch, Money, Page};
+use std::collections::HashMap;
+use sqlx::{any::AnyRow, AnyPool, Row};
 use time::OffsetDateTime;
+use uuid::Uuid;
 
+/// Orders are event sourced: each one is a stream of `OrderEvent`s.
+///
+/// The current state of every order is also kept in the `orders` table so
+/// that orders can be listed by user without replaying streams, and every
+/// status change in `order_status_history`. Both are written by the
+/// [`OrderTables`] projection in the transaction that appends the events.
+#[derive(Clone)]
 pub struct OrderRepository {
-    pool: PgPool,
+    orders: AggregateRepository<OrderAggregate>,
+    pool: AnyPool,
 }
 
 impl OrderRepository {
-    pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+    pub fn new(store: EventStore) -> Self {
+        Self {
+            pool: store.pool().clone(),
+            orders: AggregateRepository::new(store).with_snapshots(50).with_inline_projection(OrderTables),
+        }
     }
 
-    pub async fn create(&self, user_id: Uuid, product_name: &str, quantity: i32, total_price: f64) -> Result<Order, AppError> {
-        // For now, return a mock order since we don't have a real database
-        Ok(Order {
-            id: Uuid::new_v4(),
-            user_id,
-            product_name: product_name.to_string(),
-            quantity,
-            total_price,
-            created_at: OffsetDateTime::now_utc(),
-            updated_at: OffsetDateTime::now_utc(),
-        })
+    pub async fn create(&self, user_id: Uuid, line_items: Vec<LineItem>) -> Result<Order, AppError> {
+        let id = Uuid::new_v4();
+        let aggregate = OrderAggregate::default();
+        let events = aggregate.place(id, user_id, line_items)?;
+        let (aggregate, version) = self.orders.save(&id.to_string(), aggregate, 0, events).await?;
+        let mut order = aggregate.order.ok_or(AppError::OrderNotFound)?;
+        order.version = version;
+        Ok(order)
+    }
+
+    /// Moves an order to `to`. Fails with a precondition error if the order
+    /// is not at the version `if_match` names, with `InvalidTransition` if
+    /// its current status does not allow the move, and with a concurrency
+    /// conflict if the order changed while this ran.
+    pub async fn transition(
+        &self,
+        id: Uuid,
+        to: OrderStatus,
+        reason: Option<String>,
+        if_match: &IfMatch,
+    ) -> Result<(Order, StatusChange), AppError> {
+        let (aggregate, expected) = self.orders.load(&id.to_string()).await?.ok_or(AppError::OrderNotFound)?;
+        if_match.check(expected)?;
+        let from = aggregate.order.as_ref().map(|order| order.status);
+        let events = aggregate.transition(to, reason.clone())?;
+        let (aggregate, version) = self.orders.save(&id.to_string(), aggregate, expected, events).await?;
+        let mut order = aggregate.order.ok_or(AppError::OrderNotFound)?;
+        order.version = version;
+        let change = StatusChange {
+            order_id: order.id,
+            version,
+            from,
+            to,
+            reason,
+            changed_at: order.updated_at,
+        };
+        Ok((order, change))
+    }
+
+    /// Status changes of an order, oldest first.
+    pub async fn history(&self, id: Uuid) -> Result<Vec<StatusChange>, AppError> {
+        let rows = sqlx::query("SELECT * FROM order_status_history WHERE order_id = $1 ORDER BY version")
+            .bind(id.to_string())
+            .fetch_all(&self.pool)
+            .await?;
+        if rows.is_empty() {
+            return Err(AppError::OrderNotFound);
+        }
+        rows.iter().map(Self::change_from_row).collect()
     }
 
     pub async fn find_by_id(&self, id: Uuid) -> Result<Order, AppError> {
-        // For now, return a mock order since we don't have a real database
+        self.orders
+            .load(&id.to_string())
+            .await?
+            .and_then(|(aggregate, version)| aggregate.order.map(|order| Order { version, ..order }))
+            .ok_or(AppError::OrderNotFound)
+    }
+
+    /// The order as it stood at `version`.
+    pub async fn find_at(&self, id: Uuid, version: i64) -> Result<Order, AppError> {
+        self.orders
+            .load_at(&id.to_string(), version)
+            .await?
+            .and_then(|aggregate| aggregate.order.map(|order| Order { version, ..order }))
+            .ok_or(AppError::OrderNotFound)
+    }
+
+    /// Pending orders placed before `placed_before` that no saga of
+    /// `saga_type` was recorded for, oldest first.
+    pub async fn pending_without_saga(&self, saga_type: &str, placed_before: OffsetDateTime) -> Result<Vec<Uuid>, AppError> {
+        let rows = sqlx::query(
+            "SELECT id FROM orders
+             WHERE status = $1 AND created_at < $2
+               AND NOT EXISTS (SELECT 1 FROM sagas WHERE sagas.correlation_id = orders.id AND sagas.saga_type = $3)
+             ORDER BY created_at",
+        )
+        .bind(OrderStatus::Pending.as_str())
+        .bind(to_timestamp(placed_before))
+        .bind(saga_type)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter()
+            .map(|row| {
+                let id: String = row.try_get("id")?;
+                Uuid::parse_str(&id).map_err(|e| sqlx::Error::Decode(e.into()).into())
+            })
+            .collect()
+    }
+
+    /// Orders of `user_id`, newest first, starting after `cursor`.
+    pub async fn list_by_user(&self, user_id: Uuid, cursor: Option<Cursor>, limit: i64) -> Result<Page<Order>, AppError> {
+        let rows = match cursor {
+            Some(cursor) => {
+                sqlx::query(
+                    "SELECT * FROM orders
+                     WHERE user_id = $1 AND (created_at < $2 OR (created_at = $2 AND id < $3))
+                     ORDER BY created_at DESC, id DESC LIMIT $4",
+                )
+                .bind(user_id.to_string())
+                .bind(cursor.timestamp())
+                .bind(cursor.id.to_string())
+                .bind(limit + 1)
+                .fetch_all(&self.pool)
+                .await?
+            }
+            None => {
+                sqlx::query("SELECT * FROM orders WHERE user_id = $1 ORDER BY created_at DESC, id DESC LIMIT $2")
+                    .bind(user_id.to_string())
+                    .bind(limit + 1)
+                    .fetch_all(&self.pool)
+                    .await?
+            }
+        };
+        let mut orders = rows.iter().map(Self::from_row).collect::<Result<Vec<_>, _>>()?;
+        self.load_line_items(&mut orders).await?;
+        Ok(Page::from_rows(orders, limit, |order| Cursor::new(order.created_at, order.id)))
+    }
+
+    /// Fills in the line items of `orders` with one query.
+    async fn load_line_items(&self, orders: &mut [Order]) -> Result<(), AppError> {
+        if orders.is_empty() {
+            return Ok(());
+        }
+        let placeholders = (1..=orders.len()).map(|n| format!("${}", n)).collect::<Vec<_>>().join(", ");
+        let sql = format!(
+            "SELECT * FROM order_line_items WHERE order_id IN ({}) ORDER BY order_id, line_number",
+            placeholders
+        );
+        let mut query = sqlx::query(&sql);
+        for order in orders.iter() {
+            query = query.bind(order.id.to_string());
+        }
+
+        let mut items: HashMap<String, Vec<LineItem>> = HashMap::new();
+        for row in query.fetch_all(&self.pool).await? {
+            let currency: String = row.try_get("currency")?;
+            items.entry(row.try_get("order_id")?).or_default().push(LineItem {
+                sku: row.try_get("sku")?,
+                quantity: row.try_get("quantity")?,
+                unit_price: Money { amount_minor: get_amount(&row, "unit_price")?, currency },
+            });
+        }
+        for order in orders.iter_mut() {
+            order.line_items = items.remove(&order.id.to_string()).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    /// An order without its line items, which live in their own table.
+    fn from_row(row: &AnyRow) -> Result<Order, AppError> {
+        let id: String = row.try_get("id")?;
+        let user_id: String = row.try_get("user_id")?;
+        let parse = |value: &str| Uuid::parse_str(value).map_err(|e| sqlx::Error::Decode(e.into()));
         Ok(Order {
-            id,
-            user_id: Uuid::new_v4(),
-            product_name: "Test Product".to_string(),
-            quantity: 1,
-            total_price: 99.99,
-            created_at: OffsetDateTime::now_utc(),
-            updated_at: OffsetDateTime::now_utc(),
+            id: parse(&id)?,
+            user_id: parse(&user_id)?,
+            line_items: Vec::new(),
+            total: Money { amount_minor: get_amount(row, "total")?, currency: row.try_get("currency")? },
+            status: Self::status(&row.try_get::<String, _>("status")?)?,
+            created_at: from_timestamp(row.try_get("created_at")?),
+            updated_at: from_timestamp(row.try_get("updated_at")?),
+            version: row.try_get("version")?,
         })
     }
-}
\ No newline at end of file
+
+    fn change_from_row(row: &AnyRow) -> Result<StatusChange, AppError> {
+        let order_id: String = row.try_get("order_id")?;
+        let from: Option<String> = try_get_optional(row, "from_status")?;
+        Ok(StatusChange {
+            order_id: Uuid::parse_str(&order_id).map_err(|e| sqlx::Error::Decode(e.into()))?,
+            version: row.try_get("version")?,
+            from: from.as_deref().map(Self::status).transpose()?,
+            to: Self::status(&row.try_get::<String, _>("to_status")?)?,
+            reason: try_get_optional(row, "reason")?,
+            changed_at: from_timestamp(row.try_get("changed_at")?),
+        })
+    }
+
+    fn status(value: &str) -> Result<OrderStatus, AppError> {
+        OrderStatus::parse(value).ok_or_else(|| sqlx::Error::Decode(format!("unknown order status {}", value).into()).into())
+    }
+}
@@ -0,0 +1,27 @@
+use messaging::{CommandStep, Publisher, SagaDefinition};
+use std::sync::Arc;
+
+pub const ORDER_PLACEMENT: &str = "order_placement";
+
+pub const RESERVE_INVENTORY: &str = "commands.inventory.reserve";
+pub const RELEASE_INVENTORY: &str = "commands.inventory.release";
+pub const AUTHORIZE_PAYMENT: &str = "commands.payment.authorize";
+pub const VOID_PAYMENT: &str = "commands.payment.void";
+pub const CONFIRM_ORDER: &str = "commands.order.confirm";
+
+/// Placing an order: reserve stock, authorize payment, then confirm.
+///
+/// Each step is a command answered over request/reply by the owning service;
+/// a failure undoes the earlier steps in reverse order.
+pub fn order_placement(publisher: Arc<Publisher>) -> SagaDefinition {
+    SagaDefinition::new(ORDER_PLACEMENT)
+        .step(
+            CommandStep::new("reserve_inventory", publisher.clone(), RESERVE_INVENTORY)
+                .compensate_with(RELEASE_INVENTORY),
+        )
+        .step(
+            CommandStep::new("authorize_payment", publisher.clone(), AUTHORIZE_PAYMENT)
+                .compensate_with(VOID_PAYMENT),
+        )
+        .step(CommandStep::new("confirm_order", publisher, CONFIRM_ORDER))
+}
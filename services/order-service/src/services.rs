@@ -1,45 +1,226 @@
 use crate::{
-    models::{Order, CreateOrderRequest},
+    customers::CustomerDirectory,
+    models::{Order, CreateOrderRequest, OrderStatus, OrderTransition, OrderUpdate, StatusChange},
     repositories::OrderRepository,
     error::AppError,
 };
+use messaging::{Event, Message, OrderCreatedEvent, OrderLineItem, OrderStatusChangedEvent, Publisher, SagaOrchestrator};
+use shared::{Cursor, IfMatch, Page};
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::sync::broadcast;
+use uuid::Uuid;
 
+/// Updates kept for subscribers that fall behind before they miss some.
+const UPDATE_BUFFER: usize = 256;
+
+#[derive(Clone)]
 pub struct OrderService {
     repository: OrderRepository,
+    placement: Option<SagaOrchestrator>,
+    publisher: Option<Arc<Publisher>>,
+    customers: Option<CustomerDirectory>,
+    updates: broadcast::Sender<OrderUpdate>,
 }
 
 impl OrderService {
     pub fn new(repository: OrderRepository) -> Self {
-        Self { repository }
+        let (updates, _) = broadcast::channel(UPDATE_BUFFER);
+        Self { repository, placement: None, publisher: None, customers: None, updates }
+    }
+
+    /// Every order placed or changed from now on, by any instance, as
+    /// relayed from the order events on NATS.
+    pub fn subscribe(&self) -> broadcast::Receiver<OrderUpdate> {
+        self.updates.subscribe()
+    }
+
+    /// Passes a placed order on to subscribers. Orders from other producers,
+    /// which this service does not hold, are skipped.
+    pub async fn relay_placement(&self, event: &OrderCreatedEvent) -> Result<(), AppError> {
+        let order = match self.repository.find_by_id(event.order_id).await {
+            Ok(order) => order,
+            Err(AppError::OrderNotFound) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let change = StatusChange::placed(&order);
+        // Nobody listening is not an error
+        self.updates.send(OrderUpdate { order, change }).ok();
+        Ok(())
+    }
+
+    /// Passes a status change on to subscribers, with the order as the
+    /// change left it. Events from before schema v2 carry no version and
+    /// get the order as it now stands.
+    pub async fn relay_status_change(&self, event: &OrderStatusChangedEvent) -> Result<(), AppError> {
+        let status = |value: &str| {
+            OrderStatus::parse(value)
+                .ok_or_else(|| AppError::ValidationError(format!("Unknown order status {}", value)))
+        };
+        let order = match event.version {
+            Some(version) => self.repository.find_at(event.order_id, version).await?,
+            None => self.repository.find_by_id(event.order_id).await?,
+        };
+        let change = StatusChange {
+            order_id: order.id,
+            version: order.version,
+            from: Some(status(&event.from)?),
+            to: status(&event.to)?,
+            reason: event.reason.clone(),
+            changed_at: event.timestamp,
+        };
+        self.updates.send(OrderUpdate { order, change }).ok();
+        Ok(())
+    }
+
+    /// Publishes an `OrderCreatedEvent` for every new order and an
+    /// `OrderStatusChangedEvent` for every status transition.
+    pub fn with_publisher(mut self, publisher: Arc<Publisher>) -> Self {
+        self.publisher = Some(publisher);
+        self
+    }
+
+    /// Checks that the user placing each order exists.
+    pub fn with_customers(mut self, customers: CustomerDirectory) -> Self {
+        self.customers = Some(customers);
+        self
+    }
+
+    /// Runs the order placement saga for every new order.
+    pub fn with_placement_saga(mut self, placement: SagaOrchestrator) -> Self {
+        self.placement = Some(placement);
+        self
     }
 
     pub async fn create_order(&self, request: CreateOrderRequest) -> Result<Order, AppError> {
         // Validate input
-        if request.product_name.is_empty() {
-            return Err(AppError::ValidationError("Product name cannot be empty".to_string()));
+        if request.line_items.is_empty() {
+            return Err(AppError::ValidationError("An order needs at least one line item".to_string()));
+        }
+
+        for item in &request.line_items {
+            if item.sku.is_empty() {
+                return Err(AppError::ValidationError("SKU cannot be empty".to_string()));
+            }
+
+            if item.quantity <= 0 {
+                return Err(AppError::ValidationError(format!("Quantity of {} must be greater than zero", item.sku)));
+            }
+
+            item.unit_price.validate()?;
+            if !item.unit_price.is_positive() {
+                return Err(AppError::ValidationError(format!("Unit price of {} must be greater than zero", item.sku)));
+            }
+        }
+
+        if let Some(customers) = &self.customers {
+            customers.require(request.user_id).await?;
         }
 
-        if request.quantity <= 0 {
-            return Err(AppError::ValidationError("Quantity must be greater than zero".to_string()));
+        // Create order; the total is computed from the line items
+        let order = self.repository.create(request.user_id, request.line_items).await?;
+
+        // The saga shares the order id as its correlation id. The order is
+        // already committed, so a saga that cannot be recorded now is left
+        // to start_missing_placements rather than failing the request.
+        if let Some(placement) = &self.placement {
+            if let Err(e) = Self::start_placement(placement, &order).await {
+                tracing::error!("Failed to start placement saga of order {}, leaving it for retry: {}", order.id, e);
+            }
         }
 
-        if request.total_price <= 0.0 {
-            return Err(AppError::ValidationError("Total price must be greater than zero".to_string()));
+        if let Some(publisher) = &self.publisher {
+            let event = OrderCreatedEvent {
+                order_id: order.id,
+                user_id: order.user_id,
+                line_items: order
+                    .line_items
+                    .iter()
+                    .map(|item| OrderLineItem { sku: item.sku.clone(), quantity: item.quantity, unit_price: item.unit_price.clone() })
+                    .collect(),
+                total: order.total.clone(),
+                timestamp: order.created_at,
+            };
+            let message = Message::from_event("order-service", &event)?.with_correlation(order.id);
+            if let Err(e) = publisher.publish(OrderCreatedEvent::SUBJECT, message).await {
+                tracing::error!("Failed to publish creation of order {}: {}", order.id, e);
+            }
         }
 
-        // Create order
-        let order = self.repository.create(
-            request.user_id,
-            &request.product_name,
-            request.quantity,
-            request.total_price
-        ).await?;
-        
         Ok(order)
     }
 
-    pub async fn get_order_by_id(&self, id: uuid::Uuid) -> Result<Order, AppError> {
+    /// Starts the placement saga of every pending order placed more than
+    /// `grace` ago that has none, because starting it failed when the order
+    /// was placed. Returns how many were started.
+    ///
+    /// The grace period keeps this from racing `create_order` for orders it
+    /// is still placing.
+    pub async fn start_missing_placements(&self, grace: Duration) -> Result<usize, AppError> {
+        let Some(placement) = &self.placement else {
+            return Ok(0);
+        };
+        let placed_before = OffsetDateTime::now_utc() - grace;
+        let mut started = 0;
+        for id in self.repository.pending_without_saga(crate::saga::ORDER_PLACEMENT, placed_before).await? {
+            let order = self.repository.find_by_id(id).await?;
+            match Self::start_placement(placement, &order).await {
+                Ok(()) => started += 1,
+                Err(e) => tracing::error!("Failed to start placement saga of order {}: {}", id, e),
+            }
+        }
+        Ok(started)
+    }
+
+    async fn start_placement(placement: &SagaOrchestrator, order: &Order) -> Result<(), AppError> {
+        let input = serde_json::to_value(order).map_err(messaging::MessagingError::from)?;
+        placement.start(order.id, input).await?;
+        Ok(())
+    }
+
+    pub async fn get_order_by_id(&self, id: Uuid) -> Result<Order, AppError> {
         let order = self.repository.find_by_id(id).await?;
         Ok(order)
     }
-}
\ No newline at end of file
+
+    pub async fn list_orders_by_user(&self, user_id: Uuid, cursor: Option<Cursor>, limit: i64) -> Result<Page<Order>, AppError> {
+        self.repository.list_by_user(user_id, cursor, limit).await
+    }
+
+    /// Applies a lifecycle transition and announces it.
+    ///
+    /// The change is committed before the event is published, so a failed
+    /// publish is logged rather than returned.
+    pub async fn transition(
+        &self,
+        id: Uuid,
+        transition: OrderTransition,
+        reason: Option<String>,
+        if_match: &IfMatch,
+    ) -> Result<Order, AppError> {
+        let (order, change) = self.repository.transition(id, transition.target(), reason, if_match).await?;
+
+        if let Some(publisher) = &self.publisher {
+            let event = OrderStatusChangedEvent {
+                order_id: order.id,
+                user_id: order.user_id,
+                from: change.from.unwrap_or_default().to_string(),
+                to: change.to.to_string(),
+                reason: change.reason.clone(),
+                timestamp: change.changed_at,
+                version: Some(change.version),
+            };
+            let message = Message::from_event("order-service", &event)?.with_correlation(order.id);
+            if let Err(e) = publisher.publish(OrderStatusChangedEvent::SUBJECT, message).await {
+                tracing::error!("Failed to publish status change of order {}: {}", order.id, e);
+            }
+        }
+
+        Ok(order)
+    }
+
+    pub async fn get_status_history(&self, id: Uuid) -> Result<Vec<StatusChange>, AppError> {
+        self.repository.history(id).await
+    }
+}
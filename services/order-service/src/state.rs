@@ -0,0 +1,53 @@
+use axum::extract::FromRef;
+use messaging::SagaStore;
+use shared::{IdempotencyStore, IfMatchPolicy};
+use sqlx::AnyPool;
+
+use crate::{config::Config, services::OrderService};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Config,
+    pub sagas: SagaStore,
+    pub pool: AnyPool,
+    pub orders: OrderService,
+    pub idempotency: IdempotencyStore,
+}
+
+impl AppState {
+    pub fn new(config: Config, pool: AnyPool, orders: OrderService) -> Self {
+        let sagas = SagaStore::new(pool.clone());
+        let idempotency = IdempotencyStore::new(pool.clone());
+        Self { config, sagas, pool, orders, idempotency }
+    }
+}
+
+impl FromRef<AppState> for Config {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for SagaStore {
+    fn from_ref(state: &AppState) -> Self {
+        state.sagas.clone()
+    }
+}
+
+impl FromRef<AppState> for OrderService {
+    fn from_ref(state: &AppState) -> Self {
+        state.orders.clone()
+    }
+}
+
+impl FromRef<AppState> for AnyPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for IfMatchPolicy {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.if_match
+    }
+}
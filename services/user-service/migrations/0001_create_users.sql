@@ -0,0 +1,9 @@
+-- Portable across SQLite and Postgres: ids as TEXT, timestamps as DOUBLE
+-- PRECISION microseconds since the Unix epoch (see shared/src/db.rs).
+CREATE TABLE IF NOT EXISTS users (
+    id TEXT PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    email TEXT NOT NULL UNIQUE,
+    created_at DOUBLE PRECISION NOT NULL,
+    updated_at DOUBLE PRECISION NOT NULL
+);
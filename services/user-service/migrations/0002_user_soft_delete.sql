@@ -0,0 +1,5 @@
+-- Soft-deleted users keep their row, and with it their username and email,
+-- until restored. NULL for live users.
+ALTER TABLE users ADD COLUMN deleted_at DOUBLE PRECISION;
+
+CREATE INDEX IF NOT EXISTS users_created ON users (created_at, id);
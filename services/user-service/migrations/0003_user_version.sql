@@ -0,0 +1,2 @@
+-- Goes up by one with every change to the user; served as the ETag.
+ALTER TABLE users ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -1,11 +1,18 @@
 use serde::Deserialize;
 use microservice_config::ConfigError;
+use shared::IfMatchPolicy;
 
 #[derive(Deserialize, Clone)]
 pub struct Config {
     pub database_url: String,
     pub host: String,
     pub port: u16,
+    /// Port of the gRPC server, which runs next to the HTTP one.
+    pub grpc_port: u16,
+    /// User events are only published when set.
+    pub nats_url: Option<String>,
+    /// Whether updates must send `If-Match`.
+    pub if_match: IfMatchPolicy,
 }
 
 impl Config {
@@ -13,9 +20,18 @@ impl Config {
         // For now, we'll create a simple config without using the config crate
         // In a real implementation, you would use the config crate properly
         Ok(Config {
-            database_url: "sqlite:user_service.db".to_string(),
+            database_url: std::env::var("DATABASE_URL")
+                .unwrap_or_else(|_| "sqlite:user_service.db?mode=rwc".to_string()),
             host: "0.0.0.0".to_string(),
             port: 3001,
+            grpc_port: std::env::var("GRPC_PORT")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(50051),
+            nats_url: std::env::var("NATS_URL").ok(),
+            if_match: std::env::var("REQUIRE_IF_MATCH")
+                .map(|value| IfMatchPolicy::parse(&value))
+                .unwrap_or_default(),
         })
     }
 }
\ No newline at end of file
@@ -0,0 +1,18 @@
+use messaging::Scheduler;
+use sqlx::migrate::Migrator;
+use shared::IdempotencyStore;
+use sqlx::AnyPool;
+
+/// Schema migrations, embedded at compile time from `migrations/`.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Opens the database named by `database_url` and brings its schema up to
+/// date, including its event outbox and idempotency keys. `sqlite:` and
+/// `postgres:` URLs are supported.
+pub async fn connect(database_url: &str) -> Result<AnyPool, messaging::MessagingError> {
+    let pool = shared::db::connect(database_url).await?;
+    MIGRATOR.run(&pool).await.map_err(sqlx::Error::from)?;
+    Scheduler::new(pool.clone()).init_schema().await?;
+    IdempotencyStore::new(pool.clone()).init_schema().await?;
+    Ok(pool)
+}
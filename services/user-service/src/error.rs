@@ -13,16 +13,48 @@ pub enum AppError {
     ValidationError(String),
     #[error("User not found")]
     UserNotFound,
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    #[error("Gone: {0}")]
+    Gone(String),
+    #[error("{0}")]
+    Precondition(#[from] shared::PreconditionError),
+    #[error("Messaging error: {0}")]
+    MessagingError(#[from] messaging::MessagingError),
+}
+
+impl From<shared::SharedError> for AppError {
+    fn from(error: shared::SharedError) -> Self {
+        AppError::ValidationError(error.to_string())
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let status = match &self {
-            AppError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            // Carries the current ETag along with the status
+            AppError::Precondition(e) => return e.clone().into_response(),
+            AppError::DatabaseError(_) | AppError::MessagingError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::ValidationError(_) => StatusCode::BAD_REQUEST,
             AppError::UserNotFound => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Gone(_) => StatusCode::GONE,
         };
 
         (status, self.to_string()).into_response()
     }
-}
\ No newline at end of file
+}
+
+impl From<AppError> for tonic::Status {
+    fn from(error: AppError) -> Self {
+        let message = error.to_string();
+        match error {
+            AppError::DatabaseError(_) | AppError::MessagingError(_) => tonic::Status::internal(message),
+            AppError::ValidationError(_) => tonic::Status::invalid_argument(message),
+            // Deleted users are as absent to callers as unknown ones
+            AppError::UserNotFound | AppError::Gone(_) => tonic::Status::not_found(message),
+            AppError::Conflict(_) => tonic::Status::already_exists(message),
+            AppError::Precondition(_) => tonic::Status::failed_precondition(message),
+        }
+    }
+}
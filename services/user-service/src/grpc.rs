@@ -0,0 +1,82 @@
+use shared::grpc::{idempotent, non_empty, page_request, parse_id, to_timestamp};
+use shared::IdempotencyStore;
+use tonic::{Request, Response, Status};
+use user_client::user_service_server::{self, UserServiceServer};
+
+use crate::{
+    models::{CreateUserRequest, ListUsersQuery, User},
+    services::UserService,
+};
+
+/// user-service over gRPC, as described by the `user-client` crate's
+/// `proto/user.proto`.
+#[derive(Clone)]
+pub struct GrpcUsers {
+    users: UserService,
+    idempotency: IdempotencyStore,
+}
+
+impl GrpcUsers {
+    /// Creating users honours `idempotency-key` metadata, with keys kept in
+    /// `idempotency`.
+    pub fn new(users: UserService, idempotency: IdempotencyStore) -> Self {
+        Self { users, idempotency }
+    }
+
+    pub fn into_server(self) -> UserServiceServer<Self> {
+        UserServiceServer::new(self)
+    }
+}
+
+impl From<User> for user_client::User {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id.to_string(),
+            username: user.username,
+            email: user.email,
+            created_at: Some(to_timestamp(user.created_at)),
+            updated_at: Some(to_timestamp(user.updated_at)),
+            version: user.version,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl user_service_server::UserService for GrpcUsers {
+    async fn create_user(
+        &self,
+        request: Request<user_client::CreateUserRequest>,
+    ) -> Result<Response<user_client::User>, Status> {
+        idempotent(&self.idempotency, "/user.v1.UserService/CreateUser", request, |request| async move {
+            let user = self
+                .users
+                .create_user(CreateUserRequest { username: request.username, email: request.email })
+                .await?;
+            Ok(user.into())
+        })
+        .await
+    }
+
+    async fn get_user(&self, request: Request<user_client::GetUserRequest>) -> Result<Response<user_client::User>, Status> {
+        let id = parse_id("id", &request.into_inner().id)?;
+        Ok(Response::new(self.users.get_user_by_id(id).await?.into()))
+    }
+
+    async fn list_users(
+        &self,
+        request: Request<user_client::ListUsersRequest>,
+    ) -> Result<Response<user_client::ListUsersResponse>, Status> {
+        let request = request.into_inner();
+        let page = page_request(request.limit, request.cursor);
+        let filter = ListUsersQuery {
+            email: non_empty(request.email),
+            username_prefix: non_empty(request.username_prefix),
+            ..ListUsersQuery::default()
+        };
+        let users = self.users.list_users(&filter, page.cursor()?, page.limit()).await?;
+        Ok(Response::new(user_client::ListUsersResponse {
+            users: users.items.into_iter().map(Into::into).collect(),
+            next_cursor: users.next_cursor.unwrap_or_default(),
+        }))
+    }
+}
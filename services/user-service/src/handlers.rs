@@ -1,17 +1,17 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
     Json,
 };
-use serde_json::json;
+use messaging::{EventCatalog, UserCreatedEvent, UserDeletedEvent, UserRestoredEvent, UserUpdatedEvent};
+use shared::{IfMatch, IfNoneMatch, PageRequest, Versioned};
 use uuid::Uuid;
 
 use crate::{
-    models::{User, CreateUserRequest},
-    services::UserService,
-    config::Config,
+    models::{CreateUserRequest, ListUsersQuery, UpdateUserRequest},
     error::AppError,
+    services::UserService,
 };
 
 pub async fn health_check() -> impl IntoResponse {
@@ -19,35 +19,78 @@ pub async fn health_check() -> impl IntoResponse {
 }
 
 pub async fn create_user(
-    State(config): State<Config>,
+    State(users): State<UserService>,
     Json(request): Json<CreateUserRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    // In a real implementation, we would initialize the repository and service here
-    // For now, we'll just return a mock response
-    let user = User {
-        id: Uuid::new_v4(),
-        username: request.username,
-        email: request.email,
-        created_at: time::OffsetDateTime::now_utc(),
-        updated_at: time::OffsetDateTime::now_utc(),
-    };
-    
-    Ok((StatusCode::CREATED, Json(user)))
+    let user = users.create_user(request).await?;
+    Ok((StatusCode::CREATED, Versioned(user.version, user)))
 }
 
 pub async fn get_user_by_id(
-    State(config): State<Config>,
+    State(users): State<UserService>,
+    Path(id): Path<Uuid>,
+    if_none_match: IfNoneMatch,
+) -> Result<impl IntoResponse, AppError> {
+    let user = users.get_user_by_id(id).await?;
+    Ok(if_none_match.respond(user.version, user))
+}
+
+pub async fn list_users(
+    State(users): State<UserService>,
+    Query(filter): Query<ListUsersQuery>,
+    Query(page): Query<PageRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let users = users.list_users(&filter, page.cursor()?, page.limit()).await?;
+    Ok(Json(users))
+}
+
+pub async fn update_user(
+    State(users): State<UserService>,
+    Path(id): Path<Uuid>,
+    if_match: IfMatch,
+    Json(request): Json<UpdateUserRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = users.update_user(id, request, &if_match).await?;
+    Ok(Versioned(user.version, user))
+}
+
+pub async fn delete_user(
+    State(users): State<UserService>,
     Path(id): Path<Uuid>,
+    if_match: IfMatch,
 ) -> Result<impl IntoResponse, AppError> {
-    // In a real implementation, we would fetch from the database
-    // For now, we'll just return a mock response
-    let user = User {
-        id,
-        username: "testuser".to_string(),
-        email: "test@example.com".to_string(),
-        created_at: time::OffsetDateTime::now_utc(),
-        updated_at: time::OffsetDateTime::now_utc(),
-    };
-    
-    Ok(Json(user))
-}
\ No newline at end of file
+    users.delete_user(id, &if_match).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn restore_user(
+    State(users): State<UserService>,
+    Path(id): Path<Uuid>,
+    if_match: IfMatch,
+) -> Result<impl IntoResponse, AppError> {
+    let user = users.restore_user(id, &if_match).await?;
+    Ok(Versioned(user.version, user))
+}
+
+/// All users, oldest first, for services that keep a local copy of them.
+pub async fn export_users(
+    State(users): State<UserService>,
+    Query(page): Query<PageRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let users = users.export_users(page.cursor()?, page.limit()).await?;
+    Ok(Json(users))
+}
+
+/// Messages this service sends and receives.
+pub fn event_catalog() -> EventCatalog {
+    EventCatalog::new("user-service", env!("CARGO_PKG_VERSION"))
+        .description("User accounts")
+        .publishes::<UserCreatedEvent>()
+        .publishes::<UserUpdatedEvent>()
+        .publishes::<UserDeletedEvent>()
+        .publishes::<UserRestoredEvent>()
+}
+
+pub async fn asyncapi() -> impl IntoResponse {
+    Json(event_catalog().to_asyncapi())
+}
@@ -3,32 +3,70 @@ use axum::{
     Router,
 };
 use std::net::SocketAddr;
-use tracing_subscriber;
+use std::sync::Arc;
+use std::time::Duration;
 
 mod handlers;
 mod services;
 mod repositories;
 mod models;
 mod config;
+mod db;
 mod error;
+mod grpc;
+mod state;
 
 #[cfg(test)]
-mod tests;
+#[path = "tests.rs"]
+// The tests still glob-import their parent module
+#[allow(unused_imports)]
+mod unit;
 
 #[tokio::main]
 async fn main() {
     // Initialize tracing
-    tracing_subscriber::fmt::init();
+    observability::init_tracing("user-service").expect("Failed to initialize tracing");
 
     // Load configuration
     let config = config::Config::from_env().expect("Failed to load configuration");
 
-    // Build our application with routes
-    let app = Router::new()
-        .route("/health", get(handlers::health_check))
-        .route("/users", post(handlers::create_user))
-        .route("/users/:id", get(handlers::get_user_by_id))
-        .with_state(config);
+    // Users live in SQLite by default, or Postgres when DATABASE_URL says so
+    let pool = db::connect(&config.database_url).await.expect("Failed to connect to database");
+
+    // Message subscriptions register here so shutdown can drain them
+    let subscriptions = messaging::Subscriptions::new();
+
+    // User events are committed to an outbox with each change and relayed
+    // from there, including any left over from before a restart
+    let mut repository = repositories::UserRepository::new(pool.clone());
+    if let Some(nats_url) = &config.nats_url {
+        let client = async_nats::connect(nats_url).await.expect("Failed to connect to NATS");
+        let publisher = Arc::new(messaging::Publisher::new(client));
+        let outbox = messaging::Scheduler::new(pool.clone());
+        repository = repository.with_outbox(outbox.clone());
+        tokio::spawn(async move { outbox.run(publisher, Duration::from_secs(1)).await });
+    }
+    let users = services::UserService::new(repository);
+
+    // The same users over gRPC, on a port of its own
+    let grpc_addr = SocketAddr::from(([0, 0, 0, 0], config.grpc_port));
+    let (stop_grpc, grpc_stopped) = tokio::sync::oneshot::channel::<()>();
+    let grpc_server = tonic::transport::Server::builder()
+        .add_service(grpc::GrpcUsers::new(users.clone(), shared::IdempotencyStore::new(pool.clone())).into_server())
+        .serve_with_shutdown(grpc_addr, async {
+            grpc_stopped.await.ok();
+        });
+    tracing::info!("User service gRPC listening on {}", grpc_addr);
+    let grpc_server = tokio::spawn(async move {
+        if let Err(e) = grpc_server.await {
+            tracing::error!("gRPC server failed: {}", e);
+        }
+    });
+
+    let state = state::AppState::new(config, pool, users);
+    let idempotency = state.idempotency.clone();
+    tokio::spawn(async move { idempotency.run_purge(Duration::from_secs(60 * 60)).await });
+    let app = app(state);
 
     // Run our app with hyper, listening globally on port 3001
     let addr = SocketAddr::from(([0, 0, 0, 0], 3001));
@@ -36,12 +74,36 @@ async fn main() {
     
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
     axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
+        .with_graceful_shutdown(async move {
+            shutdown_signal(subscriptions).await;
+            stop_grpc.send(()).ok();
+        })
         .await
         .unwrap();
+    grpc_server.await.ok();
+
+    observability::shutdown_tracing();
+}
+
+/// Builds our application with routes.
+fn app(state: state::AppState) -> Router {
+    // Retried sign-ups with the same Idempotency-Key create one user
+    let idempotent = axum::middleware::from_fn_with_state(state.idempotency.clone(), shared::idempotency);
+    Router::new()
+        .route("/health", get(handlers::health_check))
+        .route("/users", post(handlers::create_user).layer(idempotent).get(handlers::list_users))
+        .route("/users/export", get(handlers::export_users))
+        .route(
+            "/users/:id",
+            get(handlers::get_user_by_id).patch(handlers::update_user).delete(handlers::delete_user),
+        )
+        .route("/users/:id/restore", post(handlers::restore_user))
+        .route(messaging::ASYNCAPI_PATH, get(handlers::asyncapi))
+        .layer(axum::middleware::from_fn(observability::trace_requests))
+        .with_state(state)
 }
 
-async fn shutdown_signal() {
+async fn shutdown_signal(subscriptions: messaging::Subscriptions) {
     let ctrl_c = async {
         tokio::signal::ctrl_c()
             .await
@@ -65,4 +127,9 @@ async fn shutdown_signal() {
     }
     
     tracing::info!("Signal received, starting graceful shutdown");
+
+    // Finish in-flight message handlers while the HTTP server is still up
+    for status in subscriptions.drain_all().await {
+        tracing::info!("Drained {} ({} handled, {} failed)", status.subject, status.handled, status.failed);
+    }
 }
\ No newline at end of file
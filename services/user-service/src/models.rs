@@ -1,19 +1,71 @@
+use messaging::{UserCreatedEvent, UserDeletedEvent, UserRestoredEvent, UserUpdatedEvent};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
 use uuid::Uuid;
 use time::OffsetDateTime;
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct User {
     pub id: Uuid,
     pub username: String,
     pub email: String,
     pub created_at: OffsetDateTime,
     pub updated_at: OffsetDateTime,
+    /// Goes up by one with every change; the user's ETag.
+    pub version: i64,
+    /// Set while the user is soft deleted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<OffsetDateTime>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateUserRequest {
     pub username: String,
     pub email: String,
-}
\ No newline at end of file
+}
+
+/// Body of `PATCH /users/:id`. Absent fields are left as they are.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UpdateUserRequest {
+    pub username: Option<String>,
+    pub email: Option<String>,
+}
+
+/// Filters of `GET /users`. Times are RFC 3339, e.g. `2024-01-31T00:00:00Z`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ListUsersQuery {
+    /// Email addresses starting with this.
+    pub email: Option<String>,
+    pub username_prefix: Option<String>,
+    /// Users created at or after this time.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub created_from: Option<OffsetDateTime>,
+    /// Users created before this time.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub created_to: Option<OffsetDateTime>,
+}
+
+// The events announcing each change, built from the user as written
+
+impl From<&User> for UserCreatedEvent {
+    fn from(user: &User) -> Self {
+        Self { user_id: user.id, username: user.username.clone(), email: user.email.clone(), timestamp: user.created_at }
+    }
+}
+
+impl From<&User> for UserUpdatedEvent {
+    fn from(user: &User) -> Self {
+        Self { user_id: user.id, username: user.username.clone(), email: user.email.clone(), timestamp: user.updated_at }
+    }
+}
+
+impl From<&User> for UserDeletedEvent {
+    fn from(user: &User) -> Self {
+        Self { user_id: user.id, timestamp: user.updated_at }
+    }
+}
+
+impl From<&User> for UserRestoredEvent {
+    fn from(user: &User) -> Self {
+        Self { user_id: user.id, username: user.username.clone(), email: user.email.clone(), timestamp: user.updated_at }
+    }
+}
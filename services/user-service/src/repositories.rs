@@ -1,36 +1,271 @@
-use crate::{models::User, error::AppError};
-use sqlx::PgPool;
+use crate::{models::{ListUsersQuery, User}, error::AppError};
+use shared::db::{from_timestamp, to_timestamp, try_get_optional};
+use shared::{etag, Cursor, Page, PreconditionError};
+use messaging::{Event, Message, Scheduler};
+use sqlx::{any::AnyRow, AnyConnection, AnyPool, Row};
+use std::time::Duration;
 use uuid::Uuid;
 use time::OffsetDateTime;
 
+/// An event announcing a change to a user, built from the user as written.
+pub trait UserEvent: Event + for<'a> From<&'a User> {}
+
+impl<E: Event + for<'a> From<&'a User>> UserEvent for E {}
+
+#[derive(Clone)]
 pub struct UserRepository {
-    pool: PgPool,
+    pool: AnyPool,
+    outbox: Option<Scheduler>,
 }
 
 impl UserRepository {
-    pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+    pub fn new(pool: AnyPool) -> Self {
+        Self { pool, outbox: None }
     }
 
-    pub async fn create(&self, username: &str, email: &str) -> Result<User, AppError> {
-        // For now, return a mock user since we don't have a real database
-        Ok(User {
+    /// Stores the event announcing each change in `outbox`, in the same
+    /// transaction as the change, for [`Scheduler::run`] to publish. Without
+    /// an outbox changes are not announced.
+    pub fn with_outbox(mut self, outbox: Scheduler) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
+    /// Creates the user and announces it with an `E`.
+    pub async fn create<E: UserEvent>(&self, username: &str, email: &str) -> Result<User, AppError> {
+        // Round to the stored precision so the returned user matches later reads
+        let now = from_timestamp(to_timestamp(OffsetDateTime::now_utc()));
+        let user = User {
             id: Uuid::new_v4(),
             username: username.to_string(),
             email: email.to_string(),
-            created_at: OffsetDateTime::now_utc(),
-            updated_at: OffsetDateTime::now_utc(),
-        })
+            created_at: now,
+            updated_at: now,
+            version: 1,
+            deleted_at: None,
+        };
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO users (id, username, email, created_at, updated_at, version) VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(user.id.to_string())
+        .bind(&user.username)
+        .bind(&user.email)
+        .bind(to_timestamp(user.created_at))
+        .bind(to_timestamp(user.updated_at))
+        .bind(user.version)
+        .execute(&mut *tx)
+        .await
+        .map_err(Self::conflict)?;
+        self.announce::<E>(&mut tx, &user).await?;
+        tx.commit().await?;
+        Ok(user)
     }
 
+    /// The user, whether or not they are soft deleted.
     pub async fn find_by_id(&self, id: Uuid) -> Result<User, AppError> {
-        // For now, return a mock user since we don't have a real database
+        let row = sqlx::query("SELECT * FROM users WHERE id = $1")
+            .bind(id.to_string())
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+        Self::from_row(&row)
+    }
+
+    /// Writes the profile and deletion mark of `user` with a new `updated_at`,
+    /// and announces the change with an `E`.
+    pub async fn save<E: UserEvent>(&self, mut user: User) -> Result<User, AppError> {
+        user.updated_at = Self::next_change(&user);
+        self.write::<E>(user).await
+    }
+
+    /// Marks `user` deleted as of a new `updated_at`, and announces it with an `E`.
+    pub async fn soft_delete<E: UserEvent>(&self, mut user: User) -> Result<User, AppError> {
+        let deleted_at = Self::next_change(&user);
+        user.updated_at = deleted_at;
+        user.deleted_at = Some(deleted_at);
+        self.write::<E>(user).await
+    }
+
+    /// Now, or just after the user's last change if the clock has not moved
+    /// on, so consumers can order the events of one user by their time.
+    fn next_change(user: &User) -> OffsetDateTime {
+        let now = from_timestamp(to_timestamp(OffsetDateTime::now_utc()));
+        now.max(user.updated_at + Duration::from_micros(1))
+    }
+
+    /// Writes `user` as the version after the one it was read at. Fails
+    /// with a precondition error if someone else wrote it meanwhile.
+    async fn write<E: UserEvent>(&self, mut user: User) -> Result<User, AppError> {
+        let read_version = user.version;
+        user.version += 1;
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query(
+            "UPDATE users SET username = $1, email = $2, updated_at = $3, deleted_at = $4, version = $5
+             WHERE id = $6 AND version = $7",
+        )
+        .bind(&user.username)
+        .bind(&user.email)
+        .bind(to_timestamp(user.updated_at))
+        .bind(user.deleted_at.map(to_timestamp))
+        .bind(user.version)
+        .bind(user.id.to_string())
+        .bind(read_version)
+        .execute(&mut *tx)
+        .await
+        .map_err(Self::conflict)?;
+        if result.rows_affected() == 0 {
+            drop(tx);
+            let current = self.find_by_id(user.id).await?;
+            return Err(PreconditionError::Failed { current: etag(current.version) }.into());
+        }
+        self.announce::<E>(&mut tx, &user).await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    /// Adds the `E` announcing the change to `user` to the outbox on `conn`.
+    async fn announce<E: UserEvent>(&self, conn: &mut AnyConnection, user: &User) -> Result<(), AppError> {
+        let Some(outbox) = &self.outbox else {
+            return Ok(());
+        };
+        let message = Message::from_event("user-service", &E::from(user))?;
+        outbox.schedule_on(conn, E::SUBJECT, &message, outbox.now()).await?;
+        Ok(())
+    }
+
+    /// Why `username` or `email` cannot be given to user `except`, if taken.
+    /// Soft-deleted users keep theirs so that they can be restored.
+    pub async fn taken(&self, username: Option<&str>, email: Option<&str>, except: Option<Uuid>) -> Result<Option<String>, AppError> {
+        let except = except.map(|id| id.to_string()).unwrap_or_default();
+        if let Some(username) = username {
+            let row = sqlx::query("SELECT id FROM users WHERE username = $1 AND id <> $2")
+                .bind(username)
+                .bind(&except)
+                .fetch_optional(&self.pool)
+                .await?;
+            if row.is_some() {
+                return Ok(Some(format!("Username {} is already taken", username)));
+            }
+        }
+        if let Some(email) = email {
+            let row = sqlx::query("SELECT id FROM users WHERE email = $1 AND id <> $2")
+                .bind(email)
+                .bind(&except)
+                .fetch_optional(&self.pool)
+                .await?;
+            if row.is_some() {
+                return Ok(Some(format!("Email {} is already registered", email)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Live users matching `filter`, newest first, starting after `cursor`.
+    pub async fn list(&self, filter: &ListUsersQuery, cursor: Option<Cursor>, limit: i64) -> Result<Page<User>, AppError> {
+        let mut conditions = vec!["deleted_at IS NULL".to_string()];
+        let mut params: Vec<Param> = Vec::new();
+        let next = |param: Param, params: &mut Vec<Param>| {
+            params.push(param);
+            format!("${}", params.len())
+        };
+
+        // substr rather than LIKE, which is case-insensitive on SQLite only
+        if let Some(prefix) = &filter.email {
+            let n = next(Param::Text(prefix.clone()), &mut params);
+            conditions.push(format!("substr(email, 1, length({n})) = {n}"));
+        }
+        if let Some(prefix) = &filter.username_prefix {
+            let n = next(Param::Text(prefix.clone()), &mut params);
+            conditions.push(format!("substr(username, 1, length({n})) = {n}"));
+        }
+        if let Some(from) = filter.created_from {
+            conditions.push(format!("created_at >= {}", next(Param::Float(to_timestamp(from)), &mut params)));
+        }
+        if let Some(to) = filter.created_to {
+            conditions.push(format!("created_at < {}", next(Param::Float(to_timestamp(to)), &mut params)));
+        }
+        if let Some(cursor) = cursor {
+            let at = next(Param::Float(cursor.timestamp()), &mut params);
+            let id = next(Param::Text(cursor.id.to_string()), &mut params);
+            conditions.push(format!("(created_at < {at} OR (created_at = {at} AND id < {id}))"));
+        }
+        let limit_param = next(Param::Int(limit + 1), &mut params);
+
+        let sql = format!(
+            "SELECT * FROM users WHERE {} ORDER BY created_at DESC, id DESC LIMIT {}",
+            conditions.join(" AND "),
+            limit_param
+        );
+        let mut query = sqlx::query(&sql);
+        for param in params {
+            query = match param {
+                Param::Text(value) => query.bind(value),
+                Param::Float(value) => query.bind(value),
+                Param::Int(value) => query.bind(value),
+            };
+        }
+        let rows = query.fetch_all(&self.pool).await?;
+        let users = rows.iter().map(Self::from_row).collect::<Result<Vec<_>, _>>()?;
+        Ok(Page::from_rows(users, limit, |user| Cursor::new(user.created_at, user.id)))
+    }
+
+    /// Every user, soft-deleted ones included, oldest first, starting after
+    /// `cursor`.
+    pub async fn export(&self, cursor: Option<Cursor>, limit: i64) -> Result<Page<User>, AppError> {
+        let rows = match cursor {
+            Some(cursor) => {
+                sqlx::query(
+                    "SELECT * FROM users
+                     WHERE created_at > $1 OR (created_at = $1 AND id > $2)
+                     ORDER BY created_at, id LIMIT $3",
+                )
+                .bind(cursor.timestamp())
+                .bind(cursor.id.to_string())
+                .bind(limit + 1)
+                .fetch_all(&self.pool)
+                .await?
+            }
+            None => {
+                sqlx::query("SELECT * FROM users ORDER BY created_at, id LIMIT $1")
+                    .bind(limit + 1)
+                    .fetch_all(&self.pool)
+                    .await?
+            }
+        };
+        let users = rows.iter().map(Self::from_row).collect::<Result<Vec<_>, _>>()?;
+        Ok(Page::from_rows(users, limit, |user| Cursor::new(user.created_at, user.id)))
+    }
+
+    /// A unique constraint hit by a concurrent request that got past
+    /// [`Self::taken`] becomes a conflict too.
+    fn conflict(error: sqlx::Error) -> AppError {
+        match &error {
+            sqlx::Error::Database(e) if e.is_unique_violation() => {
+                AppError::Conflict("Username or email is already taken".to_string())
+            }
+            _ => error.into(),
+        }
+    }
+
+    fn from_row(row: &AnyRow) -> Result<User, AppError> {
+        let id: String = row.try_get("id")?;
         Ok(User {
-            id,
-            username: "testuser".to_string(),
-            email: "test@example.com".to_string(),
-            created_at: OffsetDateTime::now_utc(),
-            updated_at: OffsetDateTime::now_utc(),
+            id: Uuid::parse_str(&id).map_err(|e| sqlx::Error::Decode(e.into()))?,
+            username: row.try_get("username")?,
+            email: row.try_get("email")?,
+            created_at: from_timestamp(row.try_get("created_at")?),
+            updated_at: from_timestamp(row.try_get("updated_at")?),
+            version: row.try_get::<i32, _>("version")? as i64,
+            deleted_at: try_get_optional(row, "deleted_at")?.map(from_timestamp),
         })
     }
-}
\ No newline at end of file
+}
+
+/// A value bound to a dynamically built query.
+enum Param {
+    Text(String),
+    Float(f64),
+    Int(i64),
+}
@@ -1,9 +1,15 @@
 use crate::{
-    models::{User, CreateUserRequest},
+    models::{User, CreateUserRequest, ListUsersQuery, UpdateUserRequest},
     repositories::UserRepository,
     error::AppError,
 };
+use messaging::{UserCreatedEvent, UserDeletedEvent, UserRestoredEvent, UserUpdatedEvent};
+use shared::{Cursor, IfMatch, Page};
+use uuid::Uuid;
 
+/// Every change to a user is announced by a domain event, which the
+/// repository writes to its outbox together with the change.
+#[derive(Clone)]
 pub struct UserService {
     repository: UserRepository,
 }
@@ -23,13 +29,82 @@ impl UserService {
             return Err(AppError::ValidationError("Email cannot be empty".to_string()));
         }
 
+        if let Some(reason) = self.repository.taken(Some(&request.username), Some(&request.email), None).await? {
+            return Err(AppError::Conflict(reason));
+        }
+
         // Create user
-        let user = self.repository.create(&request.username, &request.email).await?;
-        Ok(user)
+        self.repository.create::<UserCreatedEvent>(&request.username, &request.email).await
     }
 
-    pub async fn get_user_by_id(&self, id: uuid::Uuid) -> Result<User, AppError> {
+    /// Fails with `Gone` for soft-deleted users.
+    pub async fn get_user_by_id(&self, id: Uuid) -> Result<User, AppError> {
         let user = self.repository.find_by_id(id).await?;
+        if user.deleted_at.is_some() {
+            return Err(AppError::Gone(format!("User {} was deleted", id)));
+        }
         Ok(user)
     }
-}
\ No newline at end of file
+
+    /// Changes the fields present in `request`. A request that changes
+    /// nothing returns the user as is and publishes nothing.
+    pub async fn update_user(&self, id: Uuid, request: UpdateUserRequest, if_match: &IfMatch) -> Result<User, AppError> {
+        if request.username.as_deref() == Some("") {
+            return Err(AppError::ValidationError("Username cannot be empty".to_string()));
+        }
+        if request.email.as_deref() == Some("") {
+            return Err(AppError::ValidationError("Email cannot be empty".to_string()));
+        }
+
+        let mut user = self.get_user_by_id(id).await?;
+        if_match.check(user.version)?;
+        let username = request.username.filter(|username| *username != user.username);
+        let email = request.email.filter(|email| *email != user.email);
+        if username.is_none() && email.is_none() {
+            return Ok(user);
+        }
+        if let Some(reason) = self.repository.taken(username.as_deref(), email.as_deref(), Some(id)).await? {
+            return Err(AppError::Conflict(reason));
+        }
+
+        if let Some(username) = username {
+            user.username = username;
+        }
+        if let Some(email) = email {
+            user.email = email;
+        }
+        self.repository.save::<UserUpdatedEvent>(user).await
+    }
+
+    /// Soft deletes the user. They keep their username and email, and can be
+    /// brought back with [`Self::restore_user`].
+    pub async fn delete_user(&self, id: Uuid, if_match: &IfMatch) -> Result<(), AppError> {
+        let user = self.get_user_by_id(id).await?;
+        if_match.check(user.version)?;
+        self.repository.soft_delete::<UserDeletedEvent>(user).await?;
+        Ok(())
+    }
+
+    pub async fn restore_user(&self, id: Uuid, if_match: &IfMatch) -> Result<User, AppError> {
+        let mut user = self.repository.find_by_id(id).await?;
+        if_match.check(user.version)?;
+        if user.deleted_at.is_none() {
+            return Err(AppError::Conflict(format!("User {} is not deleted", id)));
+        }
+        user.deleted_at = None;
+        self.repository.save::<UserRestoredEvent>(user).await
+    }
+
+    pub async fn list_users(&self, filter: &ListUsersQuery, cursor: Option<Cursor>, limit: i64) -> Result<Page<User>, AppError> {
+        if let (Some(from), Some(to)) = (filter.created_from, filter.created_to) {
+            if from > to {
+                return Err(AppError::ValidationError("created_from must not be after created_to".to_string()));
+            }
+        }
+        self.repository.list(filter, cursor, limit).await
+    }
+
+    pub async fn export_users(&self, cursor: Option<Cursor>, limit: i64) -> Result<Page<User>, AppError> {
+        self.repository.export(cursor, limit).await
+    }
+}
@@ -0,0 +1,44 @@
+use axum::extract::FromRef;
+use shared::{IdempotencyStore, IfMatchPolicy};
+use sqlx::AnyPool;
+
+use crate::{config::Config, services::UserService};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Config,
+    pub pool: AnyPool,
+    pub users: UserService,
+    pub idempotency: IdempotencyStore,
+}
+
+impl AppState {
+    pub fn new(config: Config, pool: AnyPool, users: UserService) -> Self {
+        let idempotency = IdempotencyStore::new(pool.clone());
+        Self { config, pool, users, idempotency }
+    }
+}
+
+impl FromRef<AppState> for Config {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for UserService {
+    fn from_ref(state: &AppState) -> Self {
+        state.users.clone()
+    }
+}
+
+impl FromRef<AppState> for AnyPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for IfMatchPolicy {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.if_match
+    }
+}
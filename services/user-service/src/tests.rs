@@ -27,9 +27,446 @@ mod tests {
             email: "test@example.com".to_string(),
             created_at: OffsetDateTime::now_utc(),
             updated_at: OffsetDateTime::now_utc(),
+            version: 1,
+            deleted_at: None,
         };
         
         assert_eq!(user.username, "testuser");
         assert_eq!(user.email, "test@example.com");
     }
+
+    async fn test_state() -> crate::state::AppState {
+        let pool = crate::db::connect("sqlite::memory:").await.unwrap();
+        let config = crate::config::Config {
+            database_url: "sqlite::memory:".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: 0,
+            grpc_port: 0,
+            nats_url: None,
+            if_match: shared::IfMatchPolicy::Optional,
+        };
+        let users = crate::services::UserService::new(crate::repositories::UserRepository::new(pool.clone()));
+        crate::state::AppState::new(config, pool, users)
+    }
+
+    async fn send(app: &axum::Router, request: axum::http::Request<axum::body::Body>) -> (axum::http::StatusCode, Vec<u8>) {
+        use tower::ServiceExt;
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    fn post_json(uri: &str, body: serde_json::Value) -> axum::http::Request<axum::body::Body> {
+        axum::http::Request::post(uri)
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn get(uri: &str) -> axum::http::Request<axum::body::Body> {
+        axum::http::Request::get(uri).body(axum::body::Body::empty()).unwrap()
+    }
+
+    fn patch_json(uri: &str, body: serde_json::Value) -> axum::http::Request<axum::body::Body> {
+        axum::http::Request::patch(uri)
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn delete(uri: &str) -> axum::http::Request<axum::body::Body> {
+        axum::http::Request::delete(uri).body(axum::body::Body::empty()).unwrap()
+    }
+
+    async fn create(app: &axum::Router, username: &str) -> User {
+        let (status, body) = send(app, post_json("/users", serde_json::json!({
+            "username": username,
+            "email": format!("{}@example.com", username),
+        })))
+        .await;
+        assert_eq!(status, axum::http::StatusCode::CREATED);
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_migrations_are_idempotent() {
+        let pool = crate::db::connect("sqlite::memory:").await.unwrap();
+        crate::db::MIGRATOR.run(&pool).await.unwrap();
+
+        let repository = crate::repositories::UserRepository::new(pool);
+        let user = repository.create::<messaging::UserCreatedEvent>("alice", "alice@example.com").await.unwrap();
+        assert_eq!(repository.find_by_id(user.id).await.unwrap(), user);
+        assert!(matches!(
+            repository.find_by_id(Uuid::new_v4()).await,
+            Err(crate::error::AppError::UserNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_create_and_fetch_user_over_http() {
+        let app = crate::app(test_state().await);
+
+        let (status, body) = send(&app, post_json("/users", serde_json::json!({
+            "username": "alice",
+            "email": "alice@example.com",
+        })))
+        .await;
+        assert_eq!(status, axum::http::StatusCode::CREATED);
+        let created: User = serde_json::from_slice(&body).unwrap();
+        assert_eq!(created.username, "alice");
+
+        let (status, body) = send(&app, get(&format!("/users/{}", created.id))).await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<User>(&body).unwrap(), created);
+
+        let (status, _) = send(&app, get(&format!("/users/{}", Uuid::new_v4()))).await;
+        assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+
+        let (status, _) = send(&app, post_json("/users", serde_json::json!({
+            "username": "",
+            "email": "nobody@example.com",
+        })))
+        .await;
+        assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_is_idempotent() {
+        let app = crate::app(test_state().await);
+        let user = serde_json::json!({ "username": "bob", "email": "bob@example.com" });
+        let keyed = |body: serde_json::Value, key: &str| {
+            let mut request = post_json("/users", body);
+            request.headers_mut().insert(shared::IDEMPOTENCY_KEY_HEADER, key.parse().unwrap());
+            request
+        };
+
+        let (status, first) = send(&app, keyed(user.clone(), "signup-1")).await;
+        assert_eq!(status, axum::http::StatusCode::CREATED);
+        let (status, again) = send(&app, keyed(user.clone(), "signup-1")).await;
+        assert_eq!(status, axum::http::StatusCode::CREATED);
+        assert_eq!(again, first);
+
+        let other = serde_json::json!({ "username": "carol", "email": "carol@example.com" });
+        let (status, _) = send(&app, keyed(other, "signup-1")).await;
+        assert_eq!(status, axum::http::StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_export_users_pages_oldest_first() {
+        let app = crate::app(test_state().await);
+        let mut created = Vec::new();
+        for name in ["dave", "erin", "frank"] {
+            let (_, body) = send(&app, post_json("/users", serde_json::json!({
+                "username": name,
+                "email": format!("{}@example.com", name),
+            })))
+            .await;
+            created.push(serde_json::from_slice::<User>(&body).unwrap());
+        }
+
+        let (status, body) = send(&app, get("/users/export?limit=2")).await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+        let first: shared::Page<User> = serde_json::from_slice(&body).unwrap();
+        let cursor = first.next_cursor.clone().unwrap();
+
+        let (_, body) = send(&app, get(&format!("/users/export?limit=2&cursor={}", cursor))).await;
+        let second: shared::Page<User> = serde_json::from_slice(&body).unwrap();
+        assert!(second.next_cursor.is_none());
+
+        let exported: Vec<User> = first.items.into_iter().chain(second.items).collect();
+        let mut expected = created;
+        expected.sort_by_key(|user| (user.created_at, user.id));
+        assert_eq!(exported, expected);
+    }
+
+    #[tokio::test]
+    async fn test_user_lifecycle() {
+        use axum::http::StatusCode;
+
+        let app = crate::app(test_state().await);
+        let alice = create(&app, "alice").await;
+        create(&app, "bob").await;
+        let uri = format!("/users/{}", alice.id);
+
+        // Partial update leaves the other fields alone
+        let (status, body) = send(&app, patch_json(&uri, serde_json::json!({ "email": "alice@work.example" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        let updated: User = serde_json::from_slice(&body).unwrap();
+        assert_eq!(updated.username, "alice");
+        assert_eq!(updated.email, "alice@work.example");
+        assert!(updated.updated_at > alice.updated_at);
+
+        // Usernames and emails are unique
+        let (status, body) = send(&app, patch_json(&uri, serde_json::json!({ "username": "bob" }))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(String::from_utf8(body).unwrap().contains("Username bob is already taken"));
+        let (status, _) = send(&app, post_json("/users", serde_json::json!({
+            "username": "bobby",
+            "email": "bob@example.com",
+        })))
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = send(&app, patch_json(&uri, serde_json::json!({ "username": "" }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Soft delete
+        let (status, _) = send(&app, delete(&uri)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&app, get(&uri)).await;
+        assert_eq!(status, StatusCode::GONE);
+        let (status, _) = send(&app, patch_json(&uri, serde_json::json!({ "username": "alicia" }))).await;
+        assert_eq!(status, StatusCode::GONE);
+        let (status, _) = send(&app, delete(&uri)).await;
+        assert_eq!(status, StatusCode::GONE);
+        // A deleted user keeps their username
+        let (status, _) = send(&app, post_json("/users", serde_json::json!({
+            "username": "alice",
+            "email": "another@example.com",
+        })))
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (_, body) = send(&app, get("/users")).await;
+        let listed: shared::Page<User> = serde_json::from_slice(&body).unwrap();
+        assert!(listed.items.iter().all(|user| user.id != alice.id));
+
+        // Restore
+        let (status, body) = send(&app, post_json(&format!("{}/restore", uri), serde_json::json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        let restored: User = serde_json::from_slice(&body).unwrap();
+        assert_eq!(restored.deleted_at, None);
+        assert_eq!(restored.email, "alice@work.example");
+        let (status, _) = send(&app, get(&uri)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, post_json(&format!("{}/restore", uri), serde_json::json!({}))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, _) = send(&app, delete(&format!("/users/{}", Uuid::new_v4()))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_unique_violation_is_a_conflict() {
+        let state = test_state().await;
+        let repository = crate::repositories::UserRepository::new(state.pool.clone());
+        repository.create::<messaging::UserCreatedEvent>("carol", "carol@example.com").await.unwrap();
+        // Past the service's checks, as a concurrent request would be
+        let error = repository.create::<messaging::UserCreatedEvent>("carol", "carol2@example.com").await.unwrap_err();
+        assert!(matches!(error, crate::error::AppError::Conflict(_)));
+    }
+
+    #[tokio::test]
+    async fn test_list_users_with_filters() {
+        let app = crate::app(test_state().await);
+        let mut created = Vec::new();
+        for name in ["anna", "andrew", "angela", "ben", "anton"] {
+            created.push(create(&app, name).await);
+        }
+        let list = |uri: String| {
+            let app = app.clone();
+            async move {
+                let (status, body) = send(&app, get(&uri)).await;
+                assert_eq!(status, axum::http::StatusCode::OK);
+                serde_json::from_slice::<shared::Page<User>>(&body).unwrap()
+            }
+        };
+        let names = |page: &shared::Page<User>| page.items.iter().map(|user| user.username.clone()).collect::<Vec<_>>();
+
+        // Newest first, in pages
+        let first = list("/users?username_prefix=an&limit=3".to_string()).await;
+        assert_eq!(first.items.len(), 3);
+        let rest = list(format!("/users?username_prefix=an&limit=3&cursor={}", first.next_cursor.clone().unwrap())).await;
+        assert!(rest.next_cursor.is_none());
+        let mut all = names(&first);
+        all.extend(names(&rest));
+        all.sort();
+        assert_eq!(all, ["andrew", "angela", "anna", "anton"]);
+
+        let page = list("/users?email=ben@example.com".to_string()).await;
+        assert_eq!(names(&page), ["ben"]);
+        let mut page = names(&list("/users?email=an".to_string()).await);
+        page.sort();
+        assert_eq!(page, ["andrew", "angela", "anna", "anton"]);
+        let page = list("/users?email=BEN".to_string()).await;
+        assert!(page.items.is_empty());
+        let page = list("/users?username_prefix=AN".to_string()).await;
+        assert!(page.items.is_empty());
+
+        // Created range, half open
+        let format = |time: OffsetDateTime| time.format(&time::format_description::well_known::Rfc3339).unwrap();
+        let from = created[1].created_at;
+        let to = created[3].created_at;
+        let page = list(format!("/users?created_from={}&created_to={}", format(from), format(to))).await;
+        let ids: std::collections::HashSet<Uuid> = page.items.iter().map(|user| user.id).collect();
+        let expected: std::collections::HashSet<Uuid> = created
+            .iter()
+            .filter(|user| user.created_at >= from && user.created_at < to)
+            .map(|user| user.id)
+            .collect();
+        assert_eq!(ids, expected);
+
+        let (status, _) = send(&app, get(&format!("/users?created_from={}&created_to={}", format(to), format(from)))).await;
+        let expected = if from == to { axum::http::StatusCode::OK } else { axum::http::StatusCode::BAD_REQUEST };
+        assert_eq!(status, expected);
+    }
+
+    #[tokio::test]
+    async fn test_updates_are_conditional() {
+        use axum::http::{header, StatusCode};
+        use tower::ServiceExt;
+
+        let mut state = test_state().await;
+        state.config.if_match = shared::IfMatchPolicy::Required;
+        let app = crate::app(state);
+        let alice = create(&app, "alice").await;
+        let uri = format!("/users/{}", alice.id);
+        let with = |mut request: axum::http::Request<axum::body::Body>, name: header::HeaderName, value: &str| {
+            request.headers_mut().insert(name, value.parse().unwrap());
+            request
+        };
+
+        let response = app.clone().oneshot(get(&uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ETAG], "\"1\"");
+
+        // Reads the client already has
+        let response = app.clone().oneshot(with(get(&uri), header::IF_NONE_MATCH, "\"1\"")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], "\"1\"");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(body.is_empty());
+
+        // Updates must name the version they are based on
+        let rename = |name: &str| patch_json(&uri, serde_json::json!({ "username": name }));
+        let (status, _) = send(&app, rename("alicia")).await;
+        assert_eq!(status, StatusCode::PRECONDITION_REQUIRED);
+        let response = app.clone().oneshot(with(rename("alicia"), header::IF_MATCH, "\"1\"")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ETAG], "\"2\"");
+
+        // A second writer working from the old version loses
+        let response = app.clone().oneshot(with(rename("ali"), header::IF_MATCH, "\"1\"")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(response.headers()[header::ETAG], "\"2\"");
+        let (status, _) = send(&app, with(rename("ali"), header::IF_MATCH, "W/\"2\"")).await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        let (status, _) = send(&app, with(delete(&uri), header::IF_MATCH, "\"1\"")).await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+
+        let response = app.clone().oneshot(with(get(&uri), header::IF_NONE_MATCH, "\"1\"")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let (status, _) = send(&app, with(delete(&uri), header::IF_MATCH, "*")).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let restore = post_json(&format!("{}/restore", uri), serde_json::json!({}));
+        let response = app.clone().oneshot(with(restore, header::IF_MATCH, "\"3\"")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ETAG], "\"4\"");
+        let restored: User = serde_json::from_slice(&axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!((restored.username.as_str(), restored.version), ("alicia", 4));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_writes_do_not_overwrite_each_other() {
+        let state = test_state().await;
+        let repository = crate::repositories::UserRepository::new(state.pool.clone());
+        let user = repository.create::<messaging::UserCreatedEvent>("dave", "dave@example.com").await.unwrap();
+
+        let first = User { username: "david".to_string(), ..user.clone() };
+        let second = User { email: "dave@work.example".to_string(), ..user };
+        assert_eq!(repository.save::<messaging::UserUpdatedEvent>(first).await.unwrap().version, 2);
+        let error = repository.save::<messaging::UserUpdatedEvent>(second).await.unwrap_err();
+        assert!(matches!(
+            error,
+            crate::error::AppError::Precondition(shared::PreconditionError::Failed { current }) if current == "\"2\""
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_changes_and_their_events_commit_together() {
+        use messaging::Event;
+
+        let pool = crate::db::connect("sqlite::memory:").await.unwrap();
+        let outbox = messaging::Scheduler::new(pool.clone());
+        let repository = crate::repositories::UserRepository::new(pool).with_outbox(outbox.clone());
+        let users = crate::services::UserService::new(repository.clone());
+
+        let request = CreateUserRequest { username: "frank".to_string(), email: "frank@example.com".to_string() };
+        let user = users.create_user(request).await.unwrap();
+        let rename = crate::models::UpdateUserRequest { username: Some("franklin".to_string()), email: None };
+        users.update_user(user.id, rename, &shared::IfMatch::none()).await.unwrap();
+        // Writes that fail announce nothing
+        let stale = User { email: "frank@work.example".to_string(), ..user.clone() };
+        assert!(repository.save::<messaging::UserUpdatedEvent>(stale).await.is_err());
+        let duplicate = CreateUserRequest { username: "franklin".to_string(), email: "other@example.com".to_string() };
+        assert!(repository.create::<messaging::UserCreatedEvent>(&duplicate.username, &duplicate.email).await.is_err());
+        users.delete_user(user.id, &shared::IfMatch::none()).await.unwrap();
+
+        let relayed = std::sync::Mutex::new(Vec::new());
+        let relay = |subject: String, message: messaging::Message| {
+            relayed.lock().unwrap().push((subject, message.payload["user_id"].clone()));
+            async { Ok(()) }
+        };
+        assert_eq!(outbox.dispatch_due(relay).await.unwrap(), 3);
+        assert_eq!(outbox.dispatch_due(relay).await.unwrap(), 0);
+        // Changes made within a microsecond may be relayed in either order
+        let mut relayed = relayed.into_inner().unwrap();
+        relayed.sort_by(|a, b| a.0.cmp(&b.0));
+        let user_id = serde_json::json!(user.id);
+        assert_eq!(relayed, vec![
+            (messaging::UserCreatedEvent::SUBJECT.to_string(), user_id.clone()),
+            (messaging::UserDeletedEvent::SUBJECT.to_string(), user_id.clone()),
+            (messaging::UserUpdatedEvent::SUBJECT.to_string(), user_id),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_users_over_grpc() {
+        use tonic::Code;
+        use user_client::user_service_client::UserServiceClient;
+
+        let state = test_state().await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap();
+        let server = tonic::transport::Server::builder()
+            .add_service(crate::grpc::GrpcUsers::new(state.users.clone(), state.idempotency.clone()).into_server())
+            .serve_with_incoming(incoming);
+        tokio::spawn(server);
+        let mut client = UserServiceClient::connect(url).await.unwrap();
+
+        let created = client
+            .create_user(user_client::CreateUserRequest { username: "erin".to_string(), email: "erin@example.com".to_string() })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(created.version, 1);
+        let found = client.get_user(user_client::GetUserRequest { id: created.id.clone() }).await.unwrap().into_inner();
+        assert_eq!(found, created);
+        let stored = state.users.get_user_by_id(created.id.parse().unwrap()).await.unwrap();
+        assert_eq!(found.created_at, Some(shared::grpc::to_timestamp(stored.created_at)));
+
+        let listed = client
+            .list_users(user_client::ListUsersRequest { username_prefix: "er".to_string(), ..Default::default() })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(listed.users, vec![created.clone()]);
+        assert!(listed.next_cursor.is_empty());
+
+        // Errors keep their meaning
+        let duplicate = user_client::CreateUserRequest { username: "erin".to_string(), email: "erin2@example.com".to_string() };
+        assert_eq!(client.create_user(duplicate).await.unwrap_err().code(), Code::AlreadyExists);
+        let invalid = user_client::CreateUserRequest { username: String::new(), email: "x@example.com".to_string() };
+        assert_eq!(client.create_user(invalid).await.unwrap_err().code(), Code::InvalidArgument);
+        let unknown = user_client::GetUserRequest { id: Uuid::new_v4().to_string() };
+        assert_eq!(client.get_user(unknown).await.unwrap_err().code(), Code::NotFound);
+        let malformed = user_client::GetUserRequest { id: "erin".to_string() };
+        assert_eq!(client.get_user(malformed).await.unwrap_err().code(), Code::InvalidArgument);
+        let bad_cursor = user_client::ListUsersRequest { cursor: "nope".to_string(), ..Default::default() };
+        assert_eq!(client.list_users(bad_cursor).await.unwrap_err().code(), Code::InvalidArgument);
+
+        state.users.delete_user(created.id.parse().unwrap(), &shared::IfMatch::none()).await.unwrap();
+        let deleted = user_client::GetUserRequest { id: created.id };
+        assert_eq!(client.get_user(deleted).await.unwrap_err().code(), Code::NotFound);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,200 @@
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::header::{ETAG, IF_MATCH, IF_NONE_MATCH};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// Optimistic concurrency over HTTP. Every resource has a version that goes
+// up by one with each change, and its strong ETag is that version quoted,
+// e.g. `"3"`. Updates name the version they were based on in `If-Match`,
+// and reads can skip the body with `If-None-Match`.
+
+/// The strong ETag of a resource at `version`.
+pub fn etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PreconditionError {
+    #[error("This request must carry an If-Match header")]
+    Required,
+    #[error("The resource has changed; its current ETag is {current}")]
+    Failed { current: String },
+}
+
+impl PreconditionError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            PreconditionError::Required => StatusCode::PRECONDITION_REQUIRED,
+            PreconditionError::Failed { .. } => StatusCode::PRECONDITION_FAILED,
+        }
+    }
+}
+
+impl IntoResponse for PreconditionError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status(), self.to_string()).into_response();
+        if let PreconditionError::Failed { current } = &self {
+            if let Ok(value) = HeaderValue::from_str(current) {
+                response.headers_mut().insert(ETAG, value);
+            }
+        }
+        response
+    }
+}
+
+/// Whether updates must carry `If-Match`. Even when it is optional, a
+/// request that sends one is checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IfMatchPolicy {
+    #[default]
+    Required,
+    Optional,
+}
+
+impl IfMatchPolicy {
+    /// `false`, `0`, `no` and `off` make `If-Match` optional; anything
+    /// else requires it.
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "false" | "0" | "no" | "off" => IfMatchPolicy::Optional,
+            _ => IfMatchPolicy::Required,
+        }
+    }
+}
+
+/// Entity tags listed in a header, or `*`.
+fn entity_tags(headers: &HeaderMap, name: HeaderName) -> Option<Vec<String>> {
+    let values: Vec<&str> = headers.get_all(name).iter().filter_map(|value| value.to_str().ok()).collect();
+    if values.is_empty() {
+        return None;
+    }
+    Some(
+        values
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect(),
+    )
+}
+
+/// The `If-Match` header of an update, checked against the version the
+/// resource is at with [`IfMatch::check`].
+///
+/// As an extractor it rejects requests without the header with 428 when the
+/// state's [`IfMatchPolicy`] requires it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IfMatch(Option<Vec<String>>);
+
+impl IfMatch {
+    /// No precondition, for callers that are not answering a request.
+    pub fn none() -> Self {
+        IfMatch(None)
+    }
+
+    /// A precondition on exactly `version`.
+    pub fn version(version: i64) -> Self {
+        IfMatch(Some(vec![etag(version)]))
+    }
+
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        IfMatch(entity_tags(headers, IF_MATCH))
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Passes when there is no precondition, it is `*`, or it names
+    /// `version` with a strong ETag. Weak ETags never match.
+    pub fn check(&self, version: i64) -> Result<(), PreconditionError> {
+        let Some(tags) = &self.0 else {
+            return Ok(());
+        };
+        let current = etag(version);
+        if tags.iter().any(|tag| tag == "*" || *tag == current) {
+            Ok(())
+        } else {
+            Err(PreconditionError::Failed { current })
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IfMatch
+where
+    IfMatchPolicy: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = PreconditionError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let if_match = IfMatch::from_headers(&parts.headers);
+        if !if_match.is_present() && IfMatchPolicy::from_ref(state) == IfMatchPolicy::Required {
+            return Err(PreconditionError::Required);
+        }
+        Ok(if_match)
+    }
+}
+
+/// The `If-None-Match` header of a read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IfNoneMatch(Option<Vec<String>>);
+
+impl IfNoneMatch {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        IfNoneMatch(entity_tags(headers, IF_NONE_MATCH))
+    }
+
+    /// Whether the client already has `version`. Uses the weak comparison,
+    /// as `If-None-Match` does.
+    pub fn matches(&self, version: i64) -> bool {
+        let current = etag(version);
+        self.0.as_ref().is_some_and(|tags| {
+            tags.iter().any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == current)
+        })
+    }
+
+    /// 304 with the ETag if the client has `version` already, otherwise
+    /// `body` as JSON with the ETag.
+    pub fn respond<T: Serialize>(&self, version: i64, body: T) -> Response {
+        if self.matches(version) {
+            let mut response = StatusCode::NOT_MODIFIED.into_response();
+            set_etag(&mut response, version);
+            return response;
+        }
+        Versioned(version, body).into_response()
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for IfNoneMatch {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(IfNoneMatch::from_headers(&parts.headers))
+    }
+}
+
+/// A JSON body sent with the strong ETag of its version.
+pub struct Versioned<T>(pub i64, pub T);
+
+impl<T: Serialize> IntoResponse for Versioned<T> {
+    fn into_response(self) -> Response {
+        let mut response = Json(self.1).into_response();
+        set_etag(&mut response, self.0);
+        response
+    }
+}
+
+pub fn set_etag(response: &mut Response, version: i64) {
+    if let Ok(value) = HeaderValue::from_str(&etag(version)) {
+        response.headers_mut().insert(ETAG, value);
+    }
+}
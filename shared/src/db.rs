@@ -0,0 +1,93 @@
+use sqlx::any::AnyPoolOptions;
+use sqlx::any::AnyRow;
+use sqlx::{Any, AnyPool, Connection, Decode, Row, Type, TypeInfo, ValueRef};
+use time::OffsetDateTime;
+
+// Services and libraries talk to SQLite or Postgres through `AnyPool`, so the
+// schema sticks to portable types: ids as TEXT, timestamps as DOUBLE PRECISION
+// microseconds, money amounts and JSON documents as TEXT.
+//
+// Timestamps are not BIGINT because the `Any` driver in sqlx 0.7 reads every
+// SQLite integer as an i32, truncating anything larger. Doubles hold
+// microsecond timestamps exactly until the year 2255. Money amounts must be
+// exact at any size, so they are kept as TEXT holding the integer count of
+// minor units; SQL that sums them casts to BIGINT and back to TEXT.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+}
+
+impl Backend {
+    pub async fn of(pool: &AnyPool) -> Result<Self, sqlx::Error> {
+        let conn = pool.acquire().await?;
+        Ok(match conn.backend_name() {
+            "PostgreSQL" => Backend::Postgres,
+            _ => Backend::Sqlite,
+        })
+    }
+
+    /// Column type for an auto-incrementing 64-bit primary key.
+    pub fn serial_primary_key(&self) -> &'static str {
+        match self {
+            Backend::Sqlite => "INTEGER PRIMARY KEY AUTOINCREMENT",
+            Backend::Postgres => "BIGSERIAL PRIMARY KEY",
+        }
+    }
+}
+
+/// Opens a pool for a `sqlite:` or `postgres:` URL.
+pub async fn connect(database_url: &str) -> Result<AnyPool, sqlx::Error> {
+    sqlx::any::install_default_drivers();
+
+    let mut options = AnyPoolOptions::new();
+    if database_url.contains(":memory:") {
+        // Every connection to an in-memory SQLite URL gets its own empty
+        // database, so keep exactly one open for the pool's lifetime.
+        options = options.max_connections(1).idle_timeout(None).max_lifetime(None);
+    }
+    options.connect(database_url).await
+}
+
+/// Runs each statement of `schema` in order. Statements must be idempotent
+/// (`CREATE TABLE IF NOT EXISTS` and the like).
+pub async fn execute_all(pool: &AnyPool, schema: &[&str]) -> Result<(), sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    for statement in schema {
+        sqlx::query(statement).execute(&mut *conn).await?;
+    }
+    conn.ping().await
+}
+
+/// Reads a nullable column. The `Any` driver in sqlx 0.7 never reports values
+/// as NULL, so `try_get::<Option<T>, _>` fails on them instead of returning `None`.
+pub fn try_get_optional<'r, T>(row: &'r AnyRow, column: &str) -> Result<Option<T>, sqlx::Error>
+where
+    T: Decode<'r, Any> + Type<Any>,
+{
+    if row.try_get_raw(column)?.type_info().name() == "NULL" {
+        return Ok(None);
+    }
+    row.try_get(column).map(Some)
+}
+
+/// Microseconds since the Unix epoch, for a DOUBLE PRECISION column.
+pub fn to_timestamp(time: OffsetDateTime) -> f64 {
+    (time.unix_timestamp_nanos() / 1_000) as f64
+}
+
+pub fn from_timestamp(micros: f64) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp_nanos(micros as i128 * 1_000).unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
+
+/// An amount in minor units, for a TEXT column.
+pub fn to_amount(amount_minor: i64) -> String {
+    amount_minor.to_string()
+}
+
+/// Reads an amount in minor units written by [`to_amount`].
+pub fn get_amount(row: &AnyRow, column: &str) -> Result<i64, sqlx::Error> {
+    let text: String = row.try_get(column)?;
+    text.parse().map_err(|e| sqlx::Error::ColumnDecode { index: column.to_string(), source: Box::new(e) })
+}
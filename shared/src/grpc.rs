@@ -0,0 +1,133 @@
+// Status is large, but it is what every gRPC handler returns anyway
+#![allow(clippy::result_large_err)]
+
+use axum::http::Method;
+use prost::Message;
+use prost_types::Timestamp;
+use std::future::Future;
+use time::OffsetDateTime;
+use tonic::metadata::MetadataValue;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use crate::error::SharedError;
+use crate::idempotency::{
+    idempotency_client, request_fingerprint, scoped_key, Claim, IdempotencyError, IdempotencyStore, StoredResponse,
+    IDEMPOTENCY_KEY_HEADER, IDEMPOTENT_REPLAYED_HEADER, MAX_KEY_LENGTH,
+};
+use crate::pagination::PageRequest;
+
+// Conversions between the types the services work with and those of the
+// gRPC contracts, where absent strings and numbers arrive as their zero
+// values.
+
+pub fn to_timestamp(time: OffsetDateTime) -> Timestamp {
+    Timestamp { seconds: time.unix_timestamp(), nanos: time.nanosecond() as i32 }
+}
+
+/// The time of `timestamp`, or `None` if it is out of range.
+pub fn from_timestamp(timestamp: &Timestamp) -> Option<OffsetDateTime> {
+    let nanos = timestamp.seconds as i128 * 1_000_000_000 + timestamp.nanos as i128;
+    OffsetDateTime::from_unix_timestamp_nanos(nanos).ok()
+}
+
+/// The id in the request field `field`.
+pub fn parse_id(field: &str, value: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(value).map_err(|_| Status::invalid_argument(format!("{} is not a valid id: {:?}", field, value)))
+}
+
+/// The id in the optional request field `field`.
+pub fn parse_optional_id(field: &str, value: String) -> Result<Option<Uuid>, Status> {
+    non_empty(value).map(|id| parse_id(field, &id)).transpose()
+}
+
+/// `None` for an empty string.
+pub fn non_empty(value: String) -> Option<String> {
+    (!value.is_empty()).then_some(value)
+}
+
+/// The page a listing request asks for; a zero `limit` means the default.
+pub fn page_request(limit: i32, cursor: String) -> PageRequest {
+    PageRequest { cursor: non_empty(cursor), limit: (limit != 0).then_some(limit as i64) }
+}
+
+impl From<SharedError> for Status {
+    fn from(error: SharedError) -> Self {
+        match error {
+            SharedError::ServiceUnavailable { .. } => Status::unavailable(error.to_string()),
+            _ => Status::invalid_argument(error.to_string()),
+        }
+    }
+}
+
+/// Runs `handler` at most once per `idempotency-key` in the request
+/// metadata, the gRPC counterpart of the [`idempotency`](crate::idempotency)
+/// middleware. `method` is the full gRPC method path, which scopes the key
+/// along with the `authorization` metadata.
+///
+/// Retries get the first response back, marked with `idempotent-replayed`
+/// metadata; a different request with the same key is `INVALID_ARGUMENT` and
+/// a duplicate that outwaits the first run is `ABORTED`. Errors are not
+/// stored, so the client can retry them. Requests without a key run as
+/// they are.
+pub async fn idempotent<Req, Res, F, Fut>(
+    store: &IdempotencyStore,
+    method: &str,
+    request: Request<Req>,
+    handler: F,
+) -> Result<Response<Res>, Status>
+where
+    Req: Message,
+    Res: Message + Default,
+    F: FnOnce(Req) -> Fut,
+    Fut: Future<Output = Result<Res, Status>>,
+{
+    let Some(key) = request.metadata().get(IDEMPOTENCY_KEY_HEADER) else {
+        return handler(request.into_inner()).await.map(Response::new);
+    };
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key.to_string(),
+        _ => return Err(Status::invalid_argument("idempotency-key must be 1 to 255 visible ASCII characters")),
+    };
+    let client = idempotency_client(request.metadata().get("authorization").map(|value| value.as_bytes()));
+    let key = scoped_key(Method::POST.as_str(), method, &client, &key);
+    let request = request.into_inner();
+    let fingerprint = request_fingerprint(&Method::POST, method, &request.encode_to_vec());
+
+    let token = match store.claim(&key, &fingerprint).await {
+        Ok(Claim::Acquired { token }) => token,
+        Ok(Claim::Replay(stored)) => {
+            let reply = Res::decode(stored.body.as_slice())
+                .map_err(|e| Status::internal(format!("Stored response is unreadable: {}", e)))?;
+            let mut response = Response::new(reply);
+            response.metadata_mut().insert(IDEMPOTENT_REPLAYED_HEADER, MetadataValue::from_static("true"));
+            return Ok(response);
+        }
+        Ok(Claim::Mismatch) => {
+            return Err(Status::invalid_argument("idempotency-key was already used for a different request"))
+        }
+        Err(IdempotencyError::InProgress) => {
+            return Err(Status::aborted("A request with this idempotency-key is still in progress"))
+        }
+        Err(e) => {
+            tracing::error!("Idempotency check failed for key {}: {}", key, e);
+            return Err(Status::internal("Internal server error"));
+        }
+    };
+
+    match store.hold(&key, &token, handler(request)).await {
+        Ok(reply) => {
+            let stored = StoredResponse { status: 200, headers: Vec::new(), body: reply.encode_to_vec() };
+            if let Err(e) = store.complete(&key, &token, &stored).await {
+                tracing::error!("Failed to store response for idempotency key {}: {}", key, e);
+            }
+            Ok(Response::new(reply))
+        }
+        Err(status) => {
+            if let Err(e) = store.release(&key, &token).await {
+                tracing::error!("Failed to release idempotency key {}: {}", key, e);
+            }
+            Err(status)
+        }
+    }
+}
@@ -0,0 +1,415 @@
+use crate::db::{execute_all, to_timestamp, try_get_optional};
+use axum::body::{to_bytes, Body};
+use axum::extract::{Request, State};
+use axum::http::{header::AUTHORIZATION, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{any::AnyRow, AnyPool, Row};
+use std::future::Future;
+use std::time::Duration;
+use thiserror::Error;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Set on responses that were replayed from the store rather than produced
+/// by running the handler again.
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+pub const DEFAULT_IDEMPOTENCY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long a duplicate waits for the first request with its key.
+pub const DEFAULT_IDEMPOTENCY_WAIT: Duration = Duration::from_secs(10);
+/// How long a key stays locked by a request that stopped renewing its lock,
+/// e.g. because its instance crashed, before another request may take it
+/// over. Running requests renew their lock well within this.
+pub const DEFAULT_IDEMPOTENCY_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub(crate) const MAX_KEY_LENGTH: usize = 255;
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+const POLL_INTERVAL: Duration = Duration::from_millis(25);
+
+const SCHEMA: &[&str] = &["CREATE TABLE IF NOT EXISTS idempotency_keys (
+        idempotency_key TEXT PRIMARY KEY,
+        fingerprint TEXT NOT NULL,
+        lock_token TEXT NOT NULL,
+        completed INTEGER NOT NULL DEFAULT 0,
+        status_code INTEGER,
+        headers TEXT,
+        body TEXT,
+        created_at DOUBLE PRECISION NOT NULL,
+        expires_at DOUBLE PRECISION NOT NULL
+    )"];
+
+#[derive(Error, Debug)]
+pub enum IdempotencyError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("A request with this idempotency key is still in progress")]
+    InProgress,
+    #[error("The lock on this idempotency key was taken over by another request")]
+    LockLost,
+    #[error("Stored response is unreadable: {0}")]
+    Corrupt(String),
+}
+
+/// A response recorded for an idempotency key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl IntoResponse for StoredResponse {
+    fn into_response(self) -> Response {
+        let mut response = Response::new(Body::from(self.body));
+        *response.status_mut() = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                response.headers_mut().append(name, value);
+            }
+        }
+        response.headers_mut().insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+        response
+    }
+}
+
+/// What to do with a request carrying an idempotency key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Claim {
+    /// The key is new, or its earlier request was abandoned: run the handler
+    /// under [`IdempotencyStore::hold`] and record the response with
+    /// [`IdempotencyStore::complete`], passing `token` to both.
+    Acquired { token: String },
+    /// The same request already ran; send this response again.
+    Replay(StoredResponse),
+    /// The key was used for a different request.
+    Mismatch,
+}
+
+/// Responses to requests sent with an `Idempotency-Key` header, kept in the
+/// service's own database for a TTL so that retries get the original
+/// response instead of repeating its side effects.
+///
+/// Keys are scoped to the method, path and client of the request (see
+/// [`scoped_key`]), so different clients, or one client on different
+/// endpoints, cannot collide. The fingerprint covers the body as well.
+#[derive(Clone)]
+pub struct IdempotencyStore {
+    pool: AnyPool,
+    ttl: Duration,
+    wait: Duration,
+    lock_timeout: Duration,
+}
+
+impl IdempotencyStore {
+    pub fn new(pool: AnyPool) -> Self {
+        Self {
+            pool,
+            ttl: DEFAULT_IDEMPOTENCY_TTL,
+            wait: DEFAULT_IDEMPOTENCY_WAIT,
+            lock_timeout: DEFAULT_IDEMPOTENCY_LOCK_TIMEOUT,
+        }
+    }
+
+    /// How long a completed response is replayed for.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_wait(mut self, wait: Duration) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
+    pub async fn init_schema(&self) -> Result<(), sqlx::Error> {
+        execute_all(&self.pool, SCHEMA).await
+    }
+
+    /// Claims `key` for a request with `fingerprint`. While another request
+    /// holds the key this waits for it to finish, up to the configured wait.
+    pub async fn claim(&self, key: &str, fingerprint: &str) -> Result<Claim, IdempotencyError> {
+        let deadline = tokio::time::Instant::now() + self.wait;
+        loop {
+            if let Some(claim) = self.try_claim(key, fingerprint).await? {
+                return Ok(claim);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(IdempotencyError::InProgress);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// One attempt at [`Self::claim`]; `None` while the key is in progress.
+    async fn try_claim(&self, key: &str, fingerprint: &str) -> Result<Option<Claim>, IdempotencyError> {
+        let now = OffsetDateTime::now_utc();
+        sqlx::query("DELETE FROM idempotency_keys WHERE idempotency_key = $1 AND expires_at <= $2")
+            .bind(key)
+            .bind(to_timestamp(now))
+            .execute(&self.pool)
+            .await?;
+        let token = Uuid::new_v4().to_string();
+        let inserted = sqlx::query(
+            "INSERT INTO idempotency_keys (idempotency_key, fingerprint, lock_token, completed, created_at, expires_at)
+             VALUES ($1, $2, $3, 0, $4, $5)
+             ON CONFLICT (idempotency_key) DO NOTHING",
+        )
+        .bind(key)
+        .bind(fingerprint)
+        .bind(&token)
+        .bind(to_timestamp(now))
+        .bind(to_timestamp(now + self.lock_timeout))
+        .execute(&self.pool)
+        .await?;
+        if inserted.rows_affected() == 1 {
+            return Ok(Some(Claim::Acquired { token }));
+        }
+
+        let Some(row) = sqlx::query("SELECT * FROM idempotency_keys WHERE idempotency_key = $1")
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?
+        else {
+            // Expired and removed between the two statements
+            return Ok(None);
+        };
+        if row.try_get::<String, _>("fingerprint")? != fingerprint {
+            return Ok(Some(Claim::Mismatch));
+        }
+        if row.try_get::<i32, _>("completed")? == 0 {
+            return Ok(None);
+        }
+        Self::response_from_row(&row).map(|response| Some(Claim::Replay(response)))
+    }
+
+    /// Runs `work` while holding the lock on `key`, renewing it so that no
+    /// other request takes the key over while `work` is still running.
+    pub async fn hold<F: Future>(&self, key: &str, token: &str, work: F) -> F::Output {
+        tokio::pin!(work);
+        let renew_every = (self.lock_timeout / 3).max(POLL_INTERVAL);
+        loop {
+            tokio::select! {
+                output = &mut work => return output,
+                _ = tokio::time::sleep(renew_every) => {
+                    if let Err(e) = self.renew(key, token).await {
+                        tracing::error!("Failed to renew lock on idempotency key {}: {}", key, e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Pushes back the expiry of the lock on `key`, if `token` still holds it.
+    pub async fn renew(&self, key: &str, token: &str) -> Result<(), IdempotencyError> {
+        let renewed = sqlx::query(
+            "UPDATE idempotency_keys SET expires_at = $1
+             WHERE idempotency_key = $2 AND lock_token = $3 AND completed = 0",
+        )
+        .bind(to_timestamp(OffsetDateTime::now_utc() + self.lock_timeout))
+        .bind(key)
+        .bind(token)
+        .execute(&self.pool)
+        .await?;
+        match renewed.rows_affected() {
+            0 => Err(IdempotencyError::LockLost),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records the response to replay for `key` until the TTL runs out.
+    /// Fails with [`IdempotencyError::LockLost`] unless `token` still holds
+    /// the key.
+    pub async fn complete(&self, key: &str, token: &str, response: &StoredResponse) -> Result<(), IdempotencyError> {
+        let headers = serde_json::to_string(&response.headers).map_err(|e| IdempotencyError::Corrupt(e.to_string()))?;
+        let completed = sqlx::query(
+            "UPDATE idempotency_keys SET completed = 1, status_code = $1, headers = $2, body = $3, expires_at = $4
+             WHERE idempotency_key = $5 AND lock_token = $6 AND completed = 0",
+        )
+        .bind(response.status as i32)
+        .bind(headers)
+        .bind(STANDARD.encode(&response.body))
+        .bind(to_timestamp(OffsetDateTime::now_utc() + self.ttl))
+        .bind(key)
+        .bind(token)
+        .execute(&self.pool)
+        .await?;
+        match completed.rows_affected() {
+            0 => Err(IdempotencyError::LockLost),
+            _ => Ok(()),
+        }
+    }
+
+    /// Gives up a claimed key without recording a response, so the request
+    /// can be retried. Does nothing unless `token` still holds the key.
+    pub async fn release(&self, key: &str, token: &str) -> Result<(), IdempotencyError> {
+        sqlx::query("DELETE FROM idempotency_keys WHERE idempotency_key = $1 AND lock_token = $2 AND completed = 0")
+            .bind(key)
+            .bind(token)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes expired keys and returns how many there were.
+    pub async fn purge_expired(&self) -> Result<u64, IdempotencyError> {
+        let result = sqlx::query("DELETE FROM idempotency_keys WHERE expires_at <= $1")
+            .bind(to_timestamp(OffsetDateTime::now_utc()))
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Purges expired keys every `every` until the task is dropped.
+    pub async fn run_purge(&self, every: Duration) {
+        loop {
+            if let Err(e) = self.purge_expired().await {
+                tracing::error!("Failed to purge idempotency keys: {}", e);
+            }
+            tokio::time::sleep(every).await;
+        }
+    }
+
+    fn response_from_row(row: &AnyRow) -> Result<StoredResponse, IdempotencyError> {
+        let corrupt = |e: &dyn std::fmt::Display| IdempotencyError::Corrupt(e.to_string());
+        let status: i32 = row.try_get("status_code")?;
+        let headers: Option<String> = try_get_optional(row, "headers")?;
+        let body: Option<String> = try_get_optional(row, "body")?;
+        Ok(StoredResponse {
+            status: u16::try_from(status).map_err(|e| corrupt(&e))?,
+            headers: serde_json::from_str(headers.as_deref().unwrap_or("[]")).map_err(|e| corrupt(&e))?,
+            body: STANDARD.decode(body.unwrap_or_default()).map_err(|e| corrupt(&e))?,
+        })
+    }
+}
+
+/// Hex SHA-256 of the method, path and body of a request.
+pub fn request_fingerprint(method: &Method, path: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_str().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The key a request's `Idempotency-Key` is stored under: a hex SHA-256 of
+/// the key together with the method and path it was sent to and the client
+/// that sent it.
+pub fn scoped_key(method: &str, path: &str, client: &str, key: &str) -> String {
+    let mut hasher = Sha256::new();
+    for part in [method, path, client, key] {
+        hasher.update(part.as_bytes());
+        hasher.update(b"\n");
+    }
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Who sent a request, for scoping its idempotency key: a hash of its
+/// `Authorization` credentials. Requests without any share one anonymous
+/// client.
+pub fn idempotency_client(authorization: Option<&[u8]>) -> String {
+    match authorization {
+        Some(credentials) => Sha256::digest(credentials).iter().map(|byte| format!("{:02x}", byte)).collect(),
+        None => "anonymous".to_string(),
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+/// Axum middleware that makes a route idempotent for clients that send an
+/// `Idempotency-Key` header; requests without one pass straight through.
+///
+/// Install it with `middleware::from_fn_with_state(store, idempotency)`.
+///
+/// - A retry with the same key and request gets the stored response back,
+///   marked with `idempotent-replayed: true`.
+/// - The same key with a different request is rejected with 422.
+/// - A duplicate that arrives while the first request is running waits for
+///   it, and gets 409 if it does not finish in time.
+///
+/// Keys are scoped to the method, path and `Authorization` credentials of
+/// the request. Server errors are not stored, so the client can retry them.
+pub async fn idempotency(State(store): State<IdempotencyStore>, request: Request, next: Next) -> Response {
+    let Some(key) = request.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return next.run(request).await;
+    };
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key.to_string(),
+        _ => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "Idempotency-Key must be 1 to 255 visible ASCII characters",
+            )
+        }
+    };
+
+    let (parts, body) = request.into_parts();
+    let Ok(body) = to_bytes(body, MAX_BODY_BYTES).await else {
+        return error_response(StatusCode::PAYLOAD_TOO_LARGE, "Request body is too large");
+    };
+    let fingerprint = request_fingerprint(&parts.method, parts.uri.path(), &body);
+    let client = idempotency_client(parts.headers.get(AUTHORIZATION).map(HeaderValue::as_bytes));
+    let key = scoped_key(parts.method.as_str(), parts.uri.path(), &client, &key);
+
+    let token = match store.claim(&key, &fingerprint).await {
+        Ok(Claim::Acquired { token }) => token,
+        Ok(Claim::Replay(stored)) => return stored.into_response(),
+        Ok(Claim::Mismatch) => {
+            return error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used for a different request",
+            )
+        }
+        Err(IdempotencyError::InProgress) => {
+            return error_response(StatusCode::CONFLICT, "A request with this Idempotency-Key is still in progress")
+        }
+        Err(e) => {
+            tracing::error!("Idempotency check failed for key {}: {}", key, e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error");
+        }
+    };
+
+    let response = store.hold(&key, &token, next.run(Request::from_parts(parts, Body::from(body)))).await;
+    if response.status().is_server_error() {
+        if let Err(e) = store.release(&key, &token).await {
+            tracing::error!("Failed to release idempotency key {}: {}", key, e);
+        }
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let Ok(body) = to_bytes(body, usize::MAX).await else {
+        if let Err(e) = store.release(&key, &token).await {
+            tracing::error!("Failed to release idempotency key {}: {}", key, e);
+        }
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error");
+    };
+    let stored = StoredResponse {
+        status: parts.status.as_u16(),
+        headers: parts
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+        body: body.to_vec(),
+    };
+    if let Err(e) = store.complete(&key, &token, &stored).await {
+        tracing::error!("Failed to store response for idempotency key {}: {}", key, e);
+    }
+    Response::from_parts(parts, Body::from(body))
+}
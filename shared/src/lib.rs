@@ -1,6 +1,16 @@
 pub mod models;
 pub mod error;
 pub mod utils;
+pub mod db;
+pub mod grpc;
+pub mod pagination;
+pub mod money;
+pub mod idempotency;
+pub mod conditional;
 
 pub use models::*;
-pub use error::*;
\ No newline at end of file
+pub use error::*;
+pub use pagination::*;
+pub use money::*;
+pub use idempotency::*;
+pub use conditional::*;
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum MoneyError {
+    #[error("Invalid currency code: {0}")]
+    InvalidCurrency(String),
+    #[error("Cannot combine {0} with {1}")]
+    CurrencyMismatch(String, String),
+    #[error("Amount out of range")]
+    Overflow,
+}
+
+/// An amount of money as an integer count of the currency's minor unit
+/// (cents for USD), so arithmetic is exact.
+///
+/// `currency` is an ISO 4217 code such as `USD`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Money {
+    pub amount_minor: i64,
+    pub currency: String,
+}
+
+impl Money {
+    pub fn new(amount_minor: i64, currency: &str) -> Result<Self, MoneyError> {
+        if currency.len() != 3 || !currency.bytes().all(|b| b.is_ascii_uppercase()) {
+            return Err(MoneyError::InvalidCurrency(currency.to_string()));
+        }
+        Ok(Self { amount_minor, currency: currency.to_string() })
+    }
+
+    pub fn zero(currency: &str) -> Result<Self, MoneyError> {
+        Self::new(0, currency)
+    }
+
+    /// Converts a floating-point amount in major units, as amounts were sent
+    /// before `Money`, rounding to the nearest minor unit.
+    pub fn from_major_units(amount: f64, currency: &str) -> Result<Self, MoneyError> {
+        let minor = (amount * 10f64.powi(minor_unit_digits(currency) as i32)).round();
+        // i64::MAX as f64 rounds up to 2^63, which is already out of range
+        if !minor.is_finite() || minor.abs() >= i64::MAX as f64 {
+            return Err(MoneyError::Overflow);
+        }
+        Self::new(minor as i64, currency)
+    }
+
+    /// Checks a value that came from outside, e.g. a request body.
+    pub fn validate(&self) -> Result<(), MoneyError> {
+        Self::new(self.amount_minor, &self.currency).map(|_| ())
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.amount_minor > 0
+    }
+
+    pub fn checked_add(&self, other: &Money) -> Result<Money, MoneyError> {
+        if self.currency != other.currency {
+            return Err(MoneyError::CurrencyMismatch(self.currency.clone(), other.currency.clone()));
+        }
+        let amount_minor = self.amount_minor.checked_add(other.amount_minor).ok_or(MoneyError::Overflow)?;
+        Ok(Money { amount_minor, currency: self.currency.clone() })
+    }
+
+    pub fn checked_mul(&self, factor: i64) -> Result<Money, MoneyError> {
+        let amount_minor = self.amount_minor.checked_mul(factor).ok_or(MoneyError::Overflow)?;
+        Ok(Money { amount_minor, currency: self.currency.clone() })
+    }
+
+    /// Sums `amounts`, which must all be in `currency`.
+    pub fn sum<'a>(currency: &str, amounts: impl IntoIterator<Item = &'a Money>) -> Result<Money, MoneyError> {
+        amounts.into_iter().try_fold(Money::zero(currency)?, |total, amount| total.checked_add(amount))
+    }
+
+    /// Digits after the decimal point in the currency's major unit.
+    pub fn minor_unit_digits(&self) -> u32 {
+        minor_unit_digits(&self.currency)
+    }
+}
+
+fn minor_unit_digits(currency: &str) -> u32 {
+    match currency {
+        "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX" | "VND" | "VUV"
+        | "XAF" | "XOF" | "XPF" => 0,
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+        _ => 2,
+    }
+}
+
+impl fmt::Display for Money {
+    /// `12.34 USD`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.minor_unit_digits();
+        if digits == 0 {
+            return write!(f, "{} {}", self.amount_minor, self.currency);
+        }
+        let scale = 10u64.pow(digits);
+        let sign = if self.amount_minor < 0 { "-" } else { "" };
+        let amount = self.amount_minor.unsigned_abs();
+        write!(
+            f,
+            "{}{}.{:0width$} {}",
+            sign,
+            amount / scale,
+            amount % scale,
+            self.currency,
+            width = digits as usize
+        )
+    }
+}
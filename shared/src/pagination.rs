@@ -0,0 +1,93 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::db::{from_timestamp, to_timestamp};
+use crate::error::SharedError;
+
+pub const DEFAULT_PAGE_SIZE: i64 = 20;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+/// One page of a listing, newest first. Pass `next_cursor` back as `cursor`
+/// to get the following page; it is absent on the last one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Builds a page from up to `limit + 1` rows fetched past the cursor. The
+    /// extra row only tells whether another page follows and is dropped.
+    pub fn from_rows(mut rows: Vec<T>, limit: i64, cursor_of: impl Fn(&T) -> Cursor) -> Self {
+        let limit = limit.max(0) as usize;
+        let next_cursor = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last().map(|last| cursor_of(last).encode())
+        } else {
+            None
+        };
+        Self { items: rows, next_cursor }
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page { items: self.items.into_iter().map(f).collect(), next_cursor: self.next_cursor }
+    }
+}
+
+/// Query parameters of a paginated listing.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PageRequest {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+impl PageRequest {
+    /// The requested page size, clamped to `1..=MAX_PAGE_SIZE`.
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+    }
+
+    pub fn cursor(&self) -> Result<Option<Cursor>, SharedError> {
+        self.cursor.as_deref().map(Cursor::decode).transpose()
+    }
+}
+
+/// Position of the last row of a page in a `(created_at DESC, id DESC)`
+/// ordering. Keyset rather than offset, so rows inserted meanwhile do not
+/// shift later pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub created_at: OffsetDateTime,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn new(created_at: OffsetDateTime, id: Uuid) -> Self {
+        Self { created_at, id }
+    }
+
+    /// An opaque, URL-safe token.
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", to_timestamp(self.created_at) as i64, self.id))
+    }
+
+    pub fn decode(token: &str) -> Result<Self, SharedError> {
+        let invalid = || SharedError::InvalidRequest { message: "Invalid cursor".to_string() };
+        let bytes = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+        let text = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let (micros, id) = text.split_once(':').ok_or_else(invalid)?;
+        Ok(Self {
+            created_at: from_timestamp(micros.parse::<i64>().map_err(|_| invalid())? as f64),
+            id: Uuid::parse_str(id).map_err(|_| invalid())?,
+        })
+    }
+
+    /// `created_at` as bound against a timestamp column.
+    pub fn timestamp(&self) -> f64 {
+        to_timestamp(self.created_at)
+    }
+}
@@ -145,12 +145,16 @@ mod pattern_tests {
             timestamp: OffsetDateTime::now_utc(),
         };
         
+        let unit_price = shared::Money::new(9999, "USD").unwrap();
         let order_event = OrderCreatedEvent {
             order_id: Uuid::new_v4(),
             user_id: Uuid::new_v4(),
-            product_name: "Test Product".to_string(),
-            quantity: 1,
-            total_price: 99.99,
+            line_items: vec![messaging::message::OrderLineItem {
+                sku: "TEST-1".to_string(),
+                quantity: 1,
+                unit_price: unit_price.clone(),
+            }],
+            total: unit_price,
             timestamp: OffsetDateTime::now_utc(),
         };
         
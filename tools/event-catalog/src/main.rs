@@ -0,0 +1,80 @@
+//! Builds one AsyncAPI catalog from the documents each service serves.
+//!
+//! ```text
+//! event-catalog [--out FILE] [SOURCE...]
+//! ```
+//!
+//! Each source is a service base URL, a full document URL or a file path.
+//! Without sources, every service in the shared configuration is asked for
+//! its document at the well-known path.
+
+use messaging::{merge_asyncapi, ASYNCAPI_PATH};
+use serde_json::Value;
+use std::process::ExitCode;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let mut out = None;
+    let mut sources = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" | "-o" => out = args.next(),
+            _ => sources.push(arg),
+        }
+    }
+
+    if sources.is_empty() {
+        let config = microservice_config::Config::from_env().expect("Failed to load configuration");
+        let mut names: Vec<_> = config.services.keys().cloned().collect();
+        names.sort();
+        sources = names.iter().filter_map(|name| config.get_service_url(name)).collect();
+    }
+
+    let client = reqwest::Client::new();
+    let mut documents = Vec::new();
+    for source in &sources {
+        match load(&client, source).await {
+            Ok(document) => documents.push(document),
+            // A service that is down or publishes nothing should not block
+            // the rest of the catalog
+            Err(e) => eprintln!("Skipping {}: {}", source, e),
+        }
+    }
+    if documents.is_empty() {
+        eprintln!("No AsyncAPI documents found");
+        return ExitCode::FAILURE;
+    }
+
+    let catalog = match merge_asyncapi("Event catalog", &documents) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let json = serde_json::to_string_pretty(&catalog).expect("catalog serializes");
+    match out {
+        Some(path) => {
+            if let Err(e) = std::fs::write(&path, json) {
+                eprintln!("Failed to write {}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        }
+        None => println!("{}", json),
+    }
+    ExitCode::SUCCESS
+}
+
+async fn load(client: &reqwest::Client, source: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    if !source.starts_with("http://") && !source.starts_with("https://") {
+        return Ok(serde_json::from_slice(&std::fs::read(source)?)?);
+    }
+    let url = if source.ends_with(".json") {
+        source.to_string()
+    } else {
+        format!("{}{}", source.trim_end_matches('/'), ASYNCAPI_PATH)
+    };
+    let response = client.get(&url).send().await?.error_for_status()?;
+    Ok(response.json().await?)
+}